@@ -1,4 +1,4 @@
-/// Configuration and constants for the shell
+//! Configuration and constants for the shell
 
 use serde::{Deserialize, Serialize};
 use std::fs;
@@ -7,6 +7,9 @@ use std::path::PathBuf;
 /// Shell prompt text
 pub const PROMPT: &str = "dshell> ";
 
+/// Prompt for the continuation lines of a multi-line command
+pub const CONTINUATION_PROMPT: &str = "> ";
+
 /// Welcome message displayed on startup
 pub const WELCOME_MESSAGE: &str = "Welcome to dshell terminal!";
 
@@ -26,16 +29,42 @@ const DEFAULT_INTERACTIVE_COMMANDS: &[&str] = &[
     "top",
     "htop",
     "man",
-    "python",
-    "node",
-    "irb",
     "ssh",
-    "bash",
-    "sh",
-    "git",
-    "kubectl"
 ];
 
+/// Default rules for commands that are only interactive with certain arguments
+fn default_interactive_rules() -> Vec<InteractiveRule> {
+    let rule = |commands: &[&str], subcommands: &[&str], args: &[&str]| InteractiveRule {
+        commands: strings(commands),
+        subcommands: strings(subcommands),
+        args: strings(args),
+        ..InteractiveRule::default()
+    };
+    let tty_flags = ["-i", "-t", "-it", "-ti", "--interactive", "--stdin", "--tty"];
+
+    vec![
+        // REPLs and shells without a script to run
+        InteractiveRule { no_args: true, ..rule(&["python", "python3", "node", "irb", "bash", "sh", "zsh"], &[], &[]) },
+        rule(&["python", "python3", "bash", "sh", "zsh"], &[], &["-i"]),
+        // Pagers and editors opened by git
+        rule(&["git"], &["log", "diff", "show", "blame", "shortlog", "reflog", "help"], &[]),
+        rule(&["git"], &["add", "checkout", "reset", "restore", "stash", "rebase", "clean"], &["-p", "--patch", "-i", "--interactive"]),
+        InteractiveRule {
+            without_args: strings(&["-m*", "--message*", "-F*", "--file*", "-C*", "--no-edit"]),
+            ..rule(&["git"], &["commit"], &[])
+        },
+        // Containers and pods attached to the terminal
+        rule(&["kubectl"], &["edit"], &[]),
+        rule(&["kubectl"], &["exec", "run", "attach"], &tty_flags),
+        rule(&["docker", "podman"], &["attach"], &[]),
+        rule(&["docker", "podman"], &["exec", "run"], &tty_flags),
+    ]
+}
+
+fn strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|s| s.to_string()).collect()
+}
+
 /// Default additional allowed paths
 const DEFAULT_ADDITIONAL_ALLOWED_PATHS: &[&str] = &[
     // Claude Code configuration and data
@@ -52,6 +81,174 @@ const DEFAULT_ADDITIONAL_ALLOWED_PATHS: &[&str] = &[
 /// Default denied paths (empty by default - user must explicitly configure)
 const DEFAULT_DENIED_PATHS: &[&str] = &[];
 
+/// A rule that decides whether matching commands are interactive
+///
+/// Every condition that is set must hold for the rule to match. Argument
+/// patterns match whole arguments; a trailing `*` matches any suffix.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InteractiveRule {
+    /// Program names, matched against the basename of the command
+    pub commands: Vec<String>,
+
+    /// The first non-option argument must be one of these
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subcommands: Vec<String>,
+
+    /// At least one argument must match one of these patterns
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+
+    /// No argument may match any of these patterns
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub without_args: Vec<String>,
+
+    /// Only match when the command has no arguments at all
+    #[serde(default)]
+    pub no_args: bool,
+
+    /// Whether matching commands are interactive; `false` makes an exception
+    /// to a broader rule or to `interactive_commands`
+    #[serde(default = "default_rule_interactive")]
+    pub interactive: bool,
+}
+
+impl Default for InteractiveRule {
+    fn default() -> Self {
+        Self {
+            commands: Vec::new(),
+            subcommands: Vec::new(),
+            args: Vec::new(),
+            without_args: Vec::new(),
+            no_args: false,
+            interactive: default_rule_interactive(),
+        }
+    }
+}
+
+impl InteractiveRule {
+    /// Check a command, given by the basename of its program and its arguments
+    pub fn matches(&self, program: &str, args: &[String]) -> bool {
+        if !self.commands.iter().any(|c| c == program) {
+            return false;
+        }
+        if self.no_args && !args.is_empty() {
+            return false;
+        }
+
+        // Options after `--` belong to another program
+        let options = args.iter().take_while(|a| *a != "--");
+        let matches_any = |patterns: &[String], arg: &str| patterns.iter().any(|p| pattern_matches(p, arg));
+
+        if !self.subcommands.is_empty() {
+            let subcommand = args.iter().find(|a| !a.starts_with('-'));
+            if !subcommand.is_some_and(|s| self.subcommands.contains(s)) {
+                return false;
+            }
+        }
+        if !self.args.is_empty() && !options.clone().any(|a| matches_any(&self.args, a)) {
+            return false;
+        }
+        !options.into_iter().any(|a| matches_any(&self.without_args, a))
+    }
+}
+
+/// Resource limits for launched commands
+///
+/// An unset limit falls back to a broader setting; 0 means no limit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ResourceLimits {
+    /// Wall-clock seconds before the command is killed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+
+    /// Seconds of CPU time (RLIMIT_CPU)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_secs: Option<u64>,
+
+    /// Memory in MiB: address space (RLIMIT_AS), or with cgroups the
+    /// memory the command's tree uses (`memory.max`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_mb: Option<u64>,
+
+    /// Largest file the command may write, in MiB (RLIMIT_FSIZE)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_size_mb: Option<u64>,
+
+    /// Processes the user may have at once (RLIMIT_NPROC); with cgroups,
+    /// processes in the command's tree (`pids.max`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_processes: Option<u64>,
+
+    /// Share of one CPU in percent, e.g. 50 or 200 (cgroups only, `cpu.max`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_percent: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// These limits, with unset ones taken from `fallback`
+    pub fn or(&self, fallback: &ResourceLimits) -> ResourceLimits {
+        ResourceLimits {
+            timeout_secs: self.timeout_secs.or(fallback.timeout_secs),
+            cpu_secs: self.cpu_secs.or(fallback.cpu_secs),
+            memory_mb: self.memory_mb.or(fallback.memory_mb),
+            file_size_mb: self.file_size_mb.or(fallback.file_size_mb),
+            max_processes: self.max_processes.or(fallback.max_processes),
+            cpu_percent: self.cpu_percent.or(fallback.cpu_percent),
+        }
+    }
+}
+
+/// Resource limits for specific commands, overriding the global ones
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CommandLimits {
+    /// Program names, matched against the basename of the command
+    pub commands: Vec<String>,
+
+    #[serde(flatten)]
+    pub limits: ResourceLimits,
+}
+
+/// Settings for the audit log of commands and permission changes
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    /// Size in MiB after which the log is rotated
+    pub max_size_mb: u64,
+    /// Rotated logs to keep
+    pub keep: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig { enabled: true, max_size_mb: 10, keep: 5 }
+    }
+}
+
+/// Settings for redacting secrets in captured output
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub enabled: bool,
+    /// Extra regexes to redact; with a capture group, only the group
+    pub patterns: Vec<String>,
+    /// Env vars whose values are redacted even when commands may see them
+    pub env: Vec<String>,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        RedactionConfig { enabled: true, patterns: Vec::new(), env: Vec::new() }
+    }
+}
+
+fn pattern_matches(pattern: &str, arg: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => arg.starts_with(prefix),
+        None => arg == pattern,
+    }
+}
+
 /// User configuration loaded from ~/.config/dshell/config.toml
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -59,6 +256,11 @@ pub struct Config {
     #[serde(default = "default_interactive_commands")]
     pub interactive_commands: Vec<String>,
 
+    /// Rules for commands that are interactive only with certain
+    /// subcommands or arguments; checked before `interactive_commands`
+    #[serde(default = "default_interactive_rules")]
+    pub interactive_rules: Vec<InteractiveRule>,
+
     /// Additional paths to allow access to for isolated commands
     #[serde(default = "default_additional_allowed_paths")]
     pub additional_allowed_paths: Vec<String>,
@@ -67,40 +269,118 @@ pub struct Config {
     /// These take precedence over allowed paths
     #[serde(default = "default_denied_paths")]
     pub denied_paths: Vec<String>,
+
+    /// Ask bash-completion (in a sandboxed helper) for commands that have
+    /// no completion spec in ~/.config/dshell/completions
+    #[serde(default)]
+    pub bash_completion_fallback: bool,
+
+    /// Run captured commands under a pseudo-terminal, so they keep colors
+    /// and progress output and can prompt for input
+    #[serde(default)]
+    pub captured_pty: bool,
+
+    /// Hand the terminal to a captured command that switches to the
+    /// alternate screen or raw mode (requires `captured_pty`)
+    #[serde(default = "default_detect_fullscreen")]
+    pub detect_fullscreen: bool,
+
+    /// Resource limits for every launched command
+    #[serde(default)]
+    pub limits: ResourceLimits,
+
+    /// Resource limits for specific commands; the first match overrides
+    /// `limits`
+    #[serde(default)]
+    pub command_limits: Vec<CommandLimits>,
+
+    /// Run each command in its own cgroup when a writable cgroup v2
+    /// subtree is available, for limits, usage reports and reliable kills
+    #[serde(default = "default_use_cgroups")]
+    pub use_cgroups: bool,
+
+    /// Show how long a command took, its CPU time and memory when it ran
+    /// for at least this many seconds; 0 reports after every command
+    #[serde(default = "default_report_time_secs")]
+    pub report_time_secs: u64,
+
+    /// Audit log of commands and permission changes
+    #[serde(default)]
+    pub audit: AuditConfig,
+
+    /// Redaction of secrets in captured output
+    #[serde(default)]
+    pub redaction: RedactionConfig,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             interactive_commands: default_interactive_commands(),
+            interactive_rules: default_interactive_rules(),
             additional_allowed_paths: default_additional_allowed_paths(),
             denied_paths: default_denied_paths(),
+            bash_completion_fallback: false,
+            captured_pty: false,
+            detect_fullscreen: default_detect_fullscreen(),
+            limits: ResourceLimits::default(),
+            command_limits: Vec::new(),
+            use_cgroups: default_use_cgroups(),
+            report_time_secs: default_report_time_secs(),
+            audit: AuditConfig::default(),
+            redaction: RedactionConfig::default(),
         }
     }
 }
 
 impl Config {
-    /// Get the path to the config file
-    pub fn config_path() -> Option<PathBuf> {
+    /// Get the dshell config directory (~/.config/dshell)
+    pub fn config_dir() -> Option<PathBuf> {
         std::env::var("HOME")
             .ok()
-            .map(|home| PathBuf::from(home).join(".config/dshell/config.toml"))
+            .map(|home| PathBuf::from(home).join(".config/dshell"))
+    }
+
+    /// Get the path to the config file
+    pub fn config_path() -> Option<PathBuf> {
+        Self::config_dir().map(|dir| dir.join("config.toml"))
+    }
+
+    /// Get the path to the script run at the start of every session
+    pub fn rc_path() -> Option<PathBuf> {
+        Self::config_dir().map(|dir| dir.join("rc.dsh"))
+    }
+
+    /// Get the directory for state such as the audit log
+    /// ($XDG_STATE_HOME/dshell, or ~/.local/state/dshell)
+    pub fn state_dir() -> Option<PathBuf> {
+        match std::env::var("XDG_STATE_HOME") {
+            Ok(dir) if dir.starts_with('/') => Some(PathBuf::from(dir).join("dshell")),
+            _ => std::env::var("HOME")
+                .ok()
+                .map(|home| PathBuf::from(home).join(".local/state/dshell")),
+        }
+    }
+
+    /// Get the directory holding per-command completion specs
+    pub fn completions_dir() -> Option<PathBuf> {
+        Self::config_dir().map(|dir| dir.join("completions"))
     }
 
     /// Load configuration from ~/.config/dshell/config.toml
     /// Falls back to defaults if file doesn't exist or can't be parsed
     pub fn load() -> Self {
-        if let Some(config_path) = Self::config_path() {
-            if let Ok(content) = fs::read_to_string(&config_path) {
-                match toml::from_str::<Config>(&content) {
-                    Ok(config) => {
-                        eprintln!("✓ Loaded configuration from: {}", config_path.display());
-                        return config;
-                    }
-                    Err(e) => {
-                        eprintln!("⚠ Failed to parse config file {}: {}", config_path.display(), e);
-                        eprintln!("  Using default configuration");
-                    }
+        if let Some(config_path) = Self::config_path()
+            && let Ok(content) = fs::read_to_string(&config_path)
+        {
+            match toml::from_str::<Config>(&content) {
+                Ok(config) => {
+                    eprintln!("✓ Loaded configuration from: {}", config_path.display());
+                    return config;
+                }
+                Err(e) => {
+                    eprintln!("⚠ Failed to parse config file {}: {}", config_path.display(), e);
+                    eprintln!("  Using default configuration");
                 }
             }
         }
@@ -119,7 +399,7 @@ impl Config {
             }
 
             let toml_string = toml::to_string_pretty(self)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                .map_err(std::io::Error::other)?;
 
             fs::write(&config_path, toml_string)?;
             eprintln!("✓ Configuration saved to: {}", config_path.display());
@@ -146,6 +426,9 @@ impl Config {
 
 # List of interactive commands that need direct terminal access
 # These commands will run with full terminal control (stdin/stdout/stderr)
+# Names match the program's basename, so "/usr/bin/vim" counts as "vim".
+# Extra words match leading arguments: "cargo watch" is only interactive
+# as `cargo watch ...`
 interactive_commands = [
     "claude",
     "ollama",
@@ -158,12 +441,7 @@ interactive_commands = [
     "top",
     "htop",
     "man",
-    "python",
-    "node",
-    "irb",
     "ssh",
-    "bash",
-    "sh",
 ]
 
 # Additional paths to allow access to for isolated commands
@@ -196,6 +474,95 @@ denied_paths = [
     # "~/.config/gcloud",    # Google Cloud credentials
     # "~/sensitive-data",    # Your sensitive files
 ]
+
+# Fall back to bash-completion for commands without a spec in
+# ~/.config/dshell/completions (runs bash in a sandboxed helper)
+bash_completion_fallback = false
+
+# Run captured (non-interactive) commands under a pseudo-terminal so they
+# keep colors and progress bars, and let you answer their prompts
+captured_pty = false
+
+# Hand the terminal over to a captured command as soon as it switches to
+# the alternate screen or raw mode (e.g. a pager opened by `git log`)
+detect_fullscreen = true
+
+# Rules for commands that are interactive only with certain subcommands or
+# arguments; the first matching rule wins, before interactive_commands.
+# Argument patterns match whole arguments, a trailing * matches any suffix.
+# Setting any rules replaces the built-in ones (REPLs without a script,
+# git pagers and editors, `kubectl exec -it`, `docker run -it`, ...).
+#
+# [[interactive_rules]]
+# commands = ["python", "python3"]
+# no_args = true                   # bare `python`, not `python script.py`
+#
+# [[interactive_rules]]
+# commands = ["git"]
+# subcommands = ["commit"]
+# without_args = ["-m*", "--message*", "-F*", "--no-edit"]
+#
+# [[interactive_rules]]
+# commands = ["kubectl"]
+# subcommands = ["exec"]
+# args = ["-it", "-i", "--stdin"]
+#
+# [[interactive_rules]]
+# commands = ["ssh"]
+# args = ["-V"]
+# interactive = false              # exception to interactive_commands
+
+# Run each command in its own cgroup when dshell can write to a cgroup v2
+# subtree (a delegated systemd scope, a container, or as root). Memory,
+# process and CPU limits then apply to the command's whole process tree,
+# and commands that time out are killed with everything they started.
+# Without one, limits fall back to rlimits.
+use_cgroups = true
+
+# After a command that ran for at least this many seconds, show its wall
+# time, CPU time, memory and exit status; 0 reports after every command.
+# `time <command>` always reports.
+report_time_secs = 10
+
+# Resource limits for launched commands; 0 or unset means no limit.
+# The `limit` builtin changes the global limits for the current session.
+#
+# [limits]
+# timeout_secs = 300               # kill commands still running after 5 minutes
+# cpu_secs = 120                   # CPU time
+# memory_mb = 4096                 # memory (address space without cgroups)
+# file_size_mb = 1024              # largest file a command may write
+# max_processes = 512              # processes (of your user without cgroups)
+# cpu_percent = 200                # at most two CPUs' worth (cgroups only)
+#
+# Per-command limits override the global ones; the first match wins
+#
+# [[command_limits]]
+# commands = ["cargo", "make"]
+# timeout_secs = 1800
+
+# Audit log of launched commands and permission changes, written as JSON
+# lines to $XDG_STATE_HOME/dshell/audit.jsonl (~/.local/state/dshell by
+# default). Env var values are never logged, only names. The `audit`
+# builtin shows recent entries. Entries are hash-chained; `audit verify`
+# (or `dshell audit verify`) reports edited, removed or truncated entries.
+# Isolated commands are never given access to the log directory.
+#
+# [audit]
+# enabled = true
+# max_size_mb = 10                 # rotate to audit.jsonl.1 past this size
+# keep = 5                         # rotated logs to keep
+
+# Secrets in captured output are replaced by markers such as
+# `[redacted #3: GitHub token]`: values of env vars you denied or whose
+# names look like credentials (TOKEN, SECRET, PASSWORD, ...), AWS keys,
+# GitHub and Slack tokens, JWTs and private key blocks. `unmask N` shows a
+# redacted value after confirmation.
+#
+# [redaction]
+# enabled = true
+# patterns = ['password=(\S+)']   # extra regexes; only the group if there is one
+# env = ["DATABASE_URL"]           # also redact these even when allowed
 "#;
 
             fs::write(&config_path, default_config)?;
@@ -221,3 +588,19 @@ fn default_additional_allowed_paths() -> Vec<String> {
 fn default_denied_paths() -> Vec<String> {
     DEFAULT_DENIED_PATHS.iter().map(|s| s.to_string()).collect()
 }
+
+fn default_detect_fullscreen() -> bool {
+    true
+}
+
+fn default_use_cgroups() -> bool {
+    true
+}
+
+fn default_report_time_secs() -> u64 {
+    10
+}
+
+fn default_rule_interactive() -> bool {
+    true
+}
@@ -3,25 +3,112 @@ mod security;
 mod shell;
 mod terminal;
 
-use config::{Config, PROMPT};
+use config::{Config, CONTINUATION_PROMPT, PROMPT};
 use crossterm::{
-    event::{self, Event},
+    event::{
+        self, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, Event, KeyCode,
+        KeyEvent, KeyModifiers, MouseEventKind,
+    },
     execute,
     terminal::{self as crossterm_terminal, ClearType},
     tty::IsTty,
 };
-use security::{Permission, PermissionManager, LandlockIsolation};
-use shell::{executor::Executor, CommandAction, Shell};
-use std::collections::HashMap;
-use std::io;
-use terminal::{InputAction, InputEditor, TerminalRenderer};
+use security::{redact, IsolationReport, Permission, PermissionManager, LandlockIsolation, Redactor};
+use shell::{
+    aliases::Aliases,
+    audit::{AuditCommand, AuditLog, CommandAudit, RunMode},
+    completion::Completer,
+    completion_spec::CompletionSpecs,
+    dotenv::{self, DotenvCommand},
+    variables::{VariableCommand, Variables},
+    builtins::Builtins,
+    cgroup::CgroupManager,
+    executor::{CapturedProcess, Executor, WaitStatus},
+    functions::{self, Functions},
+    highlight::Highlighter,
+    history::{self, HistoryCommand, HistoryEntry},
+    jobs::{JobCommand, JobProcess, JobState, JobTable},
+    limits::{LimitCommand, Watchdog},
+    parser::{self, ParsedCommand},
+    pty::{self, PtyProcess},
+    timing::{self, Rusage, Timing},
+    CommandAction, Shell,
+};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use terminal::{ansi::{self, StyledLine}, emulator::TerminalEmulator, input, paste, renderer, InputAction, InputEditor, InputView, TerminalRenderer};
+
+/// Lines scrolled per mouse wheel step
+const MOUSE_SCROLL_LINES: usize = 3;
+
+/// Shown in place of the prompt while a program runs on a PTY
+const RUNNING_PROMPT: &str = "[running — input goes to the program, Ctrl+C to interrupt] ";
+
+/// Minimum time between redraws of a running program's output
+const LIVE_RENDER_INTERVAL: Duration = Duration::from_millis(30);
+
+/// How often background jobs are checked while waiting for input
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An action waiting for the user to answer a confirmation prompt
+enum PendingAction {
+    /// Run a command produced by history expansion
+    RunExpanded(String),
+    /// Insert reviewed paste text into the saved input line
+    InsertPaste(String, (String, usize)),
+    /// Search the output for the answer, then restore the saved input line
+    Search((String, usize)),
+    /// Show a redacted value, by its number
+    Unmask(usize),
+    /// Load the chosen variables from a `.env` file, named for messages
+    LoadDotenv(String, Vec<(String, String)>),
+}
+
+impl PendingAction {
+    /// Prompt shown in place of the normal shell prompt
+    fn prompt(&self) -> &'static str {
+        match self {
+            PendingAction::RunExpanded(_) => "Run this command? [y/N] ",
+            PendingAction::InsertPaste(..) => "Insert pasted text? [y/N] ",
+            PendingAction::Search(_) => "Search output: ",
+            PendingAction::Unmask(_) => "Show the secret in the output? [y/N] ",
+            PendingAction::LoadDotenv(..) => "Load which variables? [all/none/numbers] ",
+        }
+    }
+}
 
 struct App {
     renderer: TerminalRenderer,
     input_editor: InputEditor,
     permissions: PermissionManager,
-    custom_env: HashMap<String, String>,
+    /// Shell variables, `set` options and the last exit status
+    variables: Variables,
     config: Config,
+    completion_specs: CompletionSpecs,
+    interactive: bool,
+    pending: Option<PendingAction>,
+    /// Background jobs and commands suspended with Ctrl+Z
+    jobs: JobTable,
+    /// dshell's cgroup, when commands run in cgroups; after `jobs`, so the
+    /// jobs' groups are removed first
+    cgroups: Option<CgroupManager>,
+    /// Timing of the last command that ran as a process
+    last_timing: Option<Timing>,
+    /// Set while `time` runs a command, to report its timing regardless of
+    /// the threshold
+    timing_forced: bool,
+    /// Where launched commands and permission changes are recorded
+    audit: AuditLog,
+    /// Set once writing the audit log has failed, to warn only once
+    audit_failed: bool,
+    /// Secrets redacted from command output, for `unmask`
+    redactor: Redactor,
+    aliases: Aliases,
+    functions: Functions,
+    /// How many function calls and multi-command aliases are running
+    depth: usize,
 }
 
 impl App {
@@ -31,6 +118,11 @@ impl App {
         // Load configuration from ~/.config/dshell/config.toml
         let config = Config::load();
 
+        // Load per-command completion specs from ~/.config/dshell/completions
+        let completion_specs = Config::completions_dir()
+            .map(|dir| CompletionSpecs::load(&dir))
+            .unwrap_or_default();
+
         // Apply denied paths from config to PermissionManager
         for path in &config.denied_paths {
             permissions.deny_path(path.clone());
@@ -64,6 +156,53 @@ impl App {
         }
         renderer.add_output("".to_string());
 
+        // Resource control
+        let cgroups = if config.use_cgroups { CgroupManager::detect() } else { None };
+        match &cgroups {
+            Some(cgroups) => {
+                renderer.add_output("  • Resource Limits: cgroup v2".to_string());
+                renderer.add_output("    Each command runs in its own cgroup; limits apply to its whole process tree".to_string());
+                if !cgroups.controllers().is_empty() {
+                    renderer.add_output(format!("    Controllers: {}", cgroups.controllers().join(", ")));
+                }
+            }
+            None => renderer.add_output("  • Resource Limits: rlimits (no writable cgroup v2 subtree)".to_string()),
+        }
+        renderer.add_output("    Use 'limit' to show or change them".to_string());
+        renderer.add_output("".to_string());
+
+        // Secret redaction
+        let (redactor, errors) = Redactor::new(&config.redaction);
+        if config.redaction.enabled {
+            renderer.add_output("  • Output Redaction: ENABLED".to_string());
+            renderer.add_output("    Secrets in command output are masked; use 'unmask' to see them".to_string());
+        } else {
+            renderer.add_output("  • Output Redaction: disabled".to_string());
+        }
+        for error in errors {
+            renderer.add_output(format!("    ⚠️  {}", error));
+        }
+        renderer.add_output("".to_string());
+
+        // Audit log
+        let audit = AuditLog::open(&config.audit);
+        match audit.path() {
+            Some(path) => {
+                renderer.add_output(format!("  • Audit Log: {}", path.display()));
+                renderer.add_output("    Commands and permission changes are recorded; use 'audit' to view".to_string());
+                // Sandboxed commands lose the working directory's own rights
+                // to keep them away from the log
+                if Executor::isolation(&config).is_ok_and(|isolation| isolation.narrows_work_dir()) {
+                    renderer.add_output(
+                        "    ⚠️  It is under the working directory, so commands can't create or delete files directly in it"
+                            .to_string(),
+                    );
+                }
+            }
+            None => renderer.add_output("  • Audit Log: disabled".to_string()),
+        }
+        renderer.add_output("".to_string());
+
         // Show configuration loaded
         renderer.add_output("📋 Configuration:".to_string());
         renderer.add_output("".to_string());
@@ -92,6 +231,9 @@ impl App {
                 }
             }
         }
+        if !config.interactive_rules.is_empty() {
+            renderer.add_output(format!("    Plus {} rule(s) for subcommands and arguments (git log, python, ...)", config.interactive_rules.len()));
+        }
         renderer.add_output("".to_string());
 
         // Show additional allowed paths
@@ -114,6 +256,11 @@ impl App {
             renderer.add_output("".to_string());
         }
 
+        if completion_specs.len() > 0 {
+            renderer.add_output(format!("  • Completion Specs: {} loaded", completion_specs.len()));
+            renderer.add_output("".to_string());
+        }
+
         renderer.add_output("Type 'help' for commands, 'security' for status".to_string());
         renderer.add_output("".to_string());
 
@@ -121,27 +268,51 @@ impl App {
             renderer,
             input_editor: InputEditor::new(),
             permissions,
-            custom_env: HashMap::new(),
+            variables: Variables::new(),
             config,
+            completion_specs,
+            interactive: false,
+            pending: None,
+            jobs: JobTable::new(),
+            cgroups,
+            last_timing: None,
+            timing_forced: false,
+            audit,
+            audit_failed: false,
+            redactor,
+            aliases: Aliases::new(),
+            functions: Functions::new(),
+            depth: 0,
         }
     }
 
-    fn run(&mut self) -> io::Result<()> {
+    /// Run the session, starting with the rc file unless it is `None`
+    fn run(&mut self, rc: Option<PathBuf>) -> io::Result<()> {
         // Check if stdin is a TTY
         if !io::stdin().is_tty() {
             // Non-interactive mode: read from stdin
-            return self.run_non_interactive();
+            return self.run_non_interactive(rc);
         }
 
-        // Enter raw mode
+        self.interactive = true;
+        Executor::init_job_control();
+
+        // Enter raw mode, with pastes delivered as a single event
         crossterm_terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnableBracketedPaste, EnableMouseCapture)?;
 
-        // Initial render
-        self.render()?;
+        // Run the rc file, then show its output with the welcome message;
+        // `exit` in it ends the session before it starts
+        let result = match self.run_rc(rc) {
+            Ok(true) => self.render().and_then(|()| self.event_loop()),
+            other => other.map(|_| ()),
+        };
 
-        let result = self.event_loop();
+        // Don't leave background or suspended jobs behind
+        self.jobs.kill_all(&mut self.audit);
 
         // Exit raw mode
+        execute!(io::stdout(), DisableBracketedPaste, DisableMouseCapture)?;
         crossterm_terminal::disable_raw_mode()?;
 
         // Clear screen on exit
@@ -151,156 +322,1434 @@ impl App {
         result
     }
 
-    fn run_non_interactive(&mut self) -> io::Result<()> {
+    fn run_non_interactive(&mut self, rc: Option<PathBuf>) -> io::Result<()> {
         use std::io::BufRead;
 
-        // Print initial output (welcome message, etc.)
+        let keep_running = self.run_rc(rc)?;
+
+        // Print initial output (welcome message, rc file output, etc.)
         for line in self.renderer.get_new_output() {
             println!("{}", line);
         }
 
         let stdin = io::stdin();
-        for line in stdin.lock().lines() {
-            let command = line?;
+        let mut lines = stdin.lock().lines();
+        while keep_running && let Some(line) = lines.next() {
+            let mut command = line?;
 
             // Skip empty lines
             if command.trim().is_empty() {
                 continue;
             }
 
+            // Read continuation lines of an incomplete command
+            while parser::needs_continuation(&command) {
+                match lines.next() {
+                    Some(next) => {
+                        command.push('\n');
+                        command.push_str(&next?);
+                    }
+                    None => break,
+                }
+            }
+
             // Process command
             if !self.handle_command(&command)? {
                 break;
             }
 
-            // Print new output
+            // Print new output, including jobs that finished meanwhile
+            let notifications = self.jobs.update(&mut self.audit);
+            self.renderer.add_output_lines(notifications);
             for line in self.renderer.get_new_output() {
                 println!("{}", line);
             }
         }
 
+        // Let background jobs finish before exiting
+        self.wait_for_jobs(&[])?;
+        for line in self.renderer.get_new_output() {
+            println!("{}", line);
+        }
+
         Ok(())
     }
 
     fn event_loop(&mut self) -> io::Result<()> {
         loop {
+            // Check on background jobs while no input arrives
+            if !event::poll(JOB_POLL_INTERVAL)? {
+                if self.jobs.is_empty() {
+                    continue;
+                }
+                let notifications = self.jobs.update(&mut self.audit);
+                if !notifications.is_empty() {
+                    self.renderer.add_output_lines(notifications);
+                    self.render()?;
+                }
+                continue;
+            }
+
             // Read event
-            if let Event::Key(key_event) = event::read()? {
-                match self.input_editor.handle_key(key_event) {
-                    InputAction::Exit => break,
-                    InputAction::Submit(command) => {
-                        if !self.handle_command(&command)? {
-                            break;
-                        }
+            let action = match event::read()? {
+                Event::Key(key_event) if self.handle_scroll_key(key_event) => InputAction::None,
+                Event::Key(key_event) => self.input_editor.handle_key(key_event),
+                Event::Paste(text) => InputAction::Paste(text),
+                // Redraw everything at the new size
+                Event::Resize(..) => InputAction::None,
+                Event::Mouse(mouse) => {
+                    match mouse.kind {
+                        MouseEventKind::ScrollUp => self.renderer.scroll_up(MOUSE_SCROLL_LINES),
+                        MouseEventKind::ScrollDown => self.renderer.scroll_down(MOUSE_SCROLL_LINES),
+                        _ => continue,
                     }
-                    InputAction::None => {}
+                    InputAction::None
                 }
+                _ => continue,
+            };
 
-                self.render()?;
+            match action {
+                InputAction::Exit => break,
+                // Ctrl+C answers "no" to a confirmation prompt
+                InputAction::Cancel(_) if self.pending.is_some() => {
+                    if !self.resolve_pending("")? {
+                        break;
+                    }
+                }
+                InputAction::Cancel(line) => {
+                    if !line.is_empty() {
+                        self.echo_command(&format!("{}^C", line));
+                    }
+                }
+                InputAction::Submit(answer) if self.pending.is_some() => {
+                    if !self.resolve_pending(&answer)? {
+                        break;
+                    }
+                }
+                InputAction::Submit(command) => {
+                    if !self.handle_command(&command)? {
+                        break;
+                    }
+                }
+                InputAction::Complete => {
+                    let completion = Completer::new(
+                        &self.permissions,
+                        &self.variables,
+                        &self.config,
+                        &self.completion_specs,
+                    )
+                    .with_definitions(&self.aliases, &self.functions)
+                    .complete(self.input_editor.buffer(), self.input_editor.cursor_pos());
+                    self.input_editor.apply_completion(completion);
+                }
+                InputAction::Paste(text) => self.handle_paste(&text),
+                InputAction::None => {}
             }
+
+            self.render()?;
         }
 
         Ok(())
     }
 
+    /// Handle scrollback and output search keys, returning whether the key was used
+    fn handle_scroll_key(&mut self, key: KeyEvent) -> bool {
+        let shift = key.modifiers.contains(KeyModifiers::SHIFT);
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let page = self.renderer.page_size();
+
+        match key.code {
+            KeyCode::PageUp => self.renderer.scroll_up(page),
+            KeyCode::PageDown => self.renderer.scroll_down(page),
+            KeyCode::Up if shift => self.renderer.scroll_up(1),
+            KeyCode::Down if shift => self.renderer.scroll_down(1),
+            KeyCode::Char('f') if ctrl && self.pending.is_none() => self.start_search(),
+            KeyCode::Char('/')
+                if self.renderer.is_scrolled() && self.input_editor.buffer().is_empty() && self.pending.is_none() =>
+            {
+                self.start_search()
+            }
+            // While a search is showing, n/N step through matches like in less
+            KeyCode::Char('n') if self.renderer.is_searching() && self.pending.is_none() => {
+                self.renderer.next_match(true)
+            }
+            KeyCode::Char('N') if self.renderer.is_searching() && self.pending.is_none() => {
+                self.renderer.next_match(false)
+            }
+            KeyCode::Esc if self.renderer.is_searching() || self.renderer.is_scrolled() => {
+                self.renderer.end_search();
+                self.renderer.scroll_to_bottom();
+            }
+            _ => {
+                // Any other key goes back to editing the command line
+                if self.pending.is_none() {
+                    self.renderer.end_search();
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Ask for a search query, keeping the current input line for later
+    fn start_search(&mut self) {
+        let line = self.input_editor.take_line();
+        self.pending = Some(PendingAction::Search(line));
+    }
+
     fn handle_command(&mut self, command: &str) -> io::Result<bool> {
-        // Add command to output
-        self.renderer
-            .add_output(format!("{}{}", PROMPT, command));
+        // Show the newest output again
+        self.renderer.end_search();
+        self.renderer.scroll_to_bottom();
 
-        // Add to history
-        self.input_editor.add_to_history(command.to_string());
+        // Add command to output
+        self.echo_command(command);
 
         // Clear input
         self.input_editor.clear();
 
+        // Expand history references before the command is parsed
+        match history::expand(command, self.input_editor.history()) {
+            Ok(None) => self.execute_command(command),
+            Ok(Some(expanded)) => {
+                self.renderer.add_output(format!("↻ {}", expanded));
+                if self.interactive {
+                    // Never re-run a rewritten command without showing it first
+                    self.pending = Some(PendingAction::RunExpanded(expanded));
+                    Ok(true)
+                } else {
+                    self.execute_command(&expanded)
+                }
+            }
+            Err(e) => {
+                self.renderer.add_output(format!("dshell: {}", e));
+                Ok(true)
+            }
+        }
+    }
+
+    /// Review pasted text and insert it, asking first if it looks risky
+    ///
+    /// Pasted text only ever lands in the input line; it is never executed
+    /// without the user pressing Enter.
+    fn handle_paste(&mut self, text: &str) {
+        let warnings = paste::review(text);
+        if warnings.is_empty() {
+            self.input_editor.insert_text(&paste::sanitize(text));
+            return;
+        }
+
+        if self.pending.is_some() {
+            self.renderer
+                .add_output("⚠️  Paste ignored while waiting for an answer".to_string());
+            return;
+        }
+
+        self.renderer.add_output("⚠️  Pasted text needs review:".to_string());
+        for warning in &warnings {
+            self.renderer.add_output(format!("  • {}", warning.describe()));
+        }
+        self.renderer.add_output_lines(paste::preview(text));
+
+        let line = self.input_editor.take_line();
+        self.pending = Some(PendingAction::InsertPaste(paste::sanitize(text), line));
+    }
+
+    /// Handle the user's answer to a pending confirmation prompt
+    fn resolve_pending(&mut self, answer: &str) -> io::Result<bool> {
+        let Some(pending) = self.pending.take() else {
+            return Ok(true);
+        };
+
+        // Search queries aren't part of the session, so don't echo them
+        if !matches!(pending, PendingAction::Search(_)) {
+            self.renderer
+                .add_output(format!("{}{}", pending.prompt(), answer));
+        }
+        self.input_editor.clear();
+
+        let confirmed = matches!(answer.trim(), "y" | "Y" | "yes");
+        match pending {
+            PendingAction::RunExpanded(command) => {
+                if confirmed {
+                    self.echo_command(&command);
+                    self.execute_command(&command)
+                } else {
+                    self.renderer.add_output("Cancelled".to_string());
+                    Ok(true)
+                }
+            }
+            PendingAction::InsertPaste(text, line) => {
+                self.input_editor.restore_line(line);
+                if confirmed {
+                    self.input_editor.insert_text(&text);
+                    self.renderer
+                        .add_output("✓ Pasted text inserted; press Enter to run it".to_string());
+                } else {
+                    self.renderer.add_output("Paste discarded".to_string());
+                }
+                Ok(true)
+            }
+            PendingAction::Unmask(number) => {
+                let masked = self.redactor.get(number).cloned().filter(|_| confirmed);
+                match masked {
+                    Some(masked) => {
+                        let target = format!("#{} {}", number, masked.kind);
+                        self.record(|log| log.record_permission("unmask", &target));
+                        self.renderer.add_output(format!("#{} ({}):", number, masked.kind));
+                        self.renderer.add_output_lines(masked.value.lines().map(str::to_string).collect());
+                    }
+                    None => self.renderer.add_output("Cancelled".to_string()),
+                }
+                Ok(true)
+            }
+            PendingAction::LoadDotenv(file, vars) => {
+                let names: Vec<&str> = vars.iter().map(|(name, _)| name.as_str()).collect();
+                match dotenv::select(answer, &names) {
+                    Ok(selected) => {
+                        let chosen = selected.into_iter().map(|index| vars[index].clone()).collect();
+                        self.load_env_vars(&file, chosen);
+                    }
+                    Err(e) => self.renderer.add_output(format!("✗ dotenv: {}; nothing loaded", e)),
+                }
+                Ok(true)
+            }
+            PendingAction::Search(line) => {
+                self.input_editor.restore_line(line);
+                if !answer.is_empty() && self.renderer.search(answer) == 0 {
+                    self.renderer.scroll_to_bottom();
+                    self.renderer.add_output(format!("Pattern not found: {}", answer));
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    /// List the variables in a `.env` file and ask which to load; their
+    /// values aren't shown
+    fn review_dotenv(&mut self, path: &Path) {
+        let file = path.display().to_string();
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                self.renderer.add_output(format!("✗ dotenv: {}: {}", file, e));
+                self.variables.set_status(1);
+                return;
+            }
+        };
+
+        // References resolve like `$VAR` on the command line
+        let (vars, errors) = dotenv::parse(&content, |name| self.variables.value(name, &self.permissions));
+        for error in errors {
+            self.renderer.add_output(format!("⚠️  {}: {}", file, error));
+        }
+        if vars.is_empty() {
+            self.renderer.add_output(format!("No variables in {}", file));
+            return;
+        }
+        if !self.interactive {
+            self.load_env_vars(&file, vars);
+            return;
+        }
+
+        self.renderer.add_output(format!("Variables in {}:", file));
+        for (number, (name, _)) in vars.iter().enumerate() {
+            let mut notes = Vec::new();
+            if self.variables.get(name).is_some() {
+                notes.push("replaces the current value");
+            }
+            if redact::is_secret_name(name) {
+                notes.push("looks like a secret");
+            }
+            let notes = if notes.is_empty() { String::new() } else { format!(" ({})", notes.join(", ")) };
+            self.renderer.add_output(format!("  {}. {}{}", number + 1, name, notes));
+        }
+        self.pending = Some(PendingAction::LoadDotenv(file, vars));
+    }
+
+    /// Add variables from a `.env` file to the session
+    fn load_env_vars(&mut self, file: &str, vars: Vec<(String, String)>) {
+        if vars.is_empty() {
+            self.renderer.add_output("Nothing loaded".to_string());
+            return;
+        }
+        let mut names = Vec::new();
+        for (name, value) in vars {
+            if let Err(e) = self.variables.export(&name, Some(value)) {
+                self.renderer.add_output(format!("✗ dotenv: {}", e));
+                self.variables.set_status(1);
+                continue;
+            }
+            // The name only; values may be secrets
+            self.record(|log| log.record_permission("export", &name));
+            names.push(name);
+        }
+        if names.is_empty() {
+            return;
+        }
+        self.renderer.add_output(format!(
+            "✓ Loaded {} variable(s) from {}: {}",
+            names.len(),
+            file,
+            names.join(", ")
+        ));
+    }
+
+    /// Run `NAME=value`, `export`, `readonly`, `unset` or `set`
+    fn run_variable_command(&mut self, command: VariableCommand) {
+        let mut errors = Vec::new();
+        match command {
+            VariableCommand::Assign(vars) => {
+                for (name, value) in vars {
+                    if let Err(e) = self.variables.set(&name, value) {
+                        errors.push(e);
+                    }
+                }
+            }
+            VariableCommand::Export(vars) if vars.is_empty() => {
+                let lines = self.variables.list(|var| var.exported);
+                self.add_builtin_output(lines);
+            }
+            VariableCommand::Export(vars) => {
+                for (name, value) in vars {
+                    let shown = value.as_ref().map(|value| format!("{}={}", name, value));
+                    match self.variables.export(&name, value) {
+                        Ok(()) => {
+                            // The name only; values may be secrets
+                            self.record(|log| log.record_permission("export", &name));
+                            self.add_builtin_output(vec![match shown {
+                                Some(shown) => format!("✓ Set environment variable: {}", shown),
+                                None => format!("✓ Exported: {}", name),
+                            }]);
+                        }
+                        // Whether commands get inherited variables is up to
+                        // `allow` and `deny`
+                        Err(e) if self.variables.value(&name, &self.permissions).is_some() => {
+                            errors.push(format!("{} in this session; inherited variables are passed on already", e))
+                        }
+                        Err(e) => errors.push(e),
+                    }
+                }
+            }
+            VariableCommand::Readonly(vars) if vars.is_empty() => {
+                let lines = self.variables.list(|var| var.readonly);
+                self.add_builtin_output(lines);
+            }
+            VariableCommand::Readonly(vars) => {
+                for (name, value) in vars {
+                    // An allowed inherited variable keeps its value
+                    let value = value.or_else(|| self.variables.value(&name, &self.permissions));
+                    if let Err(e) = self.variables.readonly(&name, value) {
+                        errors.push(e);
+                    }
+                }
+            }
+            VariableCommand::Unset(names) => {
+                for name in names {
+                    match self.variables.unset(&name) {
+                        Ok(()) => self.record(|log| log.record_permission("unset", &name)),
+                        Err(e) => errors.push(e),
+                    }
+                }
+            }
+            VariableCommand::List => {
+                let lines = self.variables.list(|_| true);
+                self.add_builtin_output(lines);
+            }
+            VariableCommand::ListOptions => {
+                let lines = self.variables.options.list();
+                self.renderer.add_output_lines(lines);
+            }
+            VariableCommand::SetOptions(options) => {
+                for (option, on) in options {
+                    self.variables.options.set(option, on);
+                }
+            }
+        }
+        if !errors.is_empty() {
+            self.renderer.add_output_lines(errors.into_iter().map(|e| format!("dshell: {}", e)).collect());
+            self.variables.set_status(1);
+        }
+    }
+
+    /// Run a function's body with `args` as its positional parameters
+    fn call_function(&mut self, name: &str, args: Vec<String>) -> io::Result<bool> {
+        let Some(body) = self.functions.get(name).map(str::to_string) else {
+            return Ok(true);
+        };
+        if self.depth > functions::MAX_DEPTH {
+            self.renderer.add_output(format!("dshell: {}: functions nested too deeply (more than {})", name, functions::MAX_DEPTH));
+            self.variables.set_status(1);
+            return Ok(true);
+        }
+
+        let saved = self.variables.set_positional(args);
+        let result = self.run_line(&body);
+        self.variables.set_positional(saved);
+        result
+    }
+
+    /// Run the commands in a script, for `source` and the rc file
+    ///
+    /// Lines starting with `#` are comments, and quotes, blocks and
+    /// trailing backslashes continue a command on the next line. A failed
+    /// command is reported with its line and the script goes on, unless
+    /// errexit is set.
+    fn source(&mut self, path: &Path, args: Vec<String>) -> io::Result<bool> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                self.renderer.add_output(format!("dshell: source: {}: {}", path.display(), e));
+                self.variables.set_status(1);
+                return Ok(true);
+            }
+        };
+        if self.depth > functions::MAX_DEPTH {
+            self.renderer.add_output(format!("dshell: {}: scripts nested too deeply (more than {})", path.display(), functions::MAX_DEPTH));
+            self.variables.set_status(1);
+            return Ok(true);
+        }
+
+        // Arguments replace the positional parameters while the script runs
+        let saved = (!args.is_empty()).then(|| self.variables.set_positional(args));
+        let mut result = Ok(true);
+        let mut lines = content.lines().enumerate();
+        while let Some((number, line)) = lines.next() {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            let mut command = line.to_string();
+            while parser::needs_continuation(&command) {
+                match lines.next() {
+                    Some((_, next)) => {
+                        command.push('\n');
+                        command.push_str(next);
+                    }
+                    None => break,
+                }
+            }
+
+            result = self.run_line(&command);
+            if !matches!(result, Ok(true)) {
+                break;
+            }
+            let status = self.variables.status();
+            if status != 0 {
+                self.renderer.add_output(format!("⚠️  {}:{}: exit status {}", path.display(), number + 1, status));
+                if self.variables.options.errexit {
+                    break;
+                }
+            }
+        }
+        if let Some(saved) = saved {
+            self.variables.set_positional(saved);
+        }
+        result
+    }
+
+    /// Run the rc file, if there is one; its errors are reported but don't
+    /// stop dshell from starting
+    fn run_rc(&mut self, rc: Option<PathBuf>) -> io::Result<bool> {
+        match rc {
+            Some(rc) if rc.exists() => self.source(&rc, Vec::new()),
+            _ => Ok(true),
+        }
+    }
+
+    /// The prompt, which `PS1` replaces
+    fn prompt(&self) -> String {
+        self.variables.get("PS1").map_or(PROMPT, |var| var.value.as_str()).to_string()
+    }
+
+    /// Show a command as it will run, for `set -x`; secrets are redacted
+    /// as in command output
+    fn trace(&mut self, command: &str) {
+        let expanded = ParsedCommand::parse_expanded(command, |reference| self.variables.expand(reference, &self.permissions));
+        if let Ok(Some(cmd)) = expanded {
+            let words: Vec<String> = std::iter::once(&cmd.command).chain(&cmd.args).map(|word| parser::quote(word)).collect();
+            self.add_command_output(vec![StyledLine::plain(format!("+ {}", words.join(" ")))]);
+        }
+    }
+
+    /// Report a command that couldn't be started, with the status other
+    /// shells use: 127 when it wasn't found, 126 otherwise
+    fn spawn_failed(&mut self, cmd: &ParsedCommand, e: &io::Error) {
+        self.renderer.add_output_lines(Executor::spawn_error(cmd, e));
+        self.variables.set_status(if e.kind() == io::ErrorKind::NotFound { 127 } else { 126 });
+    }
+
+    /// Show a submitted command in the output, with a prompt on each line
+    fn echo_command(&mut self, command: &str) {
+        let continued = command.replace('\n', &format!("\n{}", CONTINUATION_PROMPT));
+        self.renderer.add_output(format!("{}{}", self.prompt(), continued));
+    }
+
+    /// Record a command line in history and run each command in it
+    fn execute_command(&mut self, command: &str) -> io::Result<bool> {
+        // Add to history, flagging entries that must never be suggested
+        let sensitive = history::looks_sensitive(command)
+            || self.permissions.contains_secret(command)
+            || self.redactor.contains_secret(command);
+        self.input_editor.add_to_history(HistoryEntry::new(
+            command.to_string(),
+            std::env::current_dir().ok(),
+            sensitive,
+        ));
+
+        self.run_line(command)
+    }
+
+    /// Run each command in a command line, a function body or an alias
+    ///
+    /// With errexit, the first failure stops the line and every line it
+    /// was called from; only the outermost reports it.
+    fn run_line(&mut self, line: &str) -> io::Result<bool> {
+        self.depth += 1;
+        let result = self.run_commands(line);
+        self.depth -= 1;
+        result
+    }
+
+    fn run_commands(&mut self, line: &str) -> io::Result<bool> {
+        for part in parser::split_commands(line) {
+            let keep_running = match parser::background(part) {
+                Some(background) => self.start_background(background)?,
+                None => self.run_command(part)?,
+            };
+            if !keep_running {
+                return Ok(false);
+            }
+            if self.variables.options.errexit && self.variables.status() != 0 {
+                if self.depth == 1 {
+                    self.renderer.add_output(format!(
+                        "✗ Stopped: exit status {} (errexit is set)",
+                        self.variables.status()
+                    ));
+                }
+                break;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Start a command as a background job with its output kept in the job
+    fn start_background(&mut self, command: &str) -> io::Result<bool> {
+        let expanded = self.aliases.expand(command);
+        if let Some(expanded) = &expanded
+            && parser::split_commands(expanded).len() > 1
+        {
+            self.renderer.add_output(format!("dshell: {}: an alias for several commands can't run in the background", command));
+            self.variables.set_status(1);
+            return Ok(true);
+        }
+        let command = expanded.as_deref().unwrap_or(command);
+        let cmd = match Shell::parse_expanded(command, &self.permissions, &self.variables) {
+            Ok(Some(cmd)) => cmd,
+            Ok(None) => return Ok(true),
+            Err(message) => {
+                self.renderer.add_output(message);
+                self.variables.set_status(2);
+                return Ok(true);
+            }
+        };
+
+        // Builtins change dshell's own state, so they run in the foreground
+        if Builtins::parse(&cmd).is_some() {
+            return self.run_command(command);
+        }
+        if self.functions.get(&cmd.command).is_some() {
+            self.renderer.add_output(format!("dshell: {}: functions can't run in the background", cmd.command));
+            self.variables.set_status(1);
+            return Ok(true);
+        }
+
+        let watchdog = self.watchdog(&cmd);
+        let mut audit = self.command_audit(&cmd, RunMode::Background);
+        let report = IsolationReport::new()?;
+        let limits = watchdog.child_limits();
+        match Executor::spawn_captured(&cmd, &self.permissions, &self.variables, &self.config, &limits, report.sender()) {
+            Ok(process) => {
+                // The child restricted itself before exec, which spawn waits for
+                audit.isolation = report.receive();
+                let pid = process.pid();
+                let id = self.jobs.add(cmd.raw_input.clone(), JobProcess::Captured(process), JobState::Running, watchdog, audit);
+                self.renderer.add_output(format!("[{}] {}", id, pid));
+            }
+            Err(e) => self.spawn_failed(&cmd, &e),
+        }
+        Ok(true)
+    }
+
+    /// Run a single command
+    fn run_command(&mut self, command: &str) -> io::Result<bool> {
+        if self.variables.options.xtrace {
+            self.trace(command);
+        }
+
         // Process the command
-        if let Some(action) = Shell::process_input(command, &self.permissions, &self.custom_env, &self.config) {
+        let action = Shell::process_input(
+            command,
+            &self.permissions,
+            &self.variables,
+            &self.aliases,
+            &self.functions,
+            &self.config,
+        );
+        if let Some(action) = action {
+            // Builtins succeed unless they fail; commands set their own status
+            self.variables.set_status(0);
             match action {
                 CommandAction::Exit => return Ok(false),
                 CommandAction::ClearScreen => {
                     self.renderer.clear_output();
                 }
                 CommandAction::ShowOutput(lines) => {
-                    self.renderer.add_output_lines(lines);
+                    self.add_builtin_output(lines);
+                }
+                CommandAction::Error(message) => {
+                    self.renderer.add_output(message);
+                    self.variables.set_status(2);
+                }
+                CommandAction::ExecuteCaptured(cmd) if self.interactive && self.config.captured_pty => {
+                    self.execute_in_pty(&cmd)?;
+                }
+                CommandAction::ExecuteCaptured(cmd) if self.interactive => {
+                    let watchdog = self.watchdog(&cmd);
+                    let mut audit = self.command_audit(&cmd, RunMode::Captured);
+                    let report = IsolationReport::new()?;
+                    let limits = watchdog.child_limits();
+                    match Executor::spawn_captured(&cmd, &self.permissions, &self.variables, &self.config, &limits, report.sender()) {
+                        Ok(process) => {
+                            audit.isolation = report.receive();
+                            self.run_captured(cmd.raw_input.clone(), process, watchdog, audit)?
+                        }
+                        Err(e) => self.spawn_failed(&cmd, &e),
+                    }
                 }
                 CommandAction::ExecuteCaptured(cmd) => {
-                    let output = Shell::execute_captured(&cmd, &self.permissions, &self.custom_env);
-                    self.renderer.add_output_lines(output);
+                    let mut audit = self.command_audit(&cmd, RunMode::Captured);
+                    let result =
+                        Shell::execute_captured(&cmd, &self.permissions, &self.variables, &self.config, self.cgroups.as_ref());
+                    audit.isolation = result.isolation;
+                    self.add_command_output(ansi::parse_lines(&result.output));
+                    match result.timing {
+                        Some(timing) => {
+                            self.record(|log| log.record_command(&audit, Some(timing.status), timing.wall));
+                            self.variables.set_status(timing.status.code());
+                            self.report_timing(timing);
+                        }
+                        // It didn't start; the output says why
+                        None => self.variables.set_status(127),
+                    }
                 }
                 CommandAction::ExecuteInteractive(cmd) => {
-                    // Disable raw mode and clear screen
-                    crossterm_terminal::disable_raw_mode()?;
-                    TerminalRenderer::clear_screen()?;
-
                     // Execute the command with Landlock filesystem isolation
-                    match Executor::execute_interactive(&cmd, &self.permissions, &self.custom_env, &self.config) {
-                        Ok(_status) => {
-                            // Command executed successfully
-                            // The isolation status is printed by the executor
-                        }
-                        Err(e) => {
-                            eprintln!("dshell: {}: {}", cmd.command, e);
-                        }
+                    let watchdog = self.watchdog(&cmd);
+                    let audit = self.command_audit(&cmd, RunMode::Interactive);
+                    let report = IsolationReport::new()?;
+                    self.run_interactive(&cmd.raw_input, watchdog, audit, |app, watchdog, audit| {
+                        let result =
+                            Executor::execute_interactive(&cmd, &app.permissions, &app.variables, &app.config, watchdog, report.sender());
+                        audit.isolation = report.receive();
+                        result
+                    })?;
+                }
+                CommandAction::Job(job_cmd) => self.run_job_command(job_cmd)?,
+                CommandAction::Audit(AuditCommand::Show { count, search }) => {
+                    let lines = self.audit.query(count, search.as_deref());
+                    self.renderer.add_output_lines(lines);
+                }
+                CommandAction::Unmask(None) => {
+                    let lines = self.redactor.list();
+                    self.renderer.add_output_lines(lines);
+                }
+                CommandAction::Unmask(Some(number)) => match self.redactor.get(number) {
+                    None => self.renderer.add_output(format!("unmask: #{}: no such redacted value", number)),
+                    Some(_) if !self.interactive => {
+                        self.renderer.add_output("unmask: needs confirmation, so only works interactively".to_string())
                     }
-
-                    // Re-enable raw mode
-                    crossterm_terminal::enable_raw_mode()?;
-
-                    // Add continuation message
-                    self.renderer
-                        .add_output("[Nothing to display. Press Enter to continue]".to_string());
+                    Some(masked) => {
+                        self.renderer.add_output(format!("Redacted value #{}: {}", number, masked.kind));
+                        self.pending = Some(PendingAction::Unmask(number));
+                    }
+                },
+                CommandAction::Audit(AuditCommand::Verify) => {
+                    let lines = self.audit.verify().lines;
+                    self.renderer.add_output_lines(lines);
+                }
+                CommandAction::Time(command) => return self.run_timed(&command),
+                CommandAction::Limit(LimitCommand::Set(limit, value)) => {
+                    // Only for this session; the config file is left alone
+                    limit.set(&mut self.config.limits, value);
+                    self.renderer.add_output(format!(
+                        "✓ {} limit set to {} for this session",
+                        limit.name(),
+                        limit.format_value(Some(value))
+                    ));
+                }
+                CommandAction::Limit(LimitCommand::Show) => {
+                    self.renderer.add_output_lines(LimitCommand::show(&self.config, self.cgroups.as_ref()));
                 }
                 CommandAction::AllowEnvVar(var) => {
                     self.permissions.allow_env_var(var.clone());
+                    self.record(|log| log.record_permission("allow", &var));
                     self.renderer
                         .add_output(format!("✓ Allowed access to: {}", var));
                 }
                 CommandAction::DenyEnvVar(var) => {
                     self.permissions.deny_env_var(var.clone());
+                    self.record(|log| log.record_permission("deny", &var));
                     self.renderer
                         .add_output(format!("✗ Denied access to: {}", var));
                 }
                 CommandAction::AllowAllEnvVars => {
                     self.permissions.set_env_access(Permission::Allowed);
+                    self.record(|log| log.record_permission("allow", "*"));
                     self.renderer
                         .add_output("✓ Allowed access to ALL environment variables".to_string());
                 }
                 CommandAction::DenyAllEnvVars => {
                     self.permissions.set_env_access(Permission::Denied);
+                    self.record(|log| log.record_permission("deny", "*"));
                     self.renderer
                         .add_output("✗ Denied access to ALL environment variables".to_string());
                 }
-                CommandAction::SetEnvVar(key, value) => {
-                    self.custom_env.insert(key.clone(), value.clone());
-                    self.renderer
-                        .add_output(format!("✓ Set environment variable: {}={}", key, value));
-                }
+                CommandAction::Variables(variable_cmd) => self.run_variable_command(variable_cmd),
+                CommandAction::Dotenv(DotenvCommand { path }) => self.review_dotenv(&path),
+                CommandAction::Alias(alias_cmd) => match self.aliases.run(alias_cmd) {
+                    Ok(lines) => self.add_builtin_output(lines),
+                    Err(e) => {
+                        self.renderer.add_output(format!("dshell: {}", e));
+                        self.variables.set_status(1);
+                    }
+                },
+                CommandAction::Function(function_cmd) => match self.functions.run(function_cmd) {
+                    Ok(lines) => self.add_builtin_output(lines),
+                    Err(e) => {
+                        self.renderer.add_output(format!("dshell: {}", e));
+                        self.variables.set_status(1);
+                    }
+                },
+                CommandAction::CallFunction(name, args) => return self.call_function(&name, args),
+                CommandAction::RunLine(line) => return self.run_line(&line),
+                CommandAction::Source(path, args) => return self.source(&path, args),
                 CommandAction::AllowPath(path) => {
                     self.permissions.allow_path(path.clone());
+                    self.record(|log| log.record_permission("allowpath", &path));
                     self.renderer
                         .add_output(format!("✓ Allowed filesystem access to: {}", path));
+                    if let Some(log_dir) = Executor::audit_log_dir(&self.config) {
+                        if LandlockIsolation::paths_excluding(&path, &log_dir).is_empty() {
+                            self.renderer.add_output(format!(
+                                "⚠️  {} is in the audit log directory, which isolated commands can't reach, so this grants nothing",
+                                path
+                            ));
+                        } else if LandlockIsolation::covers(&path, &log_dir) {
+                            self.renderer.add_output(format!(
+                                "⚠️  Isolated commands get what {} contains now, except the way to the audit log in {}; \
+                                 they can't create files directly in the directories on that way",
+                                path,
+                                log_dir.display()
+                            ));
+                        }
+                    }
                 }
                 CommandAction::DenyPath(path) => {
                     self.permissions.deny_path(path.clone());
+                    self.record(|log| log.record_permission("denypath", &path));
                     self.renderer
                         .add_output(format!("✗ Denied filesystem access to: {}", path));
                 }
+                CommandAction::History(history_cmd) => match history_cmd {
+                    HistoryCommand::Delete(number) => {
+                        if self.input_editor.delete_history_entry(number) {
+                            self.renderer
+                                .add_output(format!("✓ Deleted history entry {}", number));
+                        } else {
+                            self.renderer
+                                .add_output(format!("history: {}: history position out of range", number));
+                        }
+                    }
+                    HistoryCommand::Clear => {
+                        self.input_editor.clear_history();
+                        self.renderer.add_output("✓ History cleared".to_string());
+                    }
+                    _ => {
+                        let lines = history_cmd.list(self.input_editor.history());
+                        self.renderer.add_output_lines(lines);
+                    }
+                },
             }
         }
 
         Ok(true)
     }
 
-    fn render(&self) -> io::Result<()> {
+    /// Run a job control builtin
+    fn run_job_command(&mut self, job_cmd: JobCommand) -> io::Result<()> {
+        match job_cmd {
+            JobCommand::List => {
+                let lines = self.jobs.list();
+                self.renderer.add_output_lines(lines);
+            }
+            JobCommand::Output(spec) => match self.jobs.resolve(spec.as_deref()) {
+                Ok(id) => {
+                    let output = self.jobs.take_output(id);
+                    if output.is_empty() {
+                        self.renderer.add_output(format!("(no new output from job {})", id));
+                    }
+                    self.add_command_output(ansi::parse_lines(&output));
+                }
+                Err(e) => self.renderer.add_output(format!("dshell: jobs: {}", e)),
+            },
+            JobCommand::Foreground(spec) => {
+                let job = match self.jobs.resolve(spec.as_deref()) {
+                    Ok(id) => self.jobs.take(id).expect("resolved job is in the table"),
+                    Err(e) => {
+                        self.renderer.add_output(format!("dshell: fg: {}", e));
+                        return Ok(());
+                    }
+                };
+                let (id, state) = (job.id, job.state);
+                let (command, output, process, watchdog, audit) = job.into_parts();
+                self.renderer.add_output(command.clone());
+                self.add_command_output(ansi::parse_lines(&output));
+                if let JobState::Done(_) = state {
+                    self.renderer.add_output(format!("dshell: fg: job {} has already finished", id));
+                    return Ok(());
+                }
+
+                if state == JobState::Stopped {
+                    let _ = Executor::signal_group(process.pid(), libc::SIGCONT);
+                }
+                match process {
+                    JobProcess::Interactive(pid) => {
+                        self.run_interactive(&command, watchdog, audit, |_, watchdog, _| {
+                            Executor::resume_interactive(pid, watchdog)
+                        })?;
+                    }
+                    JobProcess::Captured(process) if self.interactive => {
+                        self.run_captured(command, process, watchdog, audit)?
+                    }
+                    JobProcess::Captured(process) => {
+                        let (output, status, usage) = process.finish();
+                        self.add_command_output(ansi::parse_lines(&output));
+                        self.report_finished(&watchdog, &audit, status, usage);
+                    }
+                    JobProcess::Pty(pty) => self.run_in_pty(command, pty, watchdog, audit)?,
+                }
+            }
+            JobCommand::Background(spec) => match self.jobs.resolve(spec.as_deref()) {
+                Ok(id) if self.jobs.get(id).is_some_and(|job| job.state == JobState::Stopped) => {
+                    match self.jobs.signal(id, libc::SIGCONT) {
+                        Ok(()) => {
+                            let command = &self.jobs.get(id).expect("resolved job is in the table").command;
+                            self.renderer.add_output(format!("[{}]+ {} &", id, command));
+                        }
+                        Err(e) => self.renderer.add_output(format!("dshell: bg: {}", e)),
+                    }
+                }
+                Ok(id) => self.renderer.add_output(format!("dshell: bg: job {} is not stopped", id)),
+                Err(e) => self.renderer.add_output(format!("dshell: bg: {}", e)),
+            },
+            JobCommand::Disown(spec) => match self.jobs.resolve(spec.as_deref()) {
+                Ok(id) => {
+                    let job = self.jobs.take(id).expect("resolved job is in the table");
+                    let (command, _, process, watchdog, audit) = job.into_parts();
+                    // dshell won't see how it ends
+                    self.record(|log| log.record_command(&audit, None, watchdog.elapsed()));
+                    if let JobProcess::Pty(_) = process {
+                        self.renderer
+                            .add_output(format!("⚠️  {} loses its terminal and will be sent SIGHUP", command));
+                    }
+                    self.renderer.add_output(format!("✓ Disowned [{}] {} (PID {})", id, command, process.pid()));
+                }
+                Err(e) => self.renderer.add_output(format!("dshell: disown: {}", e)),
+            },
+            JobCommand::Kill(signal, targets) => {
+                for target in targets {
+                    if let Err(e) = self.signal_target(&target, signal) {
+                        self.renderer.add_output(format!("dshell: kill: {}: {}", target, e));
+                    }
+                }
+            }
+            JobCommand::Wait(specs) => {
+                let mut ids = Vec::new();
+                for spec in &specs {
+                    match self.jobs.resolve(Some(spec)) {
+                        Ok(id) => ids.push(id),
+                        Err(e) => self.renderer.add_output(format!("dshell: wait: {}", e)),
+                    }
+                }
+                if specs.is_empty() || !ids.is_empty() {
+                    self.wait_for_jobs(&ids)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a signal to a job (`%n`) or a process ID for `kill`
+    fn signal_target(&mut self, target: &str, signal: i32) -> Result<(), String> {
+        if target.starts_with('%') {
+            let id = self.jobs.resolve(Some(target))?;
+            self.jobs.signal(id, signal).map_err(|e| e.to_string())?;
+            // A stopped job must run to act on the signal
+            if signal != libc::SIGSTOP && signal != libc::SIGCONT {
+                let _ = self.jobs.signal(id, libc::SIGCONT);
+            }
+            return Ok(());
+        }
+
+        let pid: i32 = target.parse().map_err(|_| "arguments must be process or job IDs".to_string())?;
+        if unsafe { libc::kill(pid, signal) } == -1 {
+            return Err(io::Error::last_os_error().to_string());
+        }
+        Ok(())
+    }
+
+    /// Wait until the given jobs (or all jobs) are no longer running,
+    /// showing notifications as they finish; Ctrl+C stops waiting
+    fn wait_for_jobs(&mut self, ids: &[usize]) -> io::Result<()> {
+        loop {
+            let notifications = self.jobs.update(&mut self.audit);
+            self.renderer.add_output_lines(notifications);
+            if !self.jobs.any_running(ids) {
+                return Ok(());
+            }
+
+            if !self.interactive {
+                std::thread::sleep(JOB_POLL_INTERVAL);
+                continue;
+            }
+            self.render()?;
+            if event::poll(JOB_POLL_INTERVAL)?
+                && let Event::Key(key) = event::read()?
+                && Self::signal_for_key(&key) == Some(libc::SIGINT)
+            {
+                self.renderer.add_output("^C".to_string());
+                return Ok(());
+            }
+        }
+    }
+
+    /// Run a captured command on a PTY, showing its screen as it runs
+    ///
+    /// Keys and pastes go to the program so it can prompt for input. When it
+    /// exits, the final rendered text is added to the output.
+    fn execute_in_pty(&mut self, cmd: &ParsedCommand) -> io::Result<()> {
+        let (width, height) = Self::live_output_size()?;
+        let watchdog = self.watchdog(cmd);
+        let mut audit = self.command_audit(cmd, RunMode::Pty);
+        let report = IsolationReport::new()?;
+        let limits = watchdog.child_limits();
+        match Executor::spawn_in_pty(cmd, &self.permissions, &self.variables, &self.config, (width, height), &limits, report.sender()) {
+            Ok(pty) => {
+                audit.isolation = report.receive();
+                self.run_in_pty(cmd.raw_input.clone(), pty, watchdog, audit)
+            }
+            Err(e) => {
+                self.spawn_failed(cmd, &e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Show a program on a PTY until it exits or is stopped with Ctrl+Z
+    ///
+    /// Ctrl+C and Ctrl+\ go to the program's terminal, which turns them into
+    /// signals. Ctrl+Z is handled here: the program leads its own session,
+    /// and the kernel ignores terminal stop requests for such programs.
+    fn run_in_pty(&mut self, command: String, mut pty: PtyProcess, watchdog: Watchdog, audit: CommandAudit) -> io::Result<()> {
+        let (width, height) = Self::live_output_size()?;
+        let _ = pty.resize(width, height);
+        let mut emulator = TerminalEmulator::new(width as usize, height as usize);
+        let mut last_render = Instant::now();
+        // Everything the program wrote, replayed if it takes over the terminal
+        let mut transcript = Vec::new();
+
+        // Read until the program closes the terminal. A background process
+        // that inherited it could keep it open, so also stop once the
+        // program has exited and its remaining output is drained.
+        let mut exited = false;
+        let mut stopped = false;
+        while let Some(bytes) = pty.read_available(Duration::from_millis(10)) {
+            emulator.feed(&bytes);
+            let responses = emulator.take_responses();
+            if !responses.is_empty() {
+                let _ = pty.write_input(&responses);
+            }
+
+            // Full-screen programs and line editors get the real terminal
+            if self.config.detect_fullscreen {
+                transcript.extend_from_slice(&bytes);
+                if emulator.in_alternate_screen() || pty.in_raw_mode() {
+                    self.passthrough(&mut pty, &mut emulator, &transcript, &watchdog)?;
+                    break;
+                }
+            }
+            if bytes.is_empty() && exited {
+                break;
+            }
+            exited = exited || pty.try_wait()?.is_some();
+            if watchdog.expired() && !exited {
+                watchdog.kill(pty.pid());
+            }
+
+            let mut dirty = !bytes.is_empty();
+            while !stopped && event::poll(Duration::ZERO)? {
+                let input = match event::read()? {
+                    Event::Key(key) if Self::signal_for_key(&key) == Some(libc::SIGTSTP) => {
+                        stopped = Executor::signal_group(pty.pid(), libc::SIGSTOP).is_ok();
+                        Vec::new()
+                    }
+                    Event::Key(key) => input::key_to_bytes(&key),
+                    Event::Paste(text) => text.into_bytes(),
+                    Event::Resize(..) => {
+                        let (width, height) = Self::live_output_size()?;
+                        let _ = pty.resize(width, height);
+                        emulator.resize(width as usize, height as usize);
+                        Vec::new()
+                    }
+                    Event::Mouse(mouse) => {
+                        match mouse.kind {
+                            MouseEventKind::ScrollUp => self.renderer.scroll_up(MOUSE_SCROLL_LINES),
+                            MouseEventKind::ScrollDown => self.renderer.scroll_down(MOUSE_SCROLL_LINES),
+                            _ => {}
+                        }
+                        Vec::new()
+                    }
+                    _ => Vec::new(),
+                };
+                if !input.is_empty() {
+                    let _ = pty.write_input(&input);
+                }
+                dirty = true;
+            }
+            if stopped {
+                break;
+            }
+
+            if dirty && last_render.elapsed() >= LIVE_RENDER_INTERVAL {
+                self.renderer.set_live_output(Some(emulator.view()));
+                self.render_running()?;
+                last_render = Instant::now();
+            }
+        }
+
+        self.renderer.set_live_output(None);
+        self.add_command_output(emulator.into_lines());
+        if stopped {
+            self.suspend(command, JobProcess::Pty(pty), watchdog, audit);
+        } else {
+            let (status, usage) = pty.finish()?;
+            self.report_finished(&watchdog, &audit, status, usage);
+        }
+        Ok(())
+    }
+
+    /// Wait for a captured command whose output is piped back
+    ///
+    /// Ctrl+C and Ctrl+\ are sent to its process group as signals, and
+    /// Ctrl+Z stops it. It is killed once past its timeout.
+    fn run_captured(
+        &mut self,
+        command: String,
+        mut process: CapturedProcess,
+        watchdog: Watchdog,
+        audit: CommandAudit,
+    ) -> io::Result<()> {
+        self.render_running()?;
+
+        let mut lines = Vec::new();
+        let stopped = loop {
+            lines.extend(process.read_lines());
+            if process.try_wait()?.is_some() {
+                break false;
+            }
+            if watchdog.expired() {
+                watchdog.kill(process.pid());
+            }
+            if !event::poll(Duration::from_millis(10))? {
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                match Self::signal_for_key(&key) {
+                    Some(libc::SIGTSTP) if Executor::signal_group(process.pid(), libc::SIGSTOP).is_ok() => break true,
+                    Some(signal) => {
+                        let _ = Executor::signal_group(process.pid(), signal);
+                    }
+                    None => {}
+                }
+            }
+        };
+
+        if stopped {
+            self.add_command_output(ansi::parse_lines(&lines));
+            self.suspend(command, JobProcess::Captured(process), watchdog, audit);
+            return Ok(());
+        }
+
+        let (rest, status, usage) = process.finish();
+        lines.extend(rest);
+        self.add_command_output(ansi::parse_lines(&lines));
+        self.report_finished(&watchdog, &audit, status, usage);
+        Ok(())
+    }
+
+    /// Run an interactive command with the terminal in normal mode
+    fn run_interactive(
+        &mut self,
+        command: &str,
+        watchdog: Watchdog,
+        mut audit: CommandAudit,
+        run: impl FnOnce(&Self, &Watchdog, &mut CommandAudit) -> io::Result<(WaitStatus, Rusage)>,
+    ) -> io::Result<()> {
+        // Disable raw mode and clear screen
+        execute!(io::stdout(), DisableBracketedPaste, DisableMouseCapture)?;
+        crossterm_terminal::disable_raw_mode()?;
+        self.renderer.clear_screen()?;
+
+        let status = run(self, &watchdog, &mut audit);
+
+        // Re-enable raw mode
+        crossterm_terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnableBracketedPaste, EnableMouseCapture)?;
+
+        match status {
+            Ok((WaitStatus::Stopped(pid), _)) => {
+                self.suspend(command.to_string(), JobProcess::Interactive(pid), watchdog, audit)
+            }
+            Ok((status, usage)) => {
+                self.report_finished(&watchdog, &audit, status, usage);
+                // Add continuation message
+                self.renderer
+                    .add_output("[Nothing to display. Press Enter to continue]".to_string());
+            }
+            Err(e) => {
+                self.renderer.add_output(format!("dshell: {}: {}", command, e));
+                self.variables.set_status(126);
+            }
+        }
+        Ok(())
+    }
+
+    /// Limits for a command about to start, in its own cgroup if possible
+    fn watchdog(&self, cmd: &ParsedCommand) -> Watchdog {
+        Watchdog::start(Executor::resource_limits(cmd, &self.config), self.cgroups.as_ref())
+    }
+
+    /// What to record about a command about to start; isolated commands
+    /// also get the paths they may reach
+    fn command_audit(&self, cmd: &ParsedCommand, mode: RunMode) -> CommandAudit {
+        let argv = std::iter::once(cmd.command.clone()).chain(cmd.args.iter().cloned()).collect();
+        let allowed_paths = Some(Executor::effective_allowed_paths(&self.permissions, &self.config));
+        CommandAudit::new(argv, mode, allowed_paths, Executor::filtered_env_names(&self.permissions, &self.variables))
+    }
+
+    /// Write to the audit log, warning the first time that fails
+    fn record(&mut self, write: impl FnOnce(&mut AuditLog) -> io::Result<()>) {
+        if let Err(e) = write(&mut self.audit)
+            && !self.audit_failed
+        {
+            self.audit_failed = true;
+            self.renderer.add_output(format!("⚠️  Could not write the audit log: {}", e));
+        }
+    }
+
+    /// Add a command's output, with secrets redacted
+    fn add_command_output(&mut self, lines: Vec<StyledLine>) {
+        let lines = self.redactor.redact(lines, &self.permissions, &self.variables);
+        self.renderer.add_styled_lines(lines);
+    }
+
+    /// Add a builtin's output, which can echo variable values, with secrets
+    /// redacted the same way
+    fn add_builtin_output(&mut self, lines: Vec<String>) {
+        self.add_command_output(renderer::plain_lines(lines));
+    }
+
+    /// Say why a foreground command stopped, if one of its limits or a
+    /// signal ended it, record it and report its timing
+    fn report_finished(&mut self, watchdog: &Watchdog, audit: &CommandAudit, status: WaitStatus, usage: Rusage) {
+        if let Some(message) = watchdog.describe(&status) {
+            self.renderer.add_output(message);
+        }
+        let timing = watchdog.timing(status, usage);
+        self.record(|log| log.record_command(audit, Some(status), timing.wall));
+        self.variables.set_status(status.code());
+        self.report_timing(timing);
+    }
+
+    /// Keep a finished command's timing, and show it if the command ran
+    /// past `report_time_secs` or `time` asked for it
+    fn report_timing(&mut self, timing: Timing) {
+        if self.timing_forced || timing.wall.as_secs() >= self.config.report_time_secs {
+            self.renderer.add_output(timing.report());
+        }
+        self.last_timing = Some(timing);
+    }
+
+    /// Run a command for `time`, which reports its timing whatever it took
+    ///
+    /// Builtins run inside dshell, so only their wall time is reported.
+    fn run_timed(&mut self, command: &str) -> io::Result<bool> {
+        let started = Instant::now();
+        self.timing_forced = true;
+        let result = self.run_command(command);
+        self.timing_forced = false;
+        if ParsedCommand::parse(command).is_some_and(|cmd| Builtins::parse(&cmd).is_some()) {
+            self.renderer.add_output(format!("⏱ {} (builtin)", timing::format_wall(started.elapsed())));
+        }
+        result
+    }
+
+    /// Keep a command stopped with Ctrl+Z as a job until `fg` or `bg` resumes it
+    fn suspend(&mut self, command: String, process: JobProcess, watchdog: Watchdog, audit: CommandAudit) {
+        let id = self.jobs.add(command.clone(), process, JobState::Stopped, watchdog, audit);
+        self.variables.set_status(128 + libc::SIGTSTP);
         self.renderer
-            .render(self.input_editor.buffer(), self.input_editor.cursor_pos())
+            .add_output(format!("⏸ Stopped: [{}] {} (use 'fg' or 'bg' to resume)", id, command));
+    }
+
+    /// The signal Ctrl+C, Ctrl+\ or Ctrl+Z sends to a foreground command
+    fn signal_for_key(key: &KeyEvent) -> Option<i32> {
+        if !key.modifiers.contains(KeyModifiers::CONTROL) {
+            return None;
+        }
+        match key.code {
+            KeyCode::Char('c') => Some(libc::SIGINT),
+            // Terminals report Ctrl+\ as Ctrl+4
+            KeyCode::Char('\\' | '4') => Some(libc::SIGQUIT),
+            KeyCode::Char('z') => Some(libc::SIGTSTP),
+            _ => None,
+        }
+    }
+
+    /// Let a program on a PTY use the whole terminal until it exits
+    ///
+    /// Its output so far is replayed, then bytes flow unchanged in both
+    /// directions. The emulator keeps tracking the output so the main screen
+    /// text still ends up in the output buffer.
+    fn passthrough(
+        &mut self,
+        pty: &mut PtyProcess,
+        emulator: &mut TerminalEmulator,
+        transcript: &[u8],
+        watchdog: &Watchdog,
+    ) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        execute!(stdout, DisableBracketedPaste, DisableMouseCapture)?;
+        self.renderer.clear_screen()?;
+
+        let mut size = crossterm_terminal::size()?;
+        let _ = pty.resize(size.0, size.1);
+        emulator.resize(size.0 as usize, size.1 as usize);
+        stdout.write_all(transcript)?;
+        stdout.flush()?;
+
+        let mut exited = false;
+        while let Some(bytes) = pty.read_available(Duration::from_millis(10)) {
+            // The real terminal answers the program's queries now
+            emulator.feed(&bytes);
+            emulator.take_responses();
+            stdout.write_all(&bytes)?;
+            stdout.flush()?;
+            if bytes.is_empty() && exited {
+                break;
+            }
+            exited = exited || pty.try_wait()?.is_some();
+            if watchdog.expired() && !exited {
+                watchdog.kill(pty.pid());
+            }
+
+            let input = pty::read_terminal_input()?;
+            if !input.is_empty() {
+                let _ = pty.write_input(&input);
+            }
+
+            let new_size = crossterm_terminal::size()?;
+            if new_size != size {
+                size = new_size;
+                let _ = pty.resize(size.0, size.1);
+                emulator.resize(size.0 as usize, size.1 as usize);
+            }
+        }
+
+        // The program may have left any mode on; start from a clean screen
+        execute!(stdout, crossterm_terminal::LeaveAlternateScreen, crossterm::cursor::Show)?;
+        execute!(stdout, EnableBracketedPaste, EnableMouseCapture)?;
+        self.renderer.clear_screen()
+    }
+
+    /// Size of the output area, which a program on a PTY gets as its screen
+    fn live_output_size() -> io::Result<(u16, u16)> {
+        let (width, height) = crossterm_terminal::size()?;
+        // Leave room for the separator and one input row
+        Ok((width.max(1), height.saturating_sub(2).max(1)))
+    }
+
+    /// Draw the screen while a program runs on a PTY
+    fn render_running(&mut self) -> io::Result<()> {
+        self.renderer.render(&InputView {
+            prompt: RUNNING_PROMPT,
+            buffer: "",
+            cursor_pos: 0,
+            highlights: &[],
+            suggestion: None,
+            menu: None,
+        })
+    }
+
+    fn render(&mut self) -> io::Result<()> {
+        let prompt = match &self.pending {
+            Some(pending) => pending.prompt().to_string(),
+            None => self.prompt(),
+        };
+
+        // No suggestions while answering a confirmation prompt
+        let cwd = std::env::current_dir().ok();
+        let suggestion = match self.pending {
+            Some(_) => None,
+            None => self.input_editor.suggestion(cwd.as_deref()),
+        };
+
+        // Answers to confirmation prompts aren't commands, so don't color them
+        let highlights = match self.pending {
+            Some(_) => Vec::new(),
+            None => Highlighter::new(&self.permissions, &self.variables, &self.config)
+                .with_definitions(&self.aliases, &self.functions)
+                .highlight(self.input_editor.buffer()),
+        };
+
+        self.renderer.render(&InputView {
+            prompt: &prompt,
+            buffer: self.input_editor.buffer(),
+            cursor_pos: self.input_editor.cursor_pos(),
+            highlights: &highlights,
+            suggestion,
+            menu: self.input_editor.completion_menu(),
+        })
     }
 }
 
 fn main() -> io::Result<()> {
+    // `dshell audit verify` checks the audit log instead of starting a session
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args == ["audit", "verify"] {
+        let verification = AuditLog::open(&Config::load().audit).verify();
+        for line in verification.lines {
+            println!("{}", line);
+        }
+        std::process::exit(if verification.intact { 0 } else { 1 });
+    }
+
+    // `--norc` starts a session without running ~/.config/dshell/rc.dsh
+    let rc = if args.iter().any(|arg| arg == "--norc") { None } else { Config::rc_path() };
+
     let mut app = App::new();
-    app.run().map_err(|e| {
+    app.run(rc).map_err(|e| {
         eprintln!("Error: {}", e);
         e
     })
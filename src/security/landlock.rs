@@ -1,15 +1,19 @@
-/// Landlock-based filesystem isolation
-///
-/// This module provides filesystem access restriction using Linux Landlock LSM.
-/// Requires Linux kernel 5.13 or later.
+//! Landlock-based filesystem isolation
+//!
+//! This module provides filesystem access restriction using Linux Landlock LSM.
+//! Requires Linux kernel 5.13 or later.
 
 use landlock::*;
+use serde::{Deserialize, Serialize};
 use std::env;
 use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 /// Isolation status indicating what level of protection is active
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum IsolationStatus {
     /// Filesystem access is fully restricted by kernel
     FullyEnforced,
@@ -38,15 +42,97 @@ impl IsolationStatus {
     }
 }
 
+/// A close-on-exec pipe through which an isolated child tells dshell what
+/// isolation it got
+///
+/// The child writes one byte after restricting itself and before it execs
+/// the command; dshell reads it once the child has exec'd or exited.
+pub struct IsolationReport {
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+impl IsolationReport {
+    pub fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        Ok(IsolationReport { read, write })
+    }
+
+    /// The end the child writes to
+    pub fn sender(&self) -> IsolationSender {
+        IsolationSender(self.write.as_raw_fd())
+    }
+
+    /// The status the child reported, if it got that far
+    pub fn receive(&self) -> Option<IsolationStatus> {
+        let mut code = 0u8;
+        if unsafe { libc::read(self.read.as_raw_fd(), (&raw mut code).cast(), 1) } != 1 {
+            return None;
+        }
+        [
+            IsolationStatus::FullyEnforced,
+            IsolationStatus::PartiallyEnforced,
+            IsolationStatus::NotEnforced,
+            IsolationStatus::NotAvailable,
+        ]
+        .get(code as usize)
+        .copied()
+    }
+}
+
+/// The write end of an `IsolationReport`, usable in a forked child
+#[derive(Debug, Clone, Copy)]
+pub struct IsolationSender(RawFd);
+
+impl IsolationSender {
+    /// Report the status; runs between fork and exec, so only a system call
+    pub fn send(self, status: IsolationStatus) {
+        let code = status as u8;
+        unsafe { libc::write(self.0, (&raw const code).cast(), 1) };
+    }
+}
+
+/// System paths that isolated commands can read and execute but not write
+const READONLY_SYSTEM_PATHS: &[&str] = &[
+    "/usr",                 // System binaries and libraries
+    "/bin",                 // Essential binaries
+    "/lib",                 // Essential libraries
+    "/lib64",               // 64-bit libraries
+    "/etc",                 // System configuration (needed for DNS, hosts, etc.)
+    "/dev",                 // Device files
+    "/proc",                // Process information
+    "/sys",                 // System information
+    "/run",                 // Runtime data (needed for systemd DNS resolution)
+];
+
+/// System paths that isolated commands get full access to (temporary files)
+const READWRITE_SYSTEM_PATHS: &[&str] = &[
+    "/tmp",                 // Temporary files
+    "/var/tmp",             // Temporary files
+];
+
 /// Landlock filesystem isolation manager
 pub struct LandlockIsolation {
     work_dir: PathBuf,
+    /// A directory no rule may reach, even through the working directory
+    /// or a system path that contains it
+    excluded: Option<PathBuf>,
 }
 
 impl LandlockIsolation {
     /// Create a new Landlock isolation manager for the given working directory
     pub fn new(work_dir: PathBuf) -> Self {
-        Self { work_dir }
+        Self { work_dir, excluded: None }
+    }
+
+    /// Keep `dir` out of reach of the isolated process, wherever it is
+    pub fn excluding(mut self, dir: Option<PathBuf>) -> Self {
+        self.excluded = dir;
+        self
     }
 
     /// Create isolation manager for current directory
@@ -58,8 +144,11 @@ impl LandlockIsolation {
 
     /// Check if Landlock is available on this system
     pub fn is_available() -> bool {
+        // Kernel support can't change while we run, so probe only once
+        static AVAILABLE: OnceLock<bool> = OnceLock::new();
+
         // Check if we can get any supported ABI version
-        Self::get_abi_version().is_some()
+        *AVAILABLE.get_or_init(|| Self::get_abi_version().is_some())
     }
 
     /// Get the highest supported Landlock ABI version
@@ -82,8 +171,8 @@ impl LandlockIsolation {
     /// Apply filesystem restrictions to current process
     ///
     /// This will restrict the process (and all its children) to only access
-    /// files within the working directory and any additional allowed paths.
-    /// Cannot be undone once applied.
+    /// files within the working directory and any additional allowed paths,
+    /// minus the excluded directory. Cannot be undone once applied.
     ///
     /// Returns the isolation status indicating if restrictions were applied.
     pub fn restrict_filesystem(&self, additional_allowed_paths: &[String]) -> io::Result<IsolationStatus> {
@@ -100,8 +189,7 @@ impl LandlockIsolation {
         let mut ruleset = Ruleset::default()
             .handle_access(fs_access)
             .map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::Other,
+                io::Error::other(
                     format!("Failed to create ruleset: {}", e),
                 )
             })?
@@ -115,104 +203,43 @@ impl LandlockIsolation {
 
         // Add rules allowing access to necessary directories
         use std::fs::File;
-        use std::path::Path;
 
         // Define read-only access for system paths (execute and read, no write)
         use AccessFs as A;
         let ro_access = A::Execute | A::ReadFile | A::ReadDir;
 
-        // System paths (read-only + execute)
-        let readonly_paths = vec![
-            Path::new("/usr"),                 // System binaries and libraries
-            Path::new("/bin"),                 // Essential binaries
-            Path::new("/lib"),                 // Essential libraries
-            Path::new("/lib64"),               // 64-bit libraries
-            Path::new("/etc"),                 // System configuration (needed for DNS, hosts, etc.)
-            Path::new("/dev"),                 // Device files
-            Path::new("/proc"),                // Process information
-            Path::new("/sys"),                 // System information
-            Path::new("/run"),                 // Runtime data (needed for systemd DNS resolution)
-        ];
-
-        // Paths that need write access for temporary files
-        let readwrite_system_paths = vec![
-            Path::new("/tmp"),                 // Temporary files
-            Path::new("/var/tmp"),             // Temporary files
-        ];
-
-        // Add read-only system paths
-        for path in readonly_paths {
-            if path.exists() {
-                if let Ok(dir_fd) = File::open(path) {
-                    ruleset = ruleset
-                        .add_rule(PathBeneath::new(dir_fd, ro_access))
-                        .map_err(|e| {
-                            io::Error::new(
-                                io::ErrorKind::Other,
-                                format!("Failed to add read-only rule for {}: {}", path.display(), e),
-                            )
-                        })?;
-                }
-            }
-        }
-
-        // Add read-write system paths (for temp files)
-        for path in readwrite_system_paths {
-            if path.exists() {
-                if let Ok(dir_fd) = File::open(path) {
+        // System paths get read-only + execute; temp dirs, allowed paths (from
+        // config and allowpath command) and the working directory get full access
+        let rules = READONLY_SYSTEM_PATHS
+            .iter()
+            .map(|path| (path.to_string(), ro_access))
+            .chain(READWRITE_SYSTEM_PATHS.iter().map(|path| (path.to_string(), fs_access)))
+            .chain(additional_allowed_paths.iter().map(|path| (path.clone(), fs_access)))
+            .chain(std::iter::once((self.work_dir.display().to_string(), fs_access)));
+
+        for (root, access) in rules {
+            for path_str in self.narrow(&root) {
+                let expanded_path = Self::expand_tilde(&path_str);
+                let path = Path::new(&expanded_path);
+
+                if path.exists()
+                    && let Ok(fd) = File::open(path)
+                {
                     ruleset = ruleset
-                        .add_rule(PathBeneath::new(dir_fd, fs_access))
+                        .add_rule(PathBeneath::new(fd, Self::access_for(path, access, abi)))
                         .map_err(|e| {
-                            io::Error::new(
-                                io::ErrorKind::Other,
-                                format!("Failed to add read-write rule for {}: {}", path.display(), e),
+                            io::Error::other(
+                                format!("Failed to add path rule for {}: {}", path.display(), e),
                             )
                         })?;
                 }
             }
         }
 
-        // Add allowed paths (from config and allowpath command)
-        for path_str in additional_allowed_paths {
-            let expanded_path = Self::expand_tilde(path_str);
-            let path = Path::new(&expanded_path);
-
-            if path.exists() {
-                if let Ok(fd) = File::open(path) {
-                    ruleset = ruleset
-                        .add_rule(PathBeneath::new(fd, fs_access))
-                        .map_err(|e| {
-                            io::Error::new(
-                                io::ErrorKind::Other,
-                                format!("Failed to add allowed path rule for {}: {}", path.display(), e),
-                            )
-                        })?;
-                }
-            }
-        }
-
-        // Add full access to working directory (read, write, execute)
-        let dir_fd = File::open(&self.work_dir).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("Failed to open working directory: {}", e),
-            )
-        })?;
-
-        ruleset = ruleset
-            .add_rule(PathBeneath::new(dir_fd, fs_access))
-            .map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Failed to add path rule: {}", e),
-                )
-            })?;
-
         // Apply the restrictions to the current thread
         // After this call, this process can only access files in work_dir
         let status = ruleset.restrict_self().map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
+            io::Error::other(
                 format!("Failed to restrict process: {}", e),
             )
         })?;
@@ -227,6 +254,147 @@ impl LandlockIsolation {
         Ok(isolation_status)
     }
 
+    /// The rights a rule for `path` can carry: directory rights such as
+    /// `ReadDir` or `MakeReg` can't apply to a file, and asking for them
+    /// would leave the ruleset only partially enforced
+    fn access_for(path: &Path, access: BitFlags<AccessFs>, abi: ABI) -> BitFlags<AccessFs> {
+        if path.is_dir() { access } else { access & AccessFs::from_file(abi) }
+    }
+
+    /// Check whether an isolated command would be able to reach `path`
+    ///
+    /// Mirrors the rules installed by `restrict_filesystem`: the working
+    /// directory, the system paths and `additional_allowed_paths` are
+    /// reachable, and anything under `denied_paths` or the excluded
+    /// directory is not. When Landlock is
+    /// unavailable nothing is restricted, so only denied paths are rejected.
+    pub fn can_access(&self, path: &Path, additional_allowed_paths: &[String], denied_paths: &[String]) -> bool {
+        let path = Self::normalize(path);
+
+        let is_under = |root: &str| path.starts_with(Self::normalize(Path::new(&Self::expand_tilde(root))));
+
+        if denied_paths.iter().any(|denied| is_under(denied)) {
+            return false;
+        }
+
+        if !Self::is_available() {
+            return true;
+        }
+        if self.excluded.as_ref().is_some_and(|excluded| path.starts_with(Self::normalize(excluded))) {
+            return false;
+        }
+
+        path.starts_with(Self::normalize(&self.work_dir))
+            || READONLY_SYSTEM_PATHS.iter().any(|root| is_under(root))
+            || READWRITE_SYSTEM_PATHS.iter().any(|root| is_under(root))
+            || additional_allowed_paths.iter().any(|root| is_under(root))
+    }
+
+    /// Check whether `path` is a parent directory of an allowed path
+    ///
+    /// Such directories can't be listed from inside the sandbox, but they are
+    /// on the way to somewhere that can (e.g. `~` on the way to `~/.cargo`).
+    pub fn leads_to_access(&self, path: &Path, additional_allowed_paths: &[String]) -> bool {
+        let path = Self::normalize(path);
+
+        std::iter::once(Self::normalize(&self.work_dir))
+            .chain(
+                additional_allowed_paths
+                    .iter()
+                    .map(|root| Self::normalize(Path::new(&Self::expand_tilde(root)))),
+            )
+            .any(|root| root.starts_with(&path))
+    }
+
+    /// Resolve symlinks where possible so prefix checks match what the kernel sees
+    ///
+    /// Paths that don't exist yet are cleaned up lexically instead, so that
+    /// `work_dir/../elsewhere` isn't mistaken for something inside `work_dir`.
+    fn normalize(path: &Path) -> PathBuf {
+        use std::path::Component;
+
+        if let Ok(canonical) = path.canonicalize() {
+            return canonical;
+        }
+
+        let mut normalized = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                other => normalized.push(other),
+            }
+        }
+        normalized
+    }
+
+    /// Check whether an allowed path (which may start with `~`) gives
+    /// access to `path`
+    pub fn covers(root: &str, path: &Path) -> bool {
+        Self::normalize(path).starts_with(Self::normalize(Path::new(&Self::expand_tilde(root))))
+    }
+
+    /// Allowed paths that reach what `root` does, except `excluded`
+    ///
+    /// Landlock rules only add access, so a directory can't be allowed with
+    /// a subdirectory carved out. Instead, each directory on the way from
+    /// `root` to `excluded` is replaced by its entries, minus the one that
+    /// leads on; files among them get file rights only. Symlinks are
+    /// skipped, since a rule for one would cover its target, which may be
+    /// the excluded directory or one above it. The entries are listed when
+    /// the command starts, so anything created in those directories
+    /// afterwards is denied to it, and it can't create new entries there
+    /// itself. Nothing is left when `root` is `excluded` or inside it.
+    pub fn paths_excluding(root: &str, excluded: &Path) -> Vec<String> {
+        let root_path = Self::normalize(Path::new(&Self::expand_tilde(root)));
+        let excluded = Self::normalize(excluded);
+        if root_path.starts_with(&excluded) {
+            return Vec::new();
+        }
+        let Ok(rest) = excluded.strip_prefix(&root_path) else {
+            return vec![root.to_string()];
+        };
+
+        let mut paths = Vec::new();
+        let mut dir = root_path.clone();
+        for component in rest.components() {
+            let next = dir.join(component);
+            if let Ok(entries) = std::fs::read_dir(&dir) {
+                let siblings = entries
+                    .flatten()
+                    .filter(|entry| entry.file_type().is_ok_and(|file_type| !file_type.is_symlink()))
+                    .map(|entry| entry.path())
+                    .filter(|path| *path != next);
+                paths.extend(siblings.map(|path| path.display().to_string()));
+            }
+            dir = next;
+        }
+        paths.sort();
+        paths
+    }
+
+    /// The paths a rule for `root` is narrowed to, so that it doesn't reach
+    /// the excluded directory
+    fn narrow(&self, root: &str) -> Vec<String> {
+        match &self.excluded {
+            Some(excluded) => Self::paths_excluding(root, excluded),
+            None => vec![root.to_string()],
+        }
+    }
+
+    /// Whether the excluded directory is beneath the working directory, so
+    /// the working directory itself only keeps the rights of its entries
+    /// and commands can't create or remove files directly in it
+    pub fn narrows_work_dir(&self) -> bool {
+        self.excluded.as_ref().is_some_and(|excluded| {
+            let excluded = Self::normalize(excluded);
+            let work_dir = Self::normalize(&self.work_dir);
+            excluded != work_dir && excluded.starts_with(&work_dir)
+        })
+    }
+
     /// Get the working directory that will be accessible after restriction
     #[allow(dead_code)]
     pub fn work_dir(&self) -> &Path {
@@ -235,10 +403,10 @@ impl LandlockIsolation {
 
     /// Expand tilde (~) in path to home directory
     fn expand_tilde(path: &str) -> String {
-        if path.starts_with("~/") || path == "~" {
-            if let Ok(home) = env::var("HOME") {
-                return path.replacen("~", &home, 1);
-            }
+        if (path.starts_with("~/") || path == "~")
+            && let Ok(home) = env::var("HOME")
+        {
+            return path.replacen("~", &home, 1);
         }
         path.to_string()
     }
@@ -269,6 +437,155 @@ mod tests {
         assert_eq!(isolation.work_dir(), temp_dir.as_path());
     }
 
+    #[test]
+    fn test_paths_excluding() {
+        let root = std::env::temp_dir().join(format!("dshell_exclude_test_{}", std::process::id()));
+        fs::create_dir_all(root.join("state/dshell")).unwrap();
+        fs::create_dir_all(root.join("state/other")).unwrap();
+        fs::create_dir_all(root.join("projects")).unwrap();
+        fs::write(root.join("notes.txt"), "").unwrap();
+        let root = root.canonicalize().unwrap();
+        let log_dir = root.join("state/dshell");
+        let path = |rest: &str| root.join(rest).display().to_string();
+
+        // A directory containing the excluded one is replaced by the rest
+        let paths = LandlockIsolation::paths_excluding(&path(""), &log_dir);
+        assert_eq!(paths, vec![path("notes.txt"), path("projects"), path("state/other")]);
+
+        // Unrelated paths are kept as written; the excluded one and paths
+        // inside it are dropped
+        assert_eq!(LandlockIsolation::paths_excluding("~/.cargo", &log_dir), vec!["~/.cargo"]);
+        assert!(LandlockIsolation::paths_excluding(&path("state/dshell"), &log_dir).is_empty());
+        assert!(LandlockIsolation::paths_excluding(&path("state/dshell/sub"), &log_dir).is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// Run a shell script under `isolation`, returning the isolation it got
+    /// and whether the script succeeded
+    fn run_restricted(isolation: LandlockIsolation, script: &str) -> (Option<IsolationStatus>, bool) {
+        use std::os::unix::process::CommandExt;
+        use std::process::{Command, Stdio};
+
+        let report = IsolationReport::new().unwrap();
+        let sender = report.sender();
+        let mut command = Command::new("sh");
+        command.args(["-c", script]).stdout(Stdio::null()).stderr(Stdio::null());
+        unsafe {
+            command.pre_exec(move || {
+                sender.send(isolation.restrict_filesystem(&[])?);
+                Ok(())
+            });
+        }
+        let success = command.status().unwrap().success();
+        (report.receive(), success)
+    }
+
+    fn can_read(isolation: LandlockIsolation, file: &Path) -> bool {
+        run_restricted(isolation, &format!("cat '{}'", file.display())).1
+    }
+
+    #[test]
+    fn test_excluded_dir_unreachable_through_work_dir() {
+        let root = std::env::temp_dir().join(format!("dshell_exclude_work_dir_{}", std::process::id()));
+        fs::create_dir_all(root.join("state/dshell")).unwrap();
+        fs::write(root.join("state/dshell/audit.jsonl"), "").unwrap();
+        fs::write(root.join("notes.txt"), "").unwrap();
+        let root = root.canonicalize().unwrap();
+        let log = root.join("state/dshell/audit.jsonl");
+        let notes = root.join("notes.txt");
+        let isolation = || LandlockIsolation::new(root.clone()).excluding(Some(root.join("state/dshell")));
+
+        if LandlockIsolation::is_available() {
+            assert!(!isolation().can_access(&log, &[], &[]));
+            assert!(isolation().can_access(&notes, &[], &[]));
+            assert!(!can_read(isolation(), &log));
+            assert!(can_read(isolation(), &notes));
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_file_rules_get_file_rights() {
+        let root = std::env::temp_dir().join(format!("dshell_file_rights_{}", std::process::id()));
+        fs::create_dir_all(root.join("state/dshell")).unwrap();
+        fs::write(root.join("notes.txt"), "").unwrap();
+        let root = root.canonicalize().unwrap();
+
+        let all = AccessFs::from_all(ABI::V2);
+        assert_eq!(LandlockIsolation::access_for(&root.join("notes.txt"), all, ABI::V2), AccessFs::from_file(ABI::V2));
+        assert_eq!(LandlockIsolation::access_for(&root.join("state"), all, ABI::V2), all);
+
+        // The working directory is narrowed to notes.txt, and the rule for
+        // the file doesn't cost full enforcement
+        if LandlockIsolation::is_available() {
+            let isolation = LandlockIsolation::new(root.clone()).excluding(Some(root.join("state/dshell")));
+            let (status, _) = run_restricted(isolation, "true");
+            assert_eq!(status, Some(IsolationStatus::FullyEnforced));
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_narrowed_dirs_deny_new_entries() {
+        let root = std::env::temp_dir().join(format!("dshell_new_entries_{}", std::process::id()));
+        fs::create_dir_all(root.join("state/dshell")).unwrap();
+        fs::create_dir_all(root.join("projects")).unwrap();
+        let root = root.canonicalize().unwrap();
+        let excluded = root.join("state/dshell");
+
+        // Entries are listed when the rules are built
+        let paths = LandlockIsolation::paths_excluding(&root.display().to_string(), &excluded);
+        fs::write(root.join("later.txt"), "").unwrap();
+        assert!(!paths.contains(&root.join("later.txt").display().to_string()));
+        assert!(LandlockIsolation::new(root.clone()).excluding(Some(excluded.clone())).narrows_work_dir());
+        assert!(!LandlockIsolation::new(root.join("projects")).excluding(Some(excluded.clone())).narrows_work_dir());
+
+        if LandlockIsolation::is_available() {
+            let isolation = || LandlockIsolation::new(root.clone()).excluding(Some(excluded.clone()));
+            // Entries that existed at startup stay usable, new ones can't be
+            // created next to them
+            let projects = root.join("projects");
+            assert!(run_restricted(isolation(), &format!("touch '{}/new.txt'", projects.display())).1);
+            assert!(!run_restricted(isolation(), &format!("touch '{}/new.txt'", root.display())).1);
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_symlinks_skipped_when_narrowing() {
+        let root = std::env::temp_dir().join(format!("dshell_exclude_symlink_{}", std::process::id()));
+        fs::create_dir_all(root.join("state/dshell")).unwrap();
+        fs::write(root.join("state/dshell/audit.jsonl"), "").unwrap();
+        fs::write(root.join("notes.txt"), "").unwrap();
+        let root = root.canonicalize().unwrap();
+        std::os::unix::fs::symlink(root.join("state"), root.join("link")).unwrap();
+        let excluded = root.join("state/dshell");
+
+        // A rule for the link would cover the state dir it points to
+        let paths = LandlockIsolation::paths_excluding(&root.display().to_string(), &excluded);
+        assert_eq!(paths, vec![root.join("notes.txt").display().to_string()]);
+
+        if LandlockIsolation::is_available() {
+            let isolation = || LandlockIsolation::new(root.clone()).excluding(Some(excluded.clone()));
+            assert!(!can_read(isolation(), &root.join("link/dshell/audit.jsonl")));
+            assert!(can_read(isolation(), &root.join("notes.txt")));
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_isolation_report() {
+        let report = IsolationReport::new().unwrap();
+        assert_eq!(report.receive(), None);
+        report.sender().send(IsolationStatus::PartiallyEnforced);
+        assert_eq!(report.receive(), Some(IsolationStatus::PartiallyEnforced));
+    }
+
     #[test]
     #[ignore] // Only run manually as it actually restricts the process
     fn test_filesystem_restriction() {
@@ -1,7 +1,9 @@
-/// Security module - Permission management and access control
+//! Security module - Permission management and access control
 
 pub mod permissions;
 pub mod landlock;
+pub mod redact;
 
 pub use permissions::{Permission, PermissionManager};
-pub use landlock::{LandlockIsolation, IsolationStatus};
+pub use landlock::{LandlockIsolation, IsolationReport, IsolationSender, IsolationStatus};
+pub use redact::Redactor;
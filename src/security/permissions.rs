@@ -1,5 +1,6 @@
-/// Permission management for secure shell
+//! Permission management for secure shell
 
+use super::redact;
 use std::collections::HashSet;
 use std::env;
 
@@ -110,6 +111,27 @@ impl PermissionManager {
         result
     }
 
+    /// Whether an inherited environment variable holds a secret: it was
+    /// denied with `deny VAR`, or its name suggests a credential
+    ///
+    /// Variables only hidden by the default policy, like `PWD` or
+    /// `HOSTNAME`, are not secrets; their values show up in ordinary output.
+    pub fn is_secret_env_var(&self, var_name: &str) -> bool {
+        self.denied_env_vars.contains(var_name) || redact::is_secret_name(var_name)
+    }
+
+    /// Check whether `text` contains the value of a secret environment
+    /// variable (see `is_secret_env_var`)
+    ///
+    /// Very short values are ignored; they would match almost anything.
+    pub fn contains_secret(&self, text: &str) -> bool {
+        env::vars().any(|(key, value)| {
+            value.len() >= redact::MIN_SECRET_LEN
+                && self.is_secret_env_var(&key)
+                && text.contains(&value)
+        })
+    }
+
     /// Get list of allowed environment variable names
     pub fn list_allowed_env_vars(&self) -> Vec<String> {
         let mut vars: Vec<String> = self.allowed_env_vars.iter().cloned().collect();
@@ -206,10 +228,10 @@ impl PermissionManager {
 
     /// Expand tilde (~) in path to home directory
     fn expand_tilde(path: &str) -> String {
-        if path.starts_with("~/") || path == "~" {
-            if let Ok(home) = env::var("HOME") {
-                return path.replacen("~", &home, 1);
-            }
+        if (path.starts_with("~/") || path == "~")
+            && let Ok(home) = env::var("HOME")
+        {
+            return path.replacen("~", &home, 1);
         }
         path.to_string()
     }
@@ -0,0 +1,207 @@
+//! Aliases and the `alias` and `unalias` builtins
+//!
+//! An alias replaces the first word of a command before it is classified,
+//! so an alias for `vim` runs exactly like `vim` would, isolation included.
+//! Aliases expand recursively, but an alias is never expanded again inside
+//! its own expansion: `alias ls='ls -F'` runs the real `ls`. Quoting or
+//! escaping the first word (`\ls`) skips alias expansion.
+
+use super::parser::{self, TokenKind, WordPartKind};
+use std::collections::BTreeMap;
+
+/// Arguments of the `alias` and `unalias` builtins
+#[derive(Debug, Clone, PartialEq)]
+pub enum AliasCommand {
+    /// `alias`: list all aliases
+    List,
+    /// `alias NAME[=VALUE]...`: define, or show when there's no value
+    Alias(Vec<(String, Option<String>)>),
+    /// `unalias NAME...`
+    Unalias(Vec<String>),
+    /// `unalias -a`
+    UnaliasAll,
+}
+
+impl AliasCommand {
+    /// Parse `alias` or `unalias` and their arguments
+    pub fn parse(command: &str, args: &[String]) -> Result<Self, String> {
+        match (command, args) {
+            ("alias", []) => Ok(AliasCommand::List),
+            ("alias", args) => {
+                let mut aliases = Vec::new();
+                for arg in args {
+                    let (name, value) = match arg.split_once('=') {
+                        Some((name, value)) => (name, Some(value.to_string())),
+                        None => (arg.as_str(), None),
+                    };
+                    if !is_valid_name(name) {
+                        return Err(format!("alias: {}: not a valid alias name", name));
+                    }
+                    aliases.push((name.to_string(), value));
+                }
+                Ok(AliasCommand::Alias(aliases))
+            }
+            ("unalias", []) => Err("unalias: usage: unalias [-a] NAME...".to_string()),
+            ("unalias", [flag]) if flag == "-a" => Ok(AliasCommand::UnaliasAll),
+            ("unalias", names) => Ok(AliasCommand::Unalias(names.to_vec())),
+            _ => Err(format!("{}: not an alias builtin", command)),
+        }
+    }
+}
+
+/// Whether `name` can be used as an alias or function name: a command
+/// word without quotes, `$`, `=`, `/` or shell operators
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '+' | ':' | '@' | '%' | ',' | '!'))
+}
+
+/// The session's aliases
+#[derive(Debug, Default)]
+pub struct Aliases {
+    aliases: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.aliases.keys().map(String::as_str)
+    }
+
+    /// Run `alias` or `unalias`, returning lines to show
+    pub fn run(&mut self, command: AliasCommand) -> Result<Vec<String>, String> {
+        let mut lines = Vec::new();
+        match command {
+            AliasCommand::List => lines.extend(self.aliases.iter().map(|(name, value)| Self::format(name, value))),
+            AliasCommand::Alias(aliases) => {
+                for (name, value) in aliases {
+                    match value {
+                        Some(value) => {
+                            self.aliases.insert(name, value);
+                        }
+                        None => match self.aliases.get(&name) {
+                            Some(value) => lines.push(Self::format(&name, value)),
+                            None => return Err(format!("alias: {}: not found", name)),
+                        },
+                    }
+                }
+            }
+            AliasCommand::Unalias(names) => {
+                for name in names {
+                    if self.aliases.remove(&name).is_none() {
+                        return Err(format!("unalias: {}: not found", name));
+                    }
+                }
+            }
+            AliasCommand::UnaliasAll => self.aliases.clear(),
+        }
+        Ok(lines)
+    }
+
+    /// A line that defines the alias again, as `alias` lists it
+    fn format(name: &str, value: &str) -> String {
+        format!("alias {}={}", name, parser::quote(value))
+    }
+
+    /// Expand an alias at the start of `command`
+    ///
+    /// Returns `None` when the first word isn't an alias. An alias may
+    /// stand for several commands, which are returned separated by
+    /// newlines, each with its own first word expanded. Words that weren't
+    /// expanded because they would repeat an alias are escaped, so
+    /// expanding the result again changes nothing.
+    pub fn expand(&self, command: &str) -> Option<String> {
+        self.alias_at(command)?;
+        Some(self.expand_command(command, &mut Vec::new()))
+    }
+
+    /// The alias named by the first word of `command`, with the rest of the
+    /// command; only an unquoted, unescaped word is looked up
+    fn alias_at<'c>(&self, command: &'c str) -> Option<(&str, &str, &'c str)> {
+        let first = parser::tokenize(command).into_iter().next()?;
+        let plain = first.kind == TokenKind::Word
+            && first.parts.iter().all(|part| part.kind == WordPartKind::Plain);
+        if !plain {
+            return None;
+        }
+        let (name, value) = self.aliases.get_key_value(&first.value)?;
+        Some((name, value, &command[first.end..]))
+    }
+
+    fn expand_command(&self, command: &str, expanding: &mut Vec<String>) -> String {
+        let Some((name, value, rest)) = self.alias_at(command) else {
+            return command.to_string();
+        };
+        if expanding.iter().any(|expanded| expanded == name) {
+            return format!("\\{}", command.trim_start());
+        }
+
+        expanding.push(name.to_string());
+        let replaced = format!("{}{}", value, rest);
+        let commands: Vec<String> = parser::split_commands(&replaced)
+            .into_iter()
+            .map(|command| self.expand_command(command, expanding))
+            .collect();
+        expanding.pop();
+        commands.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases(definitions: &[(&str, &str)]) -> Aliases {
+        let mut aliases = Aliases::new();
+        let definitions = definitions.iter().map(|(name, value)| (name.to_string(), Some(value.to_string()))).collect();
+        aliases.run(AliasCommand::Alias(definitions)).unwrap();
+        aliases
+    }
+
+    #[test]
+    fn test_parse_alias_commands() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(AliasCommand::parse("alias", &[]), Ok(AliasCommand::List));
+        assert_eq!(
+            AliasCommand::parse("alias", &args(&["ll=ls -l", "gs"])),
+            Ok(AliasCommand::Alias(vec![("ll".to_string(), Some("ls -l".to_string())), ("gs".to_string(), None)]))
+        );
+        assert!(AliasCommand::parse("alias", &args(&["a b=c"])).is_err());
+        assert!(AliasCommand::parse("alias", &args(&["$x=y"])).is_err());
+        assert_eq!(AliasCommand::parse("unalias", &args(&["-a"])), Ok(AliasCommand::UnaliasAll));
+        assert!(AliasCommand::parse("unalias", &[]).is_err());
+    }
+
+    #[test]
+    fn test_expand() {
+        let aliases = aliases(&[("ll", "ls -l"), ("ls", "ls -F"), ("v", "vim"), ("up", "cd ..; ll")]);
+        assert_eq!(aliases.expand("v notes.txt"), Some("vim notes.txt".to_string()));
+        assert_eq!(aliases.expand("ll src"), Some("\\ls -F -l src".to_string()));
+        assert_eq!(aliases.expand("up"), Some("cd ..\n\\ls -F -l".to_string()));
+        assert_eq!(aliases.expand("vim"), None);
+        assert_eq!(aliases.expand("'v' x"), None);
+        assert_eq!(aliases.expand("\\v x"), None);
+        assert_eq!(aliases.expand("echo v"), None);
+
+        // Expanding the result again changes nothing
+        for expanded in parser::split_commands("cd ..\n\\ls -F -l") {
+            assert_eq!(aliases.expand(expanded), None);
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let mut aliases = aliases(&[("ll", "ls -l"), ("gs", "git status --short")]);
+        assert_eq!(aliases.run(AliasCommand::List).unwrap(), vec!["alias gs='git status --short'", "alias ll='ls -l'"]);
+        assert_eq!(aliases.run(AliasCommand::Alias(vec![("ll".to_string(), None)])).unwrap(), vec!["alias ll='ls -l'"]);
+        assert!(aliases.run(AliasCommand::Unalias(vec!["nope".to_string()])).is_err());
+        aliases.run(AliasCommand::Unalias(vec!["ll".to_string()])).unwrap();
+        assert_eq!(aliases.names().collect::<Vec<_>>(), vec!["gs"]);
+        aliases.run(AliasCommand::UnaliasAll).unwrap();
+        assert_eq!(aliases.names().count(), 0);
+    }
+}
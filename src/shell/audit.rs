@@ -0,0 +1,778 @@
+//! Audit log of launched commands and permission changes
+//!
+//! Each entry is a JSON line in `audit.jsonl` in the XDG state directory:
+//! when and where it happened, and either a command (argv, how it ran, the
+//! paths and env var names it got, its isolation, exit status and
+//! duration) or a change to what commands may access. Env var values are
+//! never written. The log is rotated to `audit.jsonl.1`, `.2`, ... once it
+//! grows past a size limit, and the `audit` builtin shows recent entries.
+//!
+//! Entries are hash-chained: each has a sequence number, the SHA-256 of the
+//! previous entry and its own hash, and `audit.head` holds the last sequence
+//! number and hash. `audit verify` (or `dshell audit verify`) walks the
+//! chain, so edited, removed or reordered entries and a truncated log show
+//! up. The head is signed with HMAC-SHA256 using a random key kept in
+//! `audit.key`, so a rebuilt chain is caught too unless whoever rebuilt it
+//! could read the key. Sandboxed commands can't: the state directory is kept
+//! out of every Landlock rule. Processes running as the user outside dshell
+//! (or every command, without Landlock) can read it, and verification can't
+//! tell their edits apart.
+
+use super::executor::WaitStatus;
+use crate::config::{AuditConfig, Config};
+use crate::security::IsolationStatus;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Entries `audit` shows without `-n`
+const DEFAULT_COUNT: usize = 20;
+
+/// What the first entry of a chain refers to as the previous hash
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// How a command was run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunMode {
+    /// Output piped back to dshell
+    Captured,
+    /// Captured on a pseudo-terminal
+    Pty,
+    /// With the terminal
+    Interactive,
+    /// As a background job
+    Background,
+}
+
+impl RunMode {
+    fn name(&self) -> &'static str {
+        match self {
+            RunMode::Captured => "captured",
+            RunMode::Pty => "pty",
+            RunMode::Interactive => "interactive",
+            RunMode::Background => "background",
+        }
+    }
+}
+
+/// What dshell knew about a command when it launched it, kept until the
+/// command finishes
+#[derive(Debug, Clone)]
+pub struct CommandAudit {
+    started: SystemTime,
+    cwd: String,
+    argv: Vec<String>,
+    mode: RunMode,
+    /// Paths outside the working directory an isolated command may reach
+    allowed_paths: Option<Vec<String>>,
+    env: Vec<String>,
+    /// What the isolated child reported after restricting itself
+    pub isolation: Option<IsolationStatus>,
+}
+
+impl CommandAudit {
+    pub fn new(argv: Vec<String>, mode: RunMode, allowed_paths: Option<Vec<String>>, env: Vec<String>) -> Self {
+        CommandAudit {
+            started: SystemTime::now(),
+            cwd: current_dir(),
+            argv,
+            mode,
+            allowed_paths,
+            env,
+            isolation: None,
+        }
+    }
+}
+
+/// One line of the log, followed by its `hash`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Position in the chain, from 1
+    #[serde(default)]
+    pub seq: u64,
+    /// UTC time, RFC 3339
+    pub ts: String,
+    pub cwd: String,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    /// Hash of the previous entry
+    #[serde(default)]
+    pub prev: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    Command {
+        argv: Vec<String>,
+        mode: RunMode,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        allowed_paths: Option<Vec<String>>,
+        /// Names of the env vars the command got
+        env: Vec<String>,
+        isolation: Option<IsolationStatus>,
+        /// Unset if killed by a signal, or if dshell stopped tracking it
+        exit_code: Option<i32>,
+        signal: Option<i32>,
+        duration_ms: u64,
+    },
+    /// `allow`, `deny`, `allowpath`, `denypath` or `export`
+    Permission { action: String, target: String },
+}
+
+impl AuditEntry {
+    /// A line for the `audit` builtin
+    fn display(&self) -> String {
+        let time = self.ts.get(..19).unwrap_or(&self.ts).replace('T', " ");
+        let cwd = home_relative(&self.cwd);
+        match &self.event {
+            AuditEvent::Command { argv, mode, isolation, exit_code, signal, duration_ms, .. } => {
+                let mut details = vec![mode.name().to_string()];
+                details.push(match (exit_code, signal) {
+                    (Some(code), _) => format!("exit {}", code),
+                    (None, Some(signal)) => format!("signal {}", signal),
+                    (None, None) => "not waited for".to_string(),
+                });
+                details.push(format!("{:.2}s", *duration_ms as f64 / 1000.0));
+                match isolation {
+                    Some(IsolationStatus::FullyEnforced) => details.push("isolated".to_string()),
+                    Some(IsolationStatus::PartiallyEnforced) => details.push("partly isolated".to_string()),
+                    Some(IsolationStatus::NotEnforced) => details.push("isolation failed".to_string()),
+                    Some(IsolationStatus::NotAvailable) => details.push("no Landlock".to_string()),
+                    None => details.push("isolation unknown".to_string()),
+                }
+                format!("{}  {}  $ {}  ({})", time, cwd, format_argv(argv), details.join(", "))
+            }
+            AuditEvent::Permission { action, target } => format!("{}  {}  {} {}", time, cwd, action, target),
+        }
+    }
+}
+
+/// Operations of the `audit` builtin
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditCommand {
+    /// Show the most recent entries, optionally only those containing
+    /// some text (ignoring case)
+    Show { count: usize, search: Option<String> },
+    /// Check the hash chain
+    Verify,
+}
+
+impl AuditCommand {
+    /// Parse `audit [-n COUNT] [TEXT...]` or `audit verify`
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        match args {
+            [verify] if verify == "verify" => return Ok(AuditCommand::Verify),
+            [verify, ..] if verify == "verify" => return Err("audit: verify: too many arguments".to_string()),
+            _ => {}
+        }
+
+        let mut count = DEFAULT_COUNT;
+        let mut words = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-n" => {
+                    count = args
+                        .next()
+                        .and_then(|n| n.parse().ok())
+                        .ok_or_else(|| "audit: -n: expected a number of entries".to_string())?;
+                }
+                _ if arg.starts_with('-') => return Err(format!("audit: {}: unknown option (usage: audit [-n COUNT] [TEXT])", arg)),
+                _ => words.push(arg.as_str()),
+            }
+        }
+        let search = (!words.is_empty()).then(|| words.join(" "));
+        Ok(AuditCommand::Show { count, search })
+    }
+}
+
+/// The audit log file and its rotation settings
+pub struct AuditLog {
+    /// Unset when the log is disabled or there is no state directory
+    path: Option<PathBuf>,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl AuditLog {
+    pub fn open(config: &AuditConfig) -> Self {
+        let path = Config::state_dir().filter(|_| config.enabled).map(|dir| dir.join("audit.jsonl"));
+        AuditLog { path, max_bytes: config.max_size_mb.saturating_mul(1024 * 1024), keep: config.keep }
+    }
+
+    /// Where entries are written, unless the log is disabled
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Record a finished command; `status` is unset for commands dshell
+    /// stopped tracking, such as disowned jobs
+    pub fn record_command(&mut self, audit: &CommandAudit, status: Option<WaitStatus>, duration: Duration) -> io::Result<()> {
+        let (exit_code, signal) = match status {
+            Some(WaitStatus::Exited(code)) => (Some(code), None),
+            Some(WaitStatus::Signaled(signal)) => (None, Some(signal)),
+            Some(WaitStatus::Stopped(_)) | None => (None, None),
+        };
+        self.append(AuditEntry {
+            seq: 0,
+            ts: format_timestamp(audit.started),
+            cwd: audit.cwd.clone(),
+            event: AuditEvent::Command {
+                argv: audit.argv.clone(),
+                mode: audit.mode,
+                allowed_paths: audit.allowed_paths.clone(),
+                env: audit.env.clone(),
+                isolation: audit.isolation,
+                exit_code,
+                signal,
+                duration_ms: duration.as_millis() as u64,
+            },
+            prev: String::new(),
+        })
+    }
+
+    /// Record a change to what commands may access
+    pub fn record_permission(&mut self, action: &str, target: &str) -> io::Result<()> {
+        self.append(AuditEntry {
+            seq: 0,
+            ts: format_timestamp(SystemTime::now()),
+            cwd: current_dir(),
+            event: AuditEvent::Permission { action: action.to_string(), target: target.to_string() },
+            prev: String::new(),
+        })
+    }
+
+    /// Add an entry to the end of the chain
+    fn append(&mut self, mut entry: AuditEntry) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        }
+
+        // Other sessions append to the same chain, so the head stays locked
+        // until the entry is written; closing the file releases the lock
+        let mut head_file = OpenOptions::new().read(true).write(true).create(true).truncate(false).mode(0o600).open(head_path(path))?;
+        if unsafe { libc::flock(head_file.as_raw_fd(), libc::LOCK_EX) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let head = read_head(&mut head_file)?.unwrap_or_default();
+        let key = load_or_create_key(&key_path(path))?;
+
+        entry.seq = head.seq + 1;
+        entry.prev = head.hash;
+        let (line, hash) = chained_line(&entry)?;
+        self.rotate(path, line.len() as u64)?;
+        OpenOptions::new().create(true).append(true).mode(0o600).open(path)?.write_all(line.as_bytes())?;
+
+        let head = serde_json::to_string(&ChainHead::signed(entry.seq, hash, &key)).map_err(io::Error::other)?;
+        head_file.set_len(0)?;
+        head_file.seek(SeekFrom::Start(0))?;
+        head_file.write_all(head.as_bytes())
+    }
+
+    /// Move the log to `.1` (and older ones up) if `incoming` more bytes
+    /// would take it past the size limit
+    fn rotate(&self, path: &Path, incoming: u64) -> io::Result<()> {
+        let size = fs::metadata(path).map_or(0, |metadata| metadata.len());
+        if size == 0 || size + incoming <= self.max_bytes {
+            return Ok(());
+        }
+        if self.keep == 0 {
+            return fs::remove_file(path);
+        }
+        for n in (1..self.keep).rev() {
+            match fs::rename(rotated(path, n), rotated(path, n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        fs::rename(path, rotated(path, 1))
+    }
+
+    /// Lines for the `audit` builtin: the most recent `count` entries
+    /// containing `search`, oldest first
+    pub fn query(&self, count: usize, search: Option<&str>) -> Vec<String> {
+        let Some(path) = &self.path else {
+            return vec![DISABLED.to_string()];
+        };
+        let search = search.map(str::to_lowercase);
+
+        let mut entries = Vec::new();
+        let files = std::iter::once(path.clone()).chain((1..=self.keep).map(|n| rotated(path, n)));
+        'files: for file in files {
+            let Ok(content) = fs::read_to_string(&file) else {
+                continue;
+            };
+            for line in content.lines().rev() {
+                let Ok(entry) = serde_json::from_str::<AuditEntry>(line) else {
+                    continue;
+                };
+                let display = entry.display();
+                if search.as_ref().is_none_or(|search| display.to_lowercase().contains(search)) {
+                    entries.push(display);
+                }
+                if entries.len() >= count {
+                    break 'files;
+                }
+            }
+        }
+
+        let mut lines = vec![format!("Audit log: {} (times in UTC)", path.display())];
+        if entries.is_empty() {
+            lines.push(if search.is_some() { "(no matching entries)" } else { "(no entries)" }.to_string());
+        }
+        lines.extend(entries.into_iter().rev());
+        lines
+    }
+
+    /// Walk the hash chain from the oldest kept entry to the head
+    pub fn verify(&self) -> Verification {
+        let Some(path) = &self.path else {
+            return Verification { intact: false, lines: vec![DISABLED.to_string()] };
+        };
+        let mut lines = vec![format!("Verifying {}", path.display())];
+        let mut problems = Vec::new();
+        // Entries written before the log was chained
+        let mut unchained = 0;
+        let mut first = None;
+        let mut last: Option<ChainHead> = None;
+        let mut records = 0;
+
+        let files = (1..=self.keep).rev().map(|n| rotated(path, n)).chain(std::iter::once(path.clone()));
+        for file in files {
+            let Ok(content) = fs::read_to_string(&file) else {
+                continue;
+            };
+            let name = file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            for (index, line) in content.lines().enumerate() {
+                let location = format!("{}:{}", name, index + 1);
+                let Some((body, hash)) = split_chained(line) else {
+                    if last.is_none() {
+                        unchained += 1;
+                    } else {
+                        problems.push(format!("{}: entry has no hash", location));
+                    }
+                    continue;
+                };
+                if sha256_hex(body.as_bytes()) != hash {
+                    problems.push(format!("{}: entry was modified (hash mismatch)", location));
+                }
+                let Ok(entry) = serde_json::from_str::<AuditEntry>(&body) else {
+                    problems.push(format!("{}: not an audit entry", location));
+                    continue;
+                };
+                match &last {
+                    None if entry.seq != 1 || entry.prev != GENESIS => lines.push(format!(
+                        "Chain starts at entry {}; earlier entries were rotated away",
+                        entry.seq
+                    )),
+                    Some(last) if entry.seq != last.seq + 1 || entry.prev != last.hash => problems.push(format!(
+                        "{}: chain broken before entry {} (expected entry {}); entries were removed, reordered or edited",
+                        location,
+                        entry.seq,
+                        last.seq + 1
+                    )),
+                    _ => {}
+                }
+                first.get_or_insert(entry.seq);
+                last = Some(ChainHead { seq: entry.seq, hash: hash.to_string(), mac: String::new() });
+                records += 1;
+            }
+        }
+
+        let head = File::open(head_path(path)).and_then(|mut file| read_head(&mut file));
+        if let Ok(Some(head)) = &head {
+            match fs::read_to_string(key_path(path)) {
+                Ok(key) if head.mac == hmac_sha256_hex(key.trim().as_bytes(), &head.signed_text()) => {}
+                Ok(_) => problems.push("the head isn't signed with audit.key; it was rewritten".to_string()),
+                Err(e) => problems.push(format!("can't check the head: {}: {}", key_path(path).display(), e)),
+            }
+        }
+        match (head, &last) {
+            (Ok(Some(head)), Some(last)) if head.seq > last.seq => problems.push(format!(
+                "log was truncated: the last entry is {} but the head is at entry {}",
+                last.seq, head.seq
+            )),
+            (Ok(Some(head)), Some(last)) if (head.seq, &head.hash) != (last.seq, &last.hash) => problems.push(format!(
+                "the last entry ({}) doesn't match the head (entry {})",
+                last.seq, head.seq
+            )),
+            (Ok(Some(head)), None) if head.seq > 0 => {
+                problems.push(format!("log is empty but the head is at entry {}", head.seq))
+            }
+            (Ok(None) | Err(_), Some(_)) => problems.push(format!("head file {} is missing", head_path(path).display())),
+            _ => {}
+        }
+
+        let intact = problems.is_empty();
+        if intact {
+            match (first, &last) {
+                (Some(first), Some(last)) => {
+                    lines.push(format!("✓ Audit log intact: {} entries ({} to {})", records, first, last.seq))
+                }
+                _ => lines.push("✓ Audit log intact: no chained entries yet".to_string()),
+            }
+        } else {
+            lines.extend(problems.iter().map(|problem| format!("✗ {}", problem)));
+            let plural = if problems.len() == 1 { "" } else { "s" };
+            lines.push(format!("✗ Audit log verification failed: {} problem{}", problems.len(), plural));
+        }
+        if unchained > 0 {
+            lines.push(format!("  ({} older entries were written before hash chaining)", unchained));
+        }
+        lines.push(
+            "  Edits by processes that can read audit.key (yours, outside dshell's sandbox) can't be detected".to_string(),
+        );
+        Verification { intact, lines }
+    }
+}
+
+/// Result of `audit verify`
+pub struct Verification {
+    /// Whether the chain is complete and unmodified
+    pub intact: bool,
+    pub lines: Vec<String>,
+}
+
+/// The last entry of the chain, kept in `audit.head`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ChainHead {
+    seq: u64,
+    hash: String,
+    /// HMAC-SHA256 of `seq` and `hash` with the key in `audit.key`
+    #[serde(default)]
+    mac: String,
+}
+
+impl ChainHead {
+    fn signed(seq: u64, hash: String, key: &[u8]) -> Self {
+        let mut head = ChainHead { seq, hash, mac: String::new() };
+        head.mac = hmac_sha256_hex(key, &head.signed_text());
+        head
+    }
+
+    /// What the MAC covers
+    fn signed_text(&self) -> String {
+        format!("{}:{}", self.seq, self.hash)
+    }
+}
+
+impl Default for ChainHead {
+    fn default() -> Self {
+        ChainHead { seq: 0, hash: GENESIS.to_string(), mac: String::new() }
+    }
+}
+
+const DISABLED: &str = "Audit log is disabled (see [audit] in the config)";
+
+fn head_path(path: &Path) -> PathBuf {
+    path.with_file_name("audit.head")
+}
+
+fn key_path(path: &Path) -> PathBuf {
+    path.with_file_name("audit.key")
+}
+
+/// Read the key the head is signed with, creating a random one the first
+/// time; it is stored hex-encoded and used as such
+fn load_or_create_key(path: &Path) -> io::Result<Vec<u8>> {
+    match fs::read_to_string(path) {
+        Ok(key) => return Ok(key.trim().as_bytes().to_vec()),
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+
+    let mut bytes = [0u8; 32];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    let key: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    match OpenOptions::new().write(true).create_new(true).mode(0o600).open(path) {
+        Ok(mut file) => file.write_all(key.as_bytes())?,
+        // Another session created it first
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return load_or_create_key(path),
+        Err(e) => return Err(e),
+    }
+    Ok(key.into_bytes())
+}
+
+/// Read the head, if it has been written
+fn read_head(file: &mut File) -> io::Result<Option<ChainHead>> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    Ok(serde_json::from_str(&contents).ok())
+}
+
+/// An entry as written to the log, with its hash as the last field, and
+/// the hash
+fn chained_line(entry: &AuditEntry) -> io::Result<(String, String)> {
+    let body = serde_json::to_string(entry).map_err(io::Error::other)?;
+    let hash = sha256_hex(body.as_bytes());
+    let line = format!("{},\"hash\":\"{}\"}}\n", &body[..body.len() - 1], hash);
+    Ok((line, hash))
+}
+
+/// Split a log line into the entry that was hashed and its hash
+fn split_chained(line: &str) -> Option<(String, &str)> {
+    let (start, hash) = line.strip_suffix("\"}")?.rsplit_once(",\"hash\":\"")?;
+    Some((format!("{}}}", start), hash))
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// HMAC-SHA256 of `message`, hex-encoded
+fn hmac_sha256_hex(key: &[u8], message: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(message.as_bytes());
+    mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+fn current_dir() -> String {
+    env::current_dir().map(|dir| dir.display().to_string()).unwrap_or_default()
+}
+
+fn home_relative(path: &str) -> String {
+    match env::var("HOME") {
+        Ok(home) if !home.is_empty() && path.starts_with(&home) => format!("~{}", &path[home.len()..]),
+        _ => path.to_string(),
+    }
+}
+
+/// Join arguments for display, quoting any with spaces
+fn format_argv(argv: &[String]) -> String {
+    argv.iter()
+        .map(|arg| {
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                format!("'{}'", arg)
+            } else {
+                arg.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Format a time as RFC 3339 in UTC, e.g. `2026-10-18T14:03:12.345Z`
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs % 86_400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// The date `days` after 1970-01-01 (Howard Hinnant's algorithm)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn temp_log(name: &str, max_bytes: u64, keep: usize) -> AuditLog {
+        let dir = env::temp_dir().join(format!("dshell-audit-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        AuditLog { path: Some(dir.join("audit.jsonl")), max_bytes, keep }
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let time = UNIX_EPOCH + Duration::from_millis(1_792_332_192_345);
+        assert_eq!(format_timestamp(time), "2026-10-18T14:03:12.345Z");
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+    }
+
+    #[test]
+    fn test_parse_audit_command() {
+        assert_eq!(AuditCommand::parse(&[]), Ok(AuditCommand::Show { count: DEFAULT_COUNT, search: None }));
+        assert_eq!(
+            AuditCommand::parse(&args(&["-n", "5", "git", "push"])),
+            Ok(AuditCommand::Show { count: 5, search: Some("git push".to_string()) })
+        );
+        assert_eq!(AuditCommand::parse(&args(&["verify"])), Ok(AuditCommand::Verify));
+        assert!(AuditCommand::parse(&args(&["verify", "now"])).is_err());
+        assert!(AuditCommand::parse(&args(&["-n"])).is_err());
+        assert!(AuditCommand::parse(&args(&["-x"])).is_err());
+    }
+
+    #[test]
+    fn test_record_and_query() {
+        let mut log = temp_log("query", 1024 * 1024, 2);
+        let mut audit = CommandAudit::new(
+            args(&["vim", "my notes.txt"]),
+            RunMode::Interactive,
+            Some(args(&["/data"])),
+            args(&["HOME", "PATH"]),
+        );
+        audit.isolation = Some(IsolationStatus::FullyEnforced);
+        log.record_command(&audit, Some(WaitStatus::Exited(0)), Duration::from_millis(1500)).unwrap();
+        log.record_permission("allow", "GITHUB_TOKEN").unwrap();
+
+        let path = log.path.clone().unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        let first: serde_json::Value = serde_json::from_str(content.lines().next().unwrap()).unwrap();
+        assert_eq!(first["event"], "command");
+        assert_eq!(first["mode"], "interactive");
+        assert_eq!(first["isolation"], "fully_enforced");
+        assert_eq!(first["env"], serde_json::json!(["HOME", "PATH"]));
+        assert_eq!(first["exit_code"], 0);
+        assert_eq!(first["seq"], 1);
+        assert_eq!(first["prev"], GENESIS);
+
+        let lines = log.query(10, None);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].ends_with("$ vim 'my notes.txt'  (interactive, exit 0, 1.50s, isolated)"));
+        assert!(lines[2].ends_with("allow GITHUB_TOKEN"));
+
+        let lines = log.query(10, Some("github"));
+        assert_eq!(lines.len(), 2);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_rotation() {
+        let mut log = temp_log("rotate", 600, 2);
+        for n in 0..10 {
+            log.record_permission("allowpath", &format!("/data/{}", n)).unwrap();
+        }
+        let path = log.path.clone().unwrap();
+        assert!(fs::metadata(&path).unwrap().len() <= 600);
+        assert!(rotated(&path, 2).exists());
+        assert!(!rotated(&path, 3).exists());
+
+        // Queries reach back into rotated files
+        let lines = log.query(3, None);
+        assert!(lines[3].ends_with("allowpath /data/9"));
+        assert!(lines[1].ends_with("allowpath /data/7"));
+
+        // The chain continues across files; the dropped start is noted
+        let verification = log.verify();
+        assert!(verification.intact, "{:?}", verification.lines);
+        assert!(verification.lines[1].starts_with("Chain starts at entry"));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_verify_detects_tampering() {
+        let mut log = temp_log("verify", 1024 * 1024, 2);
+        for n in 0..4 {
+            log.record_permission("allowpath", &format!("/data/{}", n)).unwrap();
+        }
+        let path = log.path.clone().unwrap();
+        let verification = log.verify();
+        assert!(verification.intact, "{:?}", verification.lines);
+        assert!(verification.lines.contains(&"✓ Audit log intact: 4 entries (1 to 4)".to_string()));
+        assert!(verification.lines.last().unwrap().contains("processes that can read audit.key"));
+
+        let original = fs::read_to_string(&path).unwrap();
+        let mut lines: Vec<&str> = original.lines().collect();
+
+        // An edited entry
+        fs::write(&path, original.replace("/data/1", "/data/X")).unwrap();
+        let verification = log.verify();
+        assert!(!verification.intact);
+        assert!(verification.lines[1].contains("audit.jsonl:2: entry was modified"));
+
+        // A removed entry
+        let removed = lines.remove(1);
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        assert!(log.verify().lines[1].contains("chain broken before entry 3"));
+        lines.insert(1, removed);
+
+        // A truncated log
+        fs::write(&path, lines[..3].join("\n") + "\n").unwrap();
+        let verification = log.verify();
+        assert!(verification.lines[1].contains("truncated: the last entry is 3 but the head is at entry 4"));
+
+        // New entries continue the chain after the head
+        fs::write(&path, &original).unwrap();
+        log.record_permission("denypath", "/data").unwrap();
+        assert!(log.verify().intact);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            hmac_sha256_hex(b"Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // Test case 6, a key longer than the block size
+        assert_eq!(
+            hmac_sha256_hex(&[0xaa; 131], "Test Using Larger Than Block-Size Key - Hash Key First"),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_verify_detects_rebuilt_chain() {
+        let mut log = temp_log("rebuild", 1024 * 1024, 2);
+        for n in 0..3 {
+            log.record_permission("allowpath", &format!("/data/{}", n)).unwrap();
+        }
+        let path = log.path.clone().unwrap();
+        assert!(fs::metadata(key_path(&path)).unwrap().permissions().mode() & 0o077 == 0);
+
+        // Edit an entry and rehash the chain after it, as someone who can
+        // write the directory but not read the key would
+        let mut head = ChainHead::default();
+        let mut rebuilt = String::new();
+        for line in fs::read_to_string(&path).unwrap().lines() {
+            let (body, _) = split_chained(line).unwrap();
+            let mut entry: AuditEntry = serde_json::from_str(&body.replace("/data/1", "/data/X")).unwrap();
+            entry.prev = head.hash.clone();
+            let (line, hash) = chained_line(&entry).unwrap();
+            rebuilt.push_str(&line);
+            head = ChainHead::signed(entry.seq, hash, b"guessed key");
+        }
+        fs::write(&path, rebuilt).unwrap();
+        fs::write(head_path(&path), serde_json::to_string(&head).unwrap()).unwrap();
+
+        let verification = log.verify();
+        assert!(!verification.intact);
+        assert!(verification.lines[1].contains("the head isn't signed with audit.key"));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -1,9 +1,16 @@
-/// Built-in shell commands
+//! Built-in shell commands
 
+use super::aliases::AliasCommand;
+use super::audit::AuditCommand;
+use super::dotenv::DotenvCommand;
+use super::functions::FunctionCommand;
+use super::history::HistoryCommand;
+use super::jobs::JobCommand;
+use super::limits::LimitCommand;
 use super::parser::ParsedCommand;
+use super::variables::{VariableCommand, Variables};
 use crate::config::Config;
 use crate::security::PermissionManager;
-use std::collections::HashMap;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum BuiltinCommand {
@@ -16,13 +23,33 @@ pub enum BuiltinCommand {
     AllowAll,           // Allow all env vars
     DenyAll,            // Deny all env vars
     SecurityStatus,     // Show security status
-    Export(String, String), // Set env var: export KEY=VALUE
+    Variables(VariableCommand), // Assignments, export, readonly, unset and set
     Echo(Vec<String>),  // Echo with variable expansion
     AllowPath(String),  // Allow filesystem path access
     DenyPath(String),   // Deny filesystem path access
     ListAllowedPaths,   // List all allowed paths
+    History(HistoryCommand), // List, search, delete or clear history
+    Job(JobCommand),    // Job control: jobs, fg, bg, disown, kill, wait
+    Limit(LimitCommand), // Show or change resource limits for the session
+    Time(String),       // Run a command and always report its timing
+    Audit(AuditCommand), // Show recent audit log entries
+    Unmask(Option<usize>), // List redacted values, or show one after confirmation
+    Dotenv(DotenvCommand), // Review and load variables from a .env file
+    Alias(AliasCommand), // Define, show or remove aliases
+    Function(FunctionCommand), // List or remove functions
+    Source(String, Vec<String>), // Run a script's commands, with arguments as $1...
+    Invalid(String),    // Built-in invoked with invalid arguments
 }
 
+/// Names recognized by `Builtins::parse`, used for tab completion
+pub const BUILTIN_NAMES: &[&str] = &[
+    "exit", "quit", "clear", "cls", "help", "env", "allow", "deny", "security", "status",
+    "export", "echo", "allowpath", "denypath", "listallowpath", "listpaths", "history",
+    "jobs", "fg", "bg", "disown", "kill", "wait", "limit", "time",
+    "audit", "unmask", "dotenv", "readonly", "unset", "set",
+    "alias", "unalias", "functions", "source", ".",
+];
+
 pub struct Builtins;
 
 impl Builtins {
@@ -48,38 +75,14 @@ impl Builtins {
                 }
             }
             "security" | "status" => Some(BuiltinCommand::SecurityStatus),
-            "export" => {
-                // Parse export KEY=VALUE with proper quote handling
-                // Supports: export KEY="value  with   multiple  spaces"
-                //           export KEY='value  with   multiple  spaces'
-                // Use raw_input to preserve all spaces
-                let raw = &cmd.raw_input;
-
-                // Find first space to skip "export" and any spaces after it
-                if let Some(first_space) = raw.find(char::is_whitespace) {
-                    // Skip past "export" and all spaces after it until non-space
-                    let after_export = &raw[first_space..];
-                    let export_arg = after_export.trim_start();
-
-                    if let Some(pos) = export_arg.find('=') {
-                        let key = export_arg[..pos].trim().to_string();
-                        let value_part = &export_arg[pos + 1..];
-
-                        // Handle quoted values - preserve all spaces within quotes
-                        let value = if (value_part.starts_with('"') && value_part.ends_with('"'))
-                            || (value_part.starts_with('\'') && value_part.ends_with('\''))
-                        {
-                            // Remove surrounding quotes, preserve internal spacing
-                            value_part[1..value_part.len() - 1].to_string()
-                        } else {
-                            value_part.to_string()
-                        };
-
-                        return Some(BuiltinCommand::Export(key, value));
-                    }
-                }
-                None
-            }
+            "unset" if cmd.args.first().is_some_and(|arg| arg == "-f") => Some(match &cmd.args[1..] {
+                [] => BuiltinCommand::Invalid("unset: usage: unset -f NAME...".to_string()),
+                names => BuiltinCommand::Function(FunctionCommand::Unset(names.to_vec())),
+            }),
+            "export" | "readonly" | "unset" | "set" => Some(match VariableCommand::parse(&cmd.command, &cmd.args) {
+                Ok(variable_cmd) => BuiltinCommand::Variables(variable_cmd),
+                Err(e) => BuiltinCommand::Invalid(e),
+            }),
             "echo" => Some(BuiltinCommand::Echo(cmd.args.clone())),
             "allowpath" => {
                 if cmd.args.is_empty() {
@@ -96,13 +99,54 @@ impl Builtins {
                 }
             }
             "listallowpath" | "listpaths" => Some(BuiltinCommand::ListAllowedPaths),
-            _ => None,
+            "jobs" | "fg" | "bg" | "disown" | "kill" | "wait" => Some(match JobCommand::parse(&cmd.command, &cmd.args) {
+                Ok(job_cmd) => BuiltinCommand::Job(job_cmd),
+                Err(e) => BuiltinCommand::Invalid(e),
+            }),
+            "limit" => Some(match LimitCommand::parse(&cmd.args) {
+                Ok(limit_cmd) => BuiltinCommand::Limit(limit_cmd),
+                Err(e) => BuiltinCommand::Invalid(e),
+            }),
+            "time" => Some(match cmd.raw_input.strip_prefix("time").map(str::trim) {
+                Some(command) if !command.is_empty() => BuiltinCommand::Time(command.to_string()),
+                _ => BuiltinCommand::Invalid("time: usage: time COMMAND".to_string()),
+            }),
+            "audit" => Some(match AuditCommand::parse(&cmd.args) {
+                Ok(audit_cmd) => BuiltinCommand::Audit(audit_cmd),
+                Err(e) => BuiltinCommand::Invalid(e),
+            }),
+            "unmask" => Some(match cmd.args.as_slice() {
+                [] => BuiltinCommand::Unmask(None),
+                [number] => match number.trim_start_matches('#').parse() {
+                    Ok(number) => BuiltinCommand::Unmask(Some(number)),
+                    Err(_) => BuiltinCommand::Invalid(format!("unmask: {}: expected a redaction number", number)),
+                },
+                _ => BuiltinCommand::Invalid("unmask: usage: unmask [N]".to_string()),
+            }),
+            "dotenv" => Some(match DotenvCommand::parse(&cmd.args) {
+                Ok(dotenv_cmd) => BuiltinCommand::Dotenv(dotenv_cmd),
+                Err(e) => BuiltinCommand::Invalid(e),
+            }),
+            "alias" | "unalias" => Some(match AliasCommand::parse(&cmd.command, &cmd.args) {
+                Ok(alias_cmd) => BuiltinCommand::Alias(alias_cmd),
+                Err(e) => BuiltinCommand::Invalid(e),
+            }),
+            "source" | "." => Some(match cmd.args.split_first() {
+                Some((file, args)) => BuiltinCommand::Source(file.clone(), args.to_vec()),
+                None => BuiltinCommand::Invalid(format!("{}: usage: {} FILE [ARGS...]", cmd.command, cmd.command)),
+            }),
+            "functions" => Some(BuiltinCommand::Function(FunctionCommand::List(cmd.args.clone()))),
+            "history" => Some(match HistoryCommand::parse(&cmd.args) {
+                Ok(history_cmd) => BuiltinCommand::History(history_cmd),
+                Err(e) => BuiltinCommand::Invalid(e),
+            }),
+            _ => VariableCommand::parse_assignments(&cmd.command, &cmd.args).map(BuiltinCommand::Variables),
         }
     }
 
     /// Execute a built-in command and return output
     /// Note: Commands that modify permissions return a marker that needs to be handled by the caller
-    pub fn execute(builtin: &BuiltinCommand, permissions: &PermissionManager, custom_env: &HashMap<String, String>, config: &Config) -> Vec<String> {
+    pub fn execute(builtin: &BuiltinCommand, permissions: &PermissionManager, variables: &Variables, config: &Config) -> Vec<String> {
         match builtin {
             BuiltinCommand::Exit => vec![],
             BuiltinCommand::Clear => vec![],
@@ -114,8 +158,53 @@ impl Builtins {
                 "  clear, cls       - Clear the screen".to_string(),
                 "  help             - Show this help message".to_string(),
                 "  env              - List environment variables (respects permissions)".to_string(),
-                "  export KEY=VALUE - Set environment variable".to_string(),
-                "  echo [args]      - Echo arguments (supports $VAR expansion)".to_string(),
+                "  NAME=VALUE       - Set a shell variable (not passed to commands)".to_string(),
+                "  export NAME[=VALUE] - Set a variable and pass it to commands".to_string(),
+                "  readonly NAME[=VALUE] - Make a variable readonly".to_string(),
+                "  unset NAME       - Remove a variable (an inherited one for this session)".to_string(),
+                "  set              - List the session's variables".to_string(),
+                "  set -o|+o OPTION - Turn errexit (-e), nounset (-u), xtrace (-x) or pipefail on or off".to_string(),
+                "  dotenv [FILE]    - Choose variables to load from FILE (default .env)".to_string(),
+                "  echo [args]      - Echo arguments".to_string(),
+                "  alias [NAME[=VALUE]] - Define or show aliases; unalias NAME (or -a for all) removes them".to_string(),
+                "  NAME() { ...; }  - Define a function; its arguments are $1, $2, ..., $# and $@".to_string(),
+                "  functions [NAME] - Show functions; unset -f NAME removes one".to_string(),
+                "  source FILE [ARGS], . FILE - Run the commands in FILE (~/.config/dshell/rc.dsh runs at startup)".to_string(),
+                "  PS1=TEXT         - Change the prompt".to_string(),
+                "  ($VAR, ${VAR:-default} and $? expand in all commands, except inside single quotes)".to_string(),
+                "".to_string(),
+                "History:".to_string(),
+                "  history [N]           - List command history (last N entries)".to_string(),
+                "  history -g <PATTERN>  - Search history for a pattern".to_string(),
+                "  history -d <N>        - Delete history entry N".to_string(),
+                "  history -c            - Clear history".to_string(),
+                "  !!, !N, !-N, !prefix  - Re-run a previous command (asks first)".to_string(),
+                "  ^old^new              - Re-run the last command with a substitution".to_string(),
+                "".to_string(),
+                "Running commands:".to_string(),
+                "  Ctrl+C                - Interrupt the command (or clear the input line)".to_string(),
+                "  Ctrl+\\                - Quit the command".to_string(),
+                "  Ctrl+Z                - Stop the command".to_string(),
+                "".to_string(),
+                "Jobs:".to_string(),
+                "  <command> &           - Run a command in the background (isolated like interactive commands)".to_string(),
+                "  jobs                  - List background and stopped jobs".to_string(),
+                "  jobs -o [%N]          - Show a job's buffered output".to_string(),
+                "  fg [%N], bg [%N]      - Resume a job in the foreground or background".to_string(),
+                "  disown [%N]           - Forget a job without killing it".to_string(),
+                "  kill [-SIG] %N|PID    - Send a signal (default TERM) to a job or process".to_string(),
+                "  wait [%N...]          - Wait for jobs to finish (Ctrl+C stops waiting)".to_string(),
+                "".to_string(),
+                "Resource limits:".to_string(),
+                "  limit                 - Show resource limits".to_string(),
+                "  limit timeout <TIME>  - Kill commands running longer than TIME (30s, 5m, 1h)".to_string(),
+                "  limit cpu <TIME>      - Limit CPU time".to_string(),
+                "  limit memory <SIZE>   - Limit memory (512M, 2G)".to_string(),
+                "  limit fsize <SIZE>    - Limit the size of files a command writes".to_string(),
+                "  limit procs <N>       - Limit the number of processes".to_string(),
+                "  limit cpushare <N%>   - Limit CPU use to N% of one CPU (needs cgroups)".to_string(),
+                "  (use 'none' to remove a limit; per-command limits from config still apply)".to_string(),
+                "  time <COMMAND>        - Run a command and report its time, CPU use and memory".to_string(),
                 "".to_string(),
                 "Security commands:".to_string(),
                 "  allow <VAR>           - Allow access to specific env variable".to_string(),
@@ -126,40 +215,46 @@ impl Builtins {
                 "  denypath <PATH>       - Deny filesystem access to specific path".to_string(),
                 "  listallowpath         - List all allowed filesystem paths".to_string(),
                 "  security, status      - Show current security status".to_string(),
+                "  audit [-n N] [TEXT]   - Show the last N audit log entries, optionally matching TEXT".to_string(),
+                "  audit verify          - Check the audit log's hash chain".to_string(),
+                "  unmask [N]            - List redacted secrets, or show secret N (asks first)".to_string(),
                 "".to_string(),
                 "All other commands are executed as external programs.".to_string(),
             ],
             BuiltinCommand::Env => {
                 let mut output = vec!["Environment Variables:".to_string(), "".to_string()];
 
-                // Get filtered system environment variables based on permissions
-                let env_vars = permissions.get_allowed_env_vars();
+                // Get filtered system environment variables based on permissions,
+                // leaving out ones unset or replaced in this session
+                let env_vars: Vec<(String, String)> = permissions
+                    .get_allowed_env_vars()
+                    .into_iter()
+                    .filter(|(key, _)| !variables.is_unset(key) && variables.get(key).is_none())
+                    .collect();
 
                 // Format and add system env vars
                 for (key, value) in &env_vars {
                     output.push(format!("{}={}", key, value));
                 }
 
-                // Add custom environment variables at the end
-                if !custom_env.is_empty() {
+                // Add exported shell variables at the end
+                let exported: Vec<(&String, &String)> = variables.exported().collect();
+                if !exported.is_empty() {
                     output.push("".to_string());
-                    output.push("Custom Environment Variables:".to_string());
+                    output.push("Exported Shell Variables:".to_string());
                     output.push("".to_string());
 
-                    let mut custom_vars: Vec<(&String, &String)> = custom_env.iter().collect();
-                    custom_vars.sort_by_key(|(k, _)| *k);
-
-                    for (key, value) in custom_vars {
+                    for (key, value) in &exported {
                         output.push(format!("{}={}", key, value));
                     }
                 }
 
                 output.push("".to_string());
                 output.push(format!(
-                    "Showing: {} system + {} custom = {} total environment variables",
+                    "Showing: {} system + {} exported = {} total environment variables",
                     env_vars.len(),
-                    custom_env.len(),
-                    env_vars.len() + custom_env.len()
+                    exported.len(),
+                    env_vars.len() + exported.len()
                 ));
 
                 output
@@ -180,24 +275,35 @@ impl Builtins {
             BuiltinCommand::DenyAll => {
                 vec!["PERMISSION_CHANGE:DENY_ALL".to_string()]
             }
-            BuiltinCommand::Export(key, value) => {
-                vec![format!("ENV_SET:{}={}", key, value)]
-            }
-            BuiltinCommand::Echo(args) => {
-                // Expand variables in arguments
-                let expanded: Vec<String> = args
-                    .iter()
-                    .map(|arg| Self::expand_variables(arg, permissions, custom_env))
-                    .collect();
-
-                vec![expanded.join(" ")]
-            }
+            // Variables live in the app, which records exports in the audit log
+            BuiltinCommand::Variables(_) => vec![],
+            // Variables were expanded when the command line was parsed
+            BuiltinCommand::Echo(args) => vec![args.join(" ")],
             BuiltinCommand::AllowPath(path) => {
                 vec![format!("PERMISSION_CHANGE:ALLOW_PATH:{}", path)]
             }
             BuiltinCommand::DenyPath(path) => {
                 vec![format!("PERMISSION_CHANGE:DENY_PATH:{}", path)]
             }
+            // History lives in the input editor, so the caller renders it
+            BuiltinCommand::History(_) => vec![],
+            // The job table lives in the app, so the caller runs these
+            BuiltinCommand::Job(_) => vec![],
+            // The cgroups dshell runs commands in live in the app
+            BuiltinCommand::Limit(_) => vec![],
+            // The caller runs the command and reports its timing
+            BuiltinCommand::Time(_) => vec![],
+            // The audit log is opened by the app
+            BuiltinCommand::Audit(_) => vec![],
+            // Redacted values are kept by the app
+            BuiltinCommand::Unmask(_) => vec![],
+            // The caller asks which variables to load
+            BuiltinCommand::Dotenv(_) => vec![],
+            // Aliases and functions are kept by the app
+            BuiltinCommand::Alias(_) | BuiltinCommand::Function(_) => vec![],
+            // Scripts run through the app's command pipeline
+            BuiltinCommand::Source(..) => vec![],
+            BuiltinCommand::Invalid(message) => vec![message.clone()],
             BuiltinCommand::ListAllowedPaths => {
                 let mut output = vec!["Allowed Filesystem Paths:".to_string(), "".to_string()];
 
@@ -235,62 +341,30 @@ impl Builtins {
             }
         }
     }
+}
 
-    /// Expand $VAR and ${VAR} in a string
-    fn expand_variables(s: &str, permissions: &PermissionManager, custom_env: &HashMap<String, String>) -> String {
-        let mut result = String::new();
-        let mut chars = s.chars().peekable();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        while let Some(c) = chars.next() {
-            if c == '$' {
-                if chars.peek() == Some(&'{') {
-                    // ${VAR} syntax
-                    chars.next(); // consume '{'
-                    let mut var_name = String::new();
-                    while let Some(&ch) = chars.peek() {
-                        if ch == '}' {
-                            chars.next(); // consume '}'
-                            break;
-                        }
-                        var_name.push(chars.next().unwrap());
-                    }
-                    result.push_str(&Self::get_var_value(&var_name, permissions, custom_env));
-                } else {
-                    // $VAR syntax
-                    let mut var_name = String::new();
-                    while let Some(&ch) = chars.peek() {
-                        if ch.is_alphanumeric() || ch == '_' {
-                            var_name.push(chars.next().unwrap());
-                        } else {
-                            break;
-                        }
-                    }
-                    result.push_str(&Self::get_var_value(&var_name, permissions, custom_env));
-                }
-            } else {
-                result.push(c);
-            }
-        }
-
-        result
+    fn parse(input: &str) -> Option<BuiltinCommand> {
+        Builtins::parse(&ParsedCommand::parse(input).unwrap())
     }
 
-    /// Get variable value from custom env or system env (respecting permissions)
-    fn get_var_value(var_name: &str, permissions: &PermissionManager, custom_env: &HashMap<String, String>) -> String {
-        // Check custom env first
-        if let Some(value) = custom_env.get(var_name) {
-            return value.clone();
-        }
-
-        // Check system env with permissions
-        let env_vars = permissions.get_allowed_env_vars();
-        for (key, value) in env_vars {
-            if key == var_name && !value.starts_with("[REDACTED") {
-                return value;
-            }
-        }
-
-        // Variable not found or not allowed
-        String::new()
+    #[test]
+    fn test_parse_variable_commands() {
+        assert_eq!(
+            parse(r#"export URL="postgres://h/db?a=1#x" NAME='two  words' # comment"#),
+            Some(BuiltinCommand::Variables(VariableCommand::Export(vec![
+                ("URL".to_string(), Some("postgres://h/db?a=1#x".to_string())),
+                ("NAME".to_string(), Some("two  words".to_string())),
+            ])))
+        );
+        assert_eq!(
+            parse("LOCAL=1"),
+            Some(BuiltinCommand::Variables(VariableCommand::Assign(vec![("LOCAL".to_string(), "1".to_string())])))
+        );
+        assert_eq!(parse("./configure"), None);
+        assert!(matches!(parse("export 1X=2"), Some(BuiltinCommand::Invalid(_))));
     }
 }
@@ -0,0 +1,241 @@
+//! cgroup v2 placement, limits and accounting for launched commands
+//!
+//! When dshell runs in a cgroup v2 subtree it may write to (a delegated
+//! systemd scope, a container, or as root), it creates a `dshell-<pid>`
+//! group below its own and runs every command in a child group of that.
+//! The enabled controllers enforce `memory.max`, `pids.max` and `cpu.max`,
+//! the group's statistics give peak memory and CPU time for the whole
+//! process tree, and `cgroup.kill` ends the tree at once. Without such a
+//! subtree dshell falls back to rlimits.
+
+use crate::config::ResourceLimits;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Controllers dshell enables for command groups, when available
+const CONTROLLERS: &[&str] = &["memory", "pids", "cpu"];
+
+/// Period for `cpu.max`, in microseconds
+const CPU_PERIOD_USEC: u64 = 100_000;
+
+/// dshell's own group, under which each command gets a group
+pub struct CgroupManager {
+    root: PathBuf,
+    controllers: Vec<String>,
+    next: AtomicUsize,
+}
+
+impl CgroupManager {
+    /// Create dshell's group, if a writable cgroup v2 subtree is available
+    pub fn detect() -> Option<Self> {
+        let mount = cgroup2_mount(&fs::read_to_string("/proc/self/mountinfo").ok()?)?;
+        let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+        let parent = mount.join(own_cgroup(&cgroups)?.trim_start_matches('/'));
+        let root = parent.join(format!("dshell-{}", std::process::id()));
+        fs::create_dir(&root).ok()?;
+
+        // A controller must be enabled in the parent before dshell's group
+        // can pass it on. The parent usually can't enable more while it has
+        // processes of its own (dshell), so this only adds what it can.
+        for group in [&parent, &root] {
+            let available = read_words(&group.join("cgroup.controllers"));
+            for controller in CONTROLLERS.iter().filter(|c| available.iter().any(|a| a == *c)) {
+                let _ = fs::write(group.join("cgroup.subtree_control"), format!("+{}", controller));
+            }
+        }
+
+        let controllers = read_words(&root.join("cgroup.subtree_control"));
+        Some(CgroupManager { root, controllers, next: AtomicUsize::new(1) })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Controllers enabled for command groups
+    pub fn controllers(&self) -> &[String] {
+        &self.controllers
+    }
+
+    /// Create the group for one command and apply the limits it can enforce
+    pub fn create(&self, limits: &ResourceLimits) -> io::Result<Cgroup> {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let path = self.root.join(format!("cmd-{}", id));
+        fs::create_dir(&path)?;
+
+        let cgroup = Cgroup { path, controllers: self.controllers.clone() };
+        cgroup.set_limits(limits)?;
+        Ok(cgroup)
+    }
+}
+
+impl Drop for CgroupManager {
+    /// Remove dshell's group; groups still holding processes (such as
+    /// disowned jobs) are left alone
+    fn drop(&mut self) {
+        if let Ok(entries) = fs::read_dir(&self.root) {
+            for entry in entries.flatten() {
+                if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                    let _ = fs::remove_dir(entry.path());
+                }
+            }
+        }
+        let _ = fs::remove_dir(&self.root);
+    }
+}
+
+/// The group a single command and everything it starts run in
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+    controllers: Vec<String>,
+}
+
+impl Cgroup {
+    /// Whether a controller is enabled, so its limits are enforced here
+    pub fn has(&self, controller: &str) -> bool {
+        self.controllers.iter().any(|c| c == controller)
+    }
+
+    fn set_limits(&self, limits: &ResourceLimits) -> io::Result<()> {
+        let set = |value: Option<u64>| value.filter(|&v| v > 0);
+
+        if let Some(mb) = set(limits.memory_mb).filter(|_| self.has("memory")) {
+            fs::write(self.path.join("memory.max"), (mb * 1024 * 1024).to_string())?;
+            // Otherwise the command is swapped out instead of stopped
+            let _ = fs::write(self.path.join("memory.swap.max"), "0");
+        }
+        if let Some(count) = set(limits.max_processes).filter(|_| self.has("pids")) {
+            fs::write(self.path.join("pids.max"), count.to_string())?;
+        }
+        if let Some(percent) = set(limits.cpu_percent).filter(|_| self.has("cpu")) {
+            fs::write(self.path.join("cpu.max"), cpu_max(percent))?;
+        }
+        Ok(())
+    }
+
+    /// Path of the file a process writes `0` to, to move itself here
+    pub fn procs_path(&self) -> CString {
+        CString::new(self.path.join("cgroup.procs").as_os_str().as_bytes()).expect("cgroup paths have no NUL bytes")
+    }
+
+    /// Kill every process in the group
+    pub fn kill(&self) -> io::Result<()> {
+        fs::write(self.path.join("cgroup.kill"), "1")
+    }
+
+    /// Resources used so far by everything that ran in the group
+    pub fn usage(&self) -> Usage {
+        let cpu = fs::read_to_string(self.path.join("cpu.stat")).unwrap_or_default();
+        let memory_events = fs::read_to_string(self.path.join("memory.events")).unwrap_or_default();
+        let pids_events = fs::read_to_string(self.path.join("pids.events")).unwrap_or_default();
+        Usage {
+            user: Duration::from_micros(stat_value(&cpu, "user_usec").unwrap_or(0)),
+            system: Duration::from_micros(stat_value(&cpu, "system_usec").unwrap_or(0)),
+            peak_memory: fs::read_to_string(self.path.join("memory.peak"))
+                .ok()
+                .and_then(|peak| peak.trim().parse().ok()),
+            oom_kills: stat_value(&memory_events, "oom_kill").unwrap_or(0),
+            process_limit_hits: stat_value(&pids_events, "max").unwrap_or(0),
+        }
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // Fails while processes the command left behind still run
+        let _ = fs::remove_dir(&self.path);
+    }
+}
+
+/// Resource usage of a command's process tree
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    pub user: Duration,
+    pub system: Duration,
+    /// Peak memory in bytes, with the memory controller
+    pub peak_memory: Option<u64>,
+    /// Processes killed for going over `memory.max`
+    pub oom_kills: u64,
+    /// Times a fork failed because of `pids.max`
+    pub process_limit_hits: u64,
+}
+
+/// `cpu.max` contents allowing `percent` of one CPU
+fn cpu_max(percent: u64) -> String {
+    format!("{} {}", percent * CPU_PERIOD_USEC / 100, CPU_PERIOD_USEC)
+}
+
+/// Find where the cgroup v2 hierarchy is mounted
+fn cgroup2_mount(mountinfo: &str) -> Option<PathBuf> {
+    mountinfo.lines().find_map(|line| {
+        // Optional fields end with "-", followed by the filesystem type
+        let (mount, fs_type) = line.split_once(" - ")?;
+        if fs_type.split_whitespace().next()? != "cgroup2" {
+            return None;
+        }
+        mount.split_whitespace().nth(4).map(PathBuf::from)
+    })
+}
+
+/// dshell's own group in the cgroup v2 hierarchy, from /proc/self/cgroup
+fn own_cgroup(cgroups: &str) -> Option<&str> {
+    cgroups.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+fn read_words(path: &Path) -> Vec<String> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|word| word.to_string())
+        .collect()
+}
+
+/// Look up `key` in a flat-keyed stat file such as `cpu.stat`
+fn stat_value(stats: &str, key: &str) -> Option<u64> {
+    stats.lines().find_map(|line| {
+        let (name, value) = line.split_once(' ')?;
+        if name == key { value.trim().parse().ok() } else { None }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_cgroup2_mount() {
+        let mountinfo = "\
+32 24 0:28 / /sys/fs/cgroup rw,relatime - tmpfs tmpfs rw,mode=755
+36 32 0:32 / /sys/fs/cgroup/memory rw,relatime - cgroup cgroup rw,memory
+42 32 0:38 / /sys/fs/cgroup/unified rw,relatime shared:9 - cgroup2 cgroup2 rw";
+        assert_eq!(cgroup2_mount(mountinfo), Some(PathBuf::from("/sys/fs/cgroup/unified")));
+        assert_eq!(cgroup2_mount("32 24 0:28 / /sys/fs/cgroup rw - tmpfs tmpfs rw"), None);
+    }
+
+    #[test]
+    fn test_own_cgroup() {
+        let cgroups = "4:memory:/user.slice\n0::/user.slice/user-1000.slice/session-2.scope\n";
+        assert_eq!(own_cgroup(cgroups), Some("/user.slice/user-1000.slice/session-2.scope"));
+        assert_eq!(own_cgroup("4:memory:/\n"), None);
+    }
+
+    #[test]
+    fn test_stat_values() {
+        let stats = "usage_usec 1500000\nuser_usec 1200000\nsystem_usec 300000\n";
+        assert_eq!(stat_value(stats, "user_usec"), Some(1_200_000));
+        assert_eq!(stat_value(stats, "usage"), None);
+        assert_eq!(stat_value("low 0\nhigh 0\nmax 3\noom 1\noom_kill 1\n", "max"), Some(3));
+    }
+
+    #[test]
+    fn test_cpu_max() {
+        assert_eq!(cpu_max(50), "50000 100000");
+        assert_eq!(cpu_max(200), "200000 100000");
+    }
+}
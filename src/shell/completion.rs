@@ -0,0 +1,499 @@
+//! Tab completion engine
+//!
+//! Completes the word under the cursor: builtins and executables on the
+//! filtered PATH in command position, `$VAR` names the permission manager
+//! lets through, arguments of the permission builtins, and file paths the
+//! Landlock sandbox can actually reach. Commands with a completion spec
+//! (see `completion_spec`) get subcommand, flag and argument completion;
+//! others can optionally fall back to bash-completion run in a sandbox.
+
+use super::aliases::Aliases;
+use super::builtins::BUILTIN_NAMES;
+use super::completion_spec::{ArgKind, CompletionSpecs};
+use super::executor::Executor;
+use super::functions::Functions;
+use super::parser::{self, TokenKind};
+use super::variables::Variables;
+use crate::config::Config;
+use crate::security::{LandlockIsolation, Permission, PermissionManager};
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long the bash-completion helper may run before it is killed
+const BASH_COMPLETION_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// Asks bash-completion for candidates; prints one COMPREPLY entry per line.
+/// $1 is the command name, $2 the line up to the cursor.
+const BASH_COMPLETION_SCRIPT: &str = r#"
+for f in /usr/share/bash-completion/bash_completion /etc/bash_completion; do
+    [ -r "$f" ] && . "$f" && break
+done
+if type _completion_loader >/dev/null 2>&1; then
+    _completion_loader "$1" >/dev/null 2>&1
+elif [ -r "/usr/share/bash-completion/completions/$1" ]; then
+    . "/usr/share/bash-completion/completions/$1" >/dev/null 2>&1
+fi
+spec=$(complete -p "$1" 2>/dev/null) || exit 1
+func=$(printf '%s\n' "$spec" | sed -n 's/.*-F \([^ ]*\).*/\1/p')
+[ -n "$func" ] || exit 1
+COMP_LINE=$2
+COMP_POINT=${#COMP_LINE}
+read -ra COMP_WORDS <<<"$COMP_LINE"
+[[ $COMP_LINE == *' ' ]] && COMP_WORDS+=('')
+COMP_CWORD=$(( ${#COMP_WORDS[@]} - 1 ))
+"$func" "$1" "${COMP_WORDS[COMP_CWORD]}" "${COMP_WORDS[COMP_CWORD-1]}" >/dev/null 2>&1
+printf '%s\n' "${COMPREPLY[@]}"
+"#;
+
+/// A single completion candidate
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    /// Text that replaces the word being completed
+    pub replacement: String,
+    /// Short label shown in the completion menu
+    pub display: String,
+}
+
+/// Result of completing the word under the cursor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    /// Byte offset in the line where the completed word starts
+    pub start: usize,
+    pub candidates: Vec<Candidate>,
+}
+
+/// What kind of word is being completed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WordKind {
+    Command,
+    Variable,
+    EnvVarName,
+    AnyPath,
+    CommandArg,
+}
+
+pub struct Completer<'a> {
+    permissions: &'a PermissionManager,
+    variables: &'a Variables,
+    config: &'a Config,
+    specs: &'a CompletionSpecs,
+    aliases: Option<&'a Aliases>,
+    functions: Option<&'a Functions>,
+}
+
+impl<'a> Completer<'a> {
+    pub fn new(
+        permissions: &'a PermissionManager,
+        variables: &'a Variables,
+        config: &'a Config,
+        specs: &'a CompletionSpecs,
+    ) -> Self {
+        Self { permissions, variables, config, specs, aliases: None, functions: None }
+    }
+
+    /// Also complete the session's aliases and functions as commands
+    pub fn with_definitions(mut self, aliases: &'a Aliases, functions: &'a Functions) -> Self {
+        self.aliases = Some(aliases);
+        self.functions = Some(functions);
+        self
+    }
+
+    /// Complete the word that ends at `cursor` in `line`
+    ///
+    /// Words are found the way the parser splits them, so a quoted or
+    /// escaped word with spaces is completed as one word, and only the
+    /// words of the command after the last operator count.
+    pub fn complete(&self, line: &str, cursor: usize) -> Completion {
+        let before = &line[..cursor];
+        let mut tokens = parser::tokenize(before);
+        let current = tokens.pop_if(|token| token.kind == TokenKind::Word && token.end == before.len());
+        let (start, word) = current.map_or((before.len(), String::new()), |token| (token.start, token.value));
+        let mut preceding: Vec<&str> = Vec::new();
+        for token in &tokens {
+            match token.kind {
+                TokenKind::Word => preceding.push(&token.value),
+                TokenKind::Operator => preceding.clear(),
+                TokenKind::Redirection => {}
+            }
+        }
+        let word = word.as_str();
+
+        let kind = if before[start..].starts_with('$') {
+            WordKind::Variable
+        } else if preceding.is_empty() {
+            if word.contains('/') {
+                WordKind::CommandArg
+            } else {
+                WordKind::Command
+            }
+        } else {
+            match preceding[0] {
+                "allow" | "deny" if preceding.len() == 1 => WordKind::EnvVarName,
+                "allowpath" | "denypath" => WordKind::AnyPath,
+                _ => WordKind::CommandArg,
+            }
+        };
+
+        let mut candidates = match kind {
+            WordKind::Command => self.complete_command(word),
+            WordKind::Variable => self.complete_variable(word),
+            WordKind::EnvVarName => self.complete_env_var_name(word, preceding[0]),
+            WordKind::AnyPath => self.complete_path(word, false, false),
+            WordKind::CommandArg if preceding.is_empty() => self.complete_path(word, true, false),
+            WordKind::CommandArg => self.complete_argument(&preceding, word, before),
+        };
+        candidates.sort_by(|a, b| a.replacement.cmp(&b.replacement));
+        candidates.dedup_by(|a, b| a.replacement == b.replacement);
+
+        Completion { start, candidates }
+    }
+
+    /// Complete an argument using the command's spec, bash-completion or paths
+    fn complete_argument(&self, preceding: &[&str], word: &str, line: &str) -> Vec<Candidate> {
+        if let Some(spec) = self.specs.get(preceding[0]) {
+            let resolved = spec.resolve(&preceding[1..], word);
+            let mut candidates: Vec<Candidate> = resolved
+                .words
+                .into_iter()
+                .map(|w| Candidate {
+                    replacement: format!("{} ", w),
+                    display: w,
+                })
+                .collect();
+            candidates.extend(self.complete_kind(resolved.args, word));
+            return candidates;
+        }
+
+        if self.config.bash_completion_fallback {
+            let candidates = self.complete_with_bash(preceding[0], line);
+            if !candidates.is_empty() {
+                return candidates;
+            }
+        }
+
+        self.complete_path(word, true, false)
+    }
+
+    /// Dynamic completions for an argument kind from a spec
+    fn complete_kind(&self, kind: ArgKind, word: &str) -> Vec<Candidate> {
+        match kind {
+            ArgKind::Path => self.complete_path(word, true, false),
+            ArgKind::Directory => self.complete_path(word, true, true),
+            ArgKind::Command => self.complete_command(word),
+            ArgKind::Variable => self.complete_env_var_name(word, "allow"),
+            ArgKind::None => Vec::new(),
+        }
+    }
+
+    /// Ask bash-completion for candidates, running bash as a sandboxed helper
+    fn complete_with_bash(&self, command: &str, line: &str) -> Vec<Candidate> {
+        let args = vec![
+            "--noprofile".to_string(),
+            "--norc".to_string(),
+            "-c".to_string(),
+            BASH_COMPLETION_SCRIPT.to_string(),
+            "dshell-complete".to_string(),
+            command.to_string(),
+            line.to_string(),
+        ];
+
+        Executor::run_sandboxed_helper(
+            "bash",
+            &args,
+            self.permissions,
+            self.variables,
+            self.config,
+            BASH_COMPLETION_TIMEOUT,
+        )
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|reply| !reply.is_empty())
+        .map(|reply| Candidate {
+            replacement: if reply.ends_with('/') { reply.clone() } else { format!("{} ", reply) },
+            display: reply,
+        })
+        .collect()
+    }
+
+    /// Complete builtins, aliases, functions and executables found on the
+    /// filtered PATH
+    fn complete_command(&self, word: &str) -> Vec<Candidate> {
+        let aliases = self.aliases.into_iter().flat_map(Aliases::names);
+        let functions = self.functions.into_iter().flat_map(Functions::names);
+        let mut names: BTreeSet<String> = BUILTIN_NAMES
+            .iter()
+            .copied()
+            .chain(aliases)
+            .chain(functions)
+            .filter(|name| name.starts_with(word))
+            .map(|name| name.to_string())
+            .collect();
+
+        if let Some(path_var) = self.visible_var("PATH") {
+            for dir in env::split_paths(&path_var) {
+                let Ok(entries) = fs::read_dir(&dir) else {
+                    continue;
+                };
+                for entry in entries.flatten() {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if name.starts_with(word) && Self::is_executable(&entry.path()) {
+                        names.insert(name);
+                    }
+                }
+            }
+        }
+
+        names
+            .into_iter()
+            .map(|name| Candidate {
+                replacement: format!("{} ", name),
+                display: name,
+            })
+            .collect()
+    }
+
+    /// Complete `$VAR` / `${VAR` names the permission manager allows
+    fn complete_variable(&self, word: &str) -> Vec<Candidate> {
+        let (sigil, prefix) = match word.strip_prefix("${") {
+            Some(rest) => ("${", rest),
+            None => ("$", &word[1..]),
+        };
+        let closing = if sigil == "${" { "}" } else { "" };
+
+        self.visible_var_names()
+            .into_iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| Candidate {
+                replacement: format!("{}{}{}", sigil, name, closing),
+                display: name,
+            })
+            .collect()
+    }
+
+    /// Complete variable names for `allow` and `deny`
+    fn complete_env_var_name(&self, word: &str, builtin: &str) -> Vec<Candidate> {
+        env::vars()
+            .map(|(name, _)| name)
+            .filter(|name| name.starts_with(word))
+            .filter(|name| {
+                // Only offer names whose permission would actually change
+                let current = self.permissions.check_env_var(name);
+                if builtin == "allow" {
+                    current != Permission::Allowed
+                } else {
+                    current != Permission::Denied
+                }
+            })
+            .map(|name| Candidate {
+                replacement: format!("{} ", name),
+                display: name,
+            })
+            .collect()
+    }
+
+    /// Complete file paths, optionally limited to what the sandbox can reach
+    fn complete_path(&self, word: &str, sandboxed: bool, dirs_only: bool) -> Vec<Candidate> {
+        let (dir_part, file_prefix) = match word.rfind('/') {
+            Some(i) => (&word[..=i], &word[i + 1..]),
+            None => ("", word),
+        };
+
+        let Ok(work_dir) = env::current_dir() else {
+            return Vec::new();
+        };
+        let dir = if dir_part.is_empty() {
+            work_dir.clone()
+        } else {
+            let expanded = Self::expand_tilde(dir_part);
+            work_dir.join(expanded)
+        };
+
+        let Ok(entries) = fs::read_dir(&dir) else {
+            return Vec::new();
+        };
+
+        let isolation = LandlockIsolation::new(work_dir).excluding(Executor::audit_log_dir(self.config));
+        let allowed_paths = Executor::effective_allowed_paths(self.permissions, self.config);
+        let denied_paths = self.permissions.list_denied_paths();
+
+        entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if !name.starts_with(file_prefix) {
+                    return None;
+                }
+                // Hidden files only when asked for explicitly
+                if name.starts_with('.') && !file_prefix.starts_with('.') {
+                    return None;
+                }
+
+                let path = entry.path();
+                if sandboxed
+                    && !isolation.can_access(&path, &allowed_paths, &denied_paths)
+                    && !isolation.leads_to_access(&path, &allowed_paths)
+                {
+                    return None;
+                }
+
+                let is_dir = path.is_dir();
+                if dirs_only && !is_dir {
+                    return None;
+                }
+                let suffix = if is_dir { "/" } else { " " };
+                Some(Candidate {
+                    replacement: format!("{}{}", Self::quote_path(&format!("{}{}", dir_part, name)), suffix),
+                    display: if is_dir { format!("{}/", name) } else { name },
+                })
+            })
+            .collect()
+    }
+
+    /// Names of variables expansion would see
+    fn visible_var_names(&self) -> Vec<String> {
+        self.variables.names(self.permissions)
+    }
+
+    /// Value of a variable as expansion would see it
+    fn visible_var(&self, name: &str) -> Option<String> {
+        self.variables.value(name, self.permissions)
+    }
+
+    fn is_executable(path: &Path) -> bool {
+        fs::metadata(path)
+            .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    }
+
+    /// Quote a path for the command line, leaving a leading `~/` unquoted
+    fn quote_path(path: &str) -> String {
+        match path.strip_prefix("~/") {
+            Some(rest) => format!("~/{}", parser::quote(rest)),
+            None => parser::quote(path),
+        }
+    }
+
+    fn expand_tilde(path: &str) -> PathBuf {
+        match (path.strip_prefix('~'), env::var("HOME")) {
+            (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => {
+                PathBuf::from(format!("{}{}", home, rest))
+            }
+            _ => PathBuf::from(path),
+        }
+    }
+}
+
+/// Longest common prefix of all candidate replacements
+pub fn common_prefix(candidates: &[Candidate]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+
+    let mut prefix = first.replacement.as_str();
+    for candidate in &candidates[1..] {
+        let common = prefix
+            .char_indices()
+            .zip(candidate.replacement.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(prefix.len().min(candidate.replacement.len()), |((i, _), _)| i);
+        prefix = &prefix[..common];
+    }
+    prefix.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::builtins::Builtins;
+    use crate::shell::parser::ParsedCommand;
+
+    fn candidate(replacement: &str) -> Candidate {
+        Candidate {
+            replacement: replacement.to_string(),
+            display: replacement.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_builtin_names_are_builtins() {
+        for name in BUILTIN_NAMES {
+            let cmd = ParsedCommand::parse(&format!("{} x=1", name)).unwrap();
+            assert!(Builtins::parse(&cmd).is_some(), "{} is not a builtin", name);
+        }
+    }
+
+    #[test]
+    fn test_complete_builtin() {
+        let permissions = PermissionManager::new();
+        let variables = Variables::new();
+        let config = Config::default();
+        let specs = CompletionSpecs::default();
+        let completer = Completer::new(&permissions, &variables, &config, &specs);
+
+        let completion = completer.complete("allowp", 6);
+        assert_eq!(completion.start, 0);
+        assert!(completion.candidates.contains(&Candidate {
+            replacement: "allowpath ".to_string(),
+            display: "allowpath".to_string(),
+        }));
+    }
+
+    #[test]
+    fn test_complete_variable_respects_permissions() {
+        let permissions = PermissionManager::new();
+        let mut variables = Variables::new();
+        variables.set("DSHELL_TEST_VAR", "1".to_string()).unwrap();
+        let config = Config::default();
+        let specs = CompletionSpecs::default();
+        let completer = Completer::new(&permissions, &variables, &config, &specs);
+
+        let completion = completer.complete("echo $DSHELL_TE", 15);
+        assert_eq!(completion.start, 5);
+        assert_eq!(completion.candidates.len(), 1);
+        assert_eq!(completion.candidates[0].replacement, "$DSHELL_TEST_VAR");
+    }
+
+    #[test]
+    fn test_complete_quotes_paths() {
+        let dir = env::temp_dir().join(format!("dshell-complete-{}", std::process::id()));
+        fs::create_dir_all(dir.join("my dir")).unwrap();
+        fs::write(dir.join("my notes.txt"), "").unwrap();
+        let permissions = PermissionManager::new();
+        let variables = Variables::new();
+        let config = Config::default();
+        let specs = CompletionSpecs::default();
+        let completer = Completer::new(&permissions, &variables, &config, &specs);
+        let dir = dir.display().to_string();
+
+        // An escaped or quoted word with a space is one word
+        for typed in [format!("cat {}/my\\ no", dir), format!("cat '{}/my no", dir)] {
+            let completion = completer.complete(&typed, typed.len());
+            assert_eq!(completion.start, 4);
+            assert_eq!(completion.candidates, vec![Candidate {
+                replacement: format!("'{}/my notes.txt' ", dir),
+                display: "my notes.txt".to_string(),
+            }]);
+        }
+        let typed = format!("cd {}/my", dir);
+        let replacements: Vec<String> = completer.complete(&typed, typed.len()).candidates.into_iter().map(|c| c.replacement).collect();
+        assert_eq!(replacements, vec![format!("'{}/my dir'/", dir), format!("'{}/my notes.txt' ", dir)]);
+
+        // Only the words after the last operator belong to the command
+        let completion = completer.complete("echo hi; allowp", 15);
+        assert_eq!(completion.start, 9);
+        assert!(completion.candidates.iter().any(|c| c.replacement == "allowpath "));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_common_prefix() {
+        assert_eq!(common_prefix(&[candidate("cargo "), candidate("cat ")]), "ca");
+        assert_eq!(common_prefix(&[candidate("src/")]), "src/");
+        assert_eq!(common_prefix(&[]), "");
+    }
+}
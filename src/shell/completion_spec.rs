@@ -0,0 +1,240 @@
+//! Programmable per-command completion specs
+//!
+//! Specs are TOML files in ~/.config/dshell/completions, one per command:
+//!
+//! ```toml
+//! command = "kubectl"
+//! flags = ["--help", "--namespace"]
+//! flag_args = { "--namespace" = "none", "-f" = "path" }
+//!
+//! [[subcommands]]
+//! name = "get"
+//! choices = ["pods", "services", "deployments"]
+//!
+//! [[subcommands]]
+//! name = "logs"
+//! flags = ["--follow", "--tail"]
+//! ```
+//!
+//! Subcommands nest to any depth and accept the same keys as the top level.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// What a positional argument (or flag value) should be completed as
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgKind {
+    /// Files and directories the sandbox can reach
+    #[default]
+    Path,
+    /// Directories only
+    Directory,
+    /// Executables on the filtered PATH
+    Command,
+    /// Environment variable names
+    Variable,
+    /// Nothing beyond `choices`
+    None,
+}
+
+/// Completion rules for a command or one of its subcommands
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CommandSpec {
+    #[serde(alias = "command")]
+    pub name: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub subcommands: Vec<CommandSpec>,
+    #[serde(default)]
+    pub flags: Vec<String>,
+    /// Flags that take a value, and what kind of value
+    #[serde(default)]
+    pub flag_args: HashMap<String, ArgKind>,
+    /// Kind of positional arguments
+    #[serde(default)]
+    pub args: ArgKind,
+    /// Fixed values offered for positional arguments
+    #[serde(default)]
+    pub choices: Vec<String>,
+}
+
+/// What the completion engine should offer for the current word
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpecCompletion {
+    /// Literal words (subcommands, flags, choices)
+    pub words: Vec<String>,
+    /// Additional dynamic completions, if any
+    pub args: ArgKind,
+}
+
+impl CommandSpec {
+    fn matches(&self, word: &str) -> bool {
+        self.name == word || self.aliases.iter().any(|a| a == word)
+    }
+
+    /// Resolve completions for `word`, given the words typed after the command
+    pub fn resolve(&self, preceding: &[&str], word: &str) -> SpecCompletion {
+        let mut spec = self;
+        let mut expecting_flag_value: Option<ArgKind> = None;
+        let mut positional_seen = false;
+
+        for arg in preceding {
+            if expecting_flag_value.take().is_some() {
+                continue;
+            }
+            if arg.starts_with('-') {
+                let flag = arg.split('=').next().unwrap_or(arg);
+                if !arg.contains('=') {
+                    expecting_flag_value = spec.flag_args.get(flag).copied();
+                }
+                continue;
+            }
+            match spec.subcommands.iter().find(|sub| sub.matches(arg)) {
+                Some(sub) if !positional_seen => spec = sub,
+                _ => positional_seen = true,
+            }
+        }
+
+        if let Some(kind) = expecting_flag_value {
+            return SpecCompletion { words: Vec::new(), args: kind };
+        }
+
+        if word.starts_with('-') {
+            let words = spec
+                .flags
+                .iter()
+                .chain(spec.flag_args.keys())
+                .filter(|flag| flag.starts_with(word))
+                .cloned()
+                .collect();
+            return SpecCompletion { words, args: ArgKind::None };
+        }
+
+        let mut words: Vec<String> = Vec::new();
+        if !positional_seen {
+            words.extend(spec.subcommands.iter().map(|sub| sub.name.clone()));
+        }
+        words.extend(spec.choices.iter().cloned());
+        words.retain(|w| w.starts_with(word));
+
+        // Commands with subcommands complete only those until one is chosen
+        let args = if !spec.subcommands.is_empty() && !positional_seen && spec.choices.is_empty() {
+            ArgKind::None
+        } else {
+            spec.args
+        };
+
+        SpecCompletion { words, args }
+    }
+}
+
+/// All completion specs loaded from the completions directory
+#[derive(Debug, Default)]
+pub struct CompletionSpecs {
+    specs: HashMap<String, CommandSpec>,
+}
+
+impl CompletionSpecs {
+    /// Load every `*.toml` spec in `dir`, reporting (but skipping) bad files
+    pub fn load(dir: &Path) -> Self {
+        let mut specs = HashMap::new();
+
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Self { specs };
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("toml") {
+                continue;
+            }
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            match toml::from_str::<CommandSpec>(&content) {
+                Ok(spec) => {
+                    for alias in &spec.aliases {
+                        specs.insert(alias.clone(), spec.clone());
+                    }
+                    specs.insert(spec.name.clone(), spec);
+                }
+                Err(e) => {
+                    eprintln!("⚠ Failed to parse completion spec {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        Self { specs }
+    }
+
+    /// Look up the spec for a command, by name or basename of a path
+    pub fn get(&self, command: &str) -> Option<&CommandSpec> {
+        let basename = command.rsplit('/').next().unwrap_or(command);
+        self.specs.get(basename)
+    }
+
+    pub fn len(&self) -> usize {
+        self.specs.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIT_SPEC: &str = r#"
+        command = "git"
+        flags = ["--version", "--help"]
+        flag_args = { "-C" = "directory" }
+
+        [[subcommands]]
+        name = "commit"
+        flags = ["--amend", "--all"]
+        flag_args = { "-m" = "none" }
+
+        [[subcommands]]
+        name = "remote"
+        [[subcommands.subcommands]]
+        name = "add"
+        args = "none"
+        [[subcommands.subcommands]]
+        name = "remove"
+        aliases = ["rm"]
+    "#;
+
+    fn git() -> CommandSpec {
+        toml::from_str(GIT_SPEC).unwrap()
+    }
+
+    #[test]
+    fn test_subcommands() {
+        let completion = git().resolve(&[], "co");
+        assert_eq!(completion.words, vec!["commit"]);
+        assert_eq!(completion.args, ArgKind::None);
+    }
+
+    #[test]
+    fn test_nested_subcommands() {
+        let completion = git().resolve(&["remote"], "");
+        assert_eq!(completion.words, vec!["add", "remove"]);
+    }
+
+    #[test]
+    fn test_flags_of_subcommand() {
+        let mut words = git().resolve(&["commit"], "--a").words;
+        words.sort();
+        assert_eq!(words, vec!["--all", "--amend"]);
+    }
+
+    #[test]
+    fn test_flag_value() {
+        assert_eq!(git().resolve(&["-C"], "").args, ArgKind::Directory);
+        assert_eq!(git().resolve(&["commit", "-m"], "").args, ArgKind::None);
+        // After the flag value, positional completion resumes
+        assert_eq!(git().resolve(&["commit", "-m", "msg"], "").args, ArgKind::Path);
+    }
+}
@@ -0,0 +1,292 @@
+//! `.env` files and the `dotenv` builtin
+//!
+//! Parses dotenv syntax: `KEY=VALUE` lines with an optional `export`
+//! prefix, `#` comments, single quotes (literal), double quotes (escapes,
+//! may span lines) and `${VAR}`, `${VAR:-default}` and `$VAR` references in
+//! unquoted and double-quoted values. References resolve to variables
+//! defined earlier in the file, then to a lookup supplied by the caller.
+//! The builtin shows the names it found and asks which to load, so values
+//! never appear in the output.
+
+use super::variables::is_valid_name;
+use std::path::PathBuf;
+
+/// The file `dotenv` loads without an argument
+pub const DEFAULT_FILE: &str = ".env";
+
+/// Arguments of the `dotenv` builtin
+#[derive(Debug, Clone, PartialEq)]
+pub struct DotenvCommand {
+    pub path: PathBuf,
+}
+
+impl DotenvCommand {
+    /// Parse `dotenv [FILE]`
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        match args {
+            [] => Ok(DotenvCommand { path: PathBuf::from(DEFAULT_FILE) }),
+            [file] => Ok(DotenvCommand { path: PathBuf::from(file) }),
+            _ => Err("dotenv: usage: dotenv [FILE]".to_string()),
+        }
+    }
+}
+
+/// Parse the contents of a `.env` file
+///
+/// Returns the variables in file order (a later definition replaces an
+/// earlier one) and a message for each line that couldn't be parsed.
+pub fn parse(content: &str, lookup: impl Fn(&str) -> Option<String>) -> (Vec<(String, String)>, Vec<String>) {
+    let mut vars: Vec<(String, String)> = Vec::new();
+    let mut errors = Vec::new();
+    let lines: Vec<&str> = content.lines().collect();
+
+    let mut index = 0;
+    while index < lines.len() {
+        let number = index + 1;
+        let line = lines[index].trim_start();
+        index += 1;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").map_or(line, str::trim_start);
+        let Some((name, rest)) = line.split_once('=') else {
+            errors.push(format!("line {}: expected KEY=VALUE", number));
+            continue;
+        };
+        let name = name.trim_end();
+        if !is_valid_name(name) {
+            errors.push(format!("line {}: {:?} is not a valid variable name", number, name));
+            continue;
+        }
+
+        let resolve = |reference: &str| {
+            vars.iter()
+                .rev()
+                .find(|(defined, _)| defined == reference)
+                .map(|(_, value)| value.clone())
+                .or_else(|| lookup(reference))
+        };
+        let rest = rest.trim_start();
+        let value = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                // Quoted values may continue on the following lines
+                let mut text = rest[1..].to_string();
+                let end = loop {
+                    if let Some(end) = closing_quote(&text, quote) {
+                        break Some(end);
+                    }
+                    match lines.get(index) {
+                        Some(next) => {
+                            text.push('\n');
+                            text.push_str(next);
+                            index += 1;
+                        }
+                        None => break None,
+                    }
+                };
+                let Some(end) = end else {
+                    errors.push(format!("line {}: unterminated {} quote", number, quote));
+                    continue;
+                };
+                let after = text[end + 1..].trim_start();
+                if !after.is_empty() && !after.starts_with('#') {
+                    errors.push(format!("line {}: unexpected text after the closing quote", number));
+                    continue;
+                }
+                if quote == '\'' {
+                    text[..end].to_string()
+                } else {
+                    interpolate(&unescape(&text[..end]), &resolve)
+                }
+            }
+            _ => {
+                // An unquoted value ends at a comment, which must follow whitespace
+                let end = rest
+                    .char_indices()
+                    .find(|&(i, c)| c == '#' && rest[..i].ends_with(char::is_whitespace))
+                    .map_or(rest.len(), |(i, _)| i);
+                interpolate(rest[..end].trim_end(), &resolve)
+            }
+        };
+
+        vars.retain(|(defined, _)| defined != name);
+        vars.push((name.to_string(), value));
+    }
+    (vars, errors)
+}
+
+/// Position of the quote that ends a value, skipping escaped double quotes
+fn closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '\\' if quote == '"' && !escaped => escaped = true,
+            c if c == quote && !escaped => return Some(i),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Resolve backslash escapes in a double-quoted value; `\$` is kept so
+/// interpolation leaves it alone
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('$') => result.push_str("\\$"),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Replace `${VAR}`, `${VAR:-default}` and `$VAR` references; unknown
+/// variables become empty, and `\$` is a literal `$`
+fn interpolate(text: &str, resolve: &impl Fn(&str) -> Option<String>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find(['$', '\\']) {
+        result.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        if rest[pos..].starts_with("\\$") {
+            result.push('$');
+            rest = &rest[pos + 2..];
+        } else if rest[pos..].starts_with('\\') {
+            result.push('\\');
+            rest = after;
+        } else if let Some(braced) = after.strip_prefix('{')
+            && let Some(close) = braced.find('}')
+        {
+            let reference = &braced[..close];
+            let (name, default) = match reference.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (reference, None),
+            };
+            match (resolve(name).filter(|value| !value.is_empty()), default) {
+                (Some(value), _) => result.push_str(&value),
+                (None, Some(default)) => result.push_str(default),
+                (None, None) => {}
+            }
+            rest = &braced[close + 1..];
+        } else {
+            let len = after.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(after.len());
+            if len == 0 {
+                result.push('$');
+            } else {
+                result.push_str(&resolve(&after[..len]).unwrap_or_default());
+            }
+            rest = &after[len..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Which of `names` an answer to the load prompt picks: `all`, `none`, or
+/// numbers and names separated by spaces or commas
+pub fn select(answer: &str, names: &[&str]) -> Result<Vec<usize>, String> {
+    match answer.trim().to_ascii_lowercase().as_str() {
+        "a" | "all" | "y" | "yes" => return Ok((0..names.len()).collect()),
+        "" | "n" | "no" | "none" => return Ok(Vec::new()),
+        _ => {}
+    }
+    let mut selected = Vec::new();
+    for word in answer.split([' ', ',']).filter(|word| !word.is_empty()) {
+        let index = match word.parse::<usize>() {
+            Ok(number) if (1..=names.len()).contains(&number) => number - 1,
+            Ok(number) => return Err(format!("{}: no such variable number", number)),
+            Err(_) => names.iter().position(|name| *name == word).ok_or_else(|| format!("{}: not in the file", word))?,
+        };
+        if !selected.contains(&index) {
+            selected.push(index);
+        }
+    }
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_vars(content: &str) -> Vec<(String, String)> {
+        let (vars, errors) = parse(content, |name| (name == "HOME").then(|| "/home/me".to_string()));
+        assert!(errors.is_empty(), "{:?}", errors);
+        vars
+    }
+
+    fn var(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_parse_values() {
+        let vars = parse_vars(
+            "# database\n\
+             export DB_URL=postgres://u:p@host/db?sslmode=require  # comment\n\
+             COLOR=#fff\n\
+             SPACED = 'single  $HOME'\n\
+             QUOTED=\"tab\\there \\\"x\\\" \\$HOME\"\n\
+             EMPTY=\n",
+        );
+        assert_eq!(
+            vars,
+            vec![
+                var("DB_URL", "postgres://u:p@host/db?sslmode=require"),
+                var("COLOR", "#fff"),
+                var("SPACED", "single  $HOME"),
+                var("QUOTED", "tab\there \"x\" $HOME"),
+                var("EMPTY", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interpolation_and_multiline() {
+        let vars = parse_vars(
+            "DIR=${HOME}/app\n\
+             LOG=$DIR/log\n\
+             LEVEL=${UNSET:-info}\n\
+             KEY=\"-----BEGIN KEY-----\nabc\n-----END KEY-----\"\n\
+             DIR=changed\n",
+        );
+        assert_eq!(
+            vars,
+            vec![
+                var("LOG", "/home/me/app/log"),
+                var("LEVEL", "info"),
+                var("KEY", "-----BEGIN KEY-----\nabc\n-----END KEY-----"),
+                var("DIR", "changed"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let (vars, errors) = parse("GOOD=1\nno equals\n1BAD=x\nOPEN=\"never closed\n", |_| None);
+        assert_eq!(vars, vec![var("GOOD", "1")]);
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].starts_with("line 2"));
+        assert!(errors[2].contains("unterminated"));
+    }
+
+    #[test]
+    fn test_select() {
+        let names = ["A", "B", "C"];
+        assert_eq!(select("all", &names), Ok(vec![0, 1, 2]));
+        assert_eq!(select("", &names), Ok(vec![]));
+        assert_eq!(select("1, C 1", &names), Ok(vec![0, 2]));
+        assert!(select("4", &names).is_err());
+        assert!(select("D", &names).is_err());
+    }
+}
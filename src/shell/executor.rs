@@ -1,12 +1,21 @@
-/// Command execution module
+//! Command execution module
 
+use super::cgroup::CgroupManager;
+use super::limits::{self, ChildLimits, Watchdog};
 use super::parser::ParsedCommand;
-use crate::config::Config;
-use crate::security::{PermissionManager, LandlockIsolation, IsolationStatus};
-use std::collections::HashMap;
+use super::pty::PtyProcess;
+use super::timing::{self, Reaper, Rusage, Timing};
+use super::variables::Variables;
+use crate::config::{Config, ResourceLimits};
+use crate::security::{PermissionManager, LandlockIsolation, IsolationReport, IsolationSender, IsolationStatus};
 use std::env;
-use std::io;
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ExecutionMode {
@@ -17,94 +26,313 @@ pub enum ExecutionMode {
 #[derive(Debug)]
 pub struct CommandResult {
     pub output: Vec<String>,
+    /// How long the command took and what it used, if it started
+    pub timing: Option<Timing>,
+    /// What isolation the command reported, if it got that far
+    pub isolation: Option<IsolationStatus>,
+}
+
+/// How a foreground command stopped running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitStatus {
+    Exited(i32),
+    /// Killed by a signal
+    Signaled(i32),
+    /// Suspended with Ctrl+Z; the process group with this ID can be resumed
+    Stopped(i32),
+}
+
+impl WaitStatus {
+    /// Decode a status from `waitpid` or `wait4` for the child `pid`
+    pub fn from_raw(pid: i32, status: libc::c_int) -> Self {
+        if libc::WIFSTOPPED(status) {
+            WaitStatus::Stopped(pid)
+        } else if libc::WIFSIGNALED(status) {
+            WaitStatus::Signaled(libc::WTERMSIG(status))
+        } else {
+            WaitStatus::Exited(libc::WEXITSTATUS(status))
+        }
+    }
+
+    /// The status as `$?` reports it: the exit code, or 128 plus the signal
+    pub fn code(&self) -> i32 {
+        match self {
+            WaitStatus::Exited(code) => *code,
+            WaitStatus::Signaled(signal) => 128 + signal,
+            WaitStatus::Stopped(_) => 128 + libc::SIGTSTP,
+        }
+    }
+
+    /// A message for commands killed by a signal
+    pub fn describe(&self) -> Option<String> {
+        match self {
+            WaitStatus::Exited(_) | WaitStatus::Stopped(_) => None,
+            WaitStatus::Signaled(libc::SIGINT) => Some("✗ Interrupted".to_string()),
+            WaitStatus::Signaled(libc::SIGQUIT) => Some("✗ Quit".to_string()),
+            WaitStatus::Signaled(libc::SIGKILL) => Some("✗ Killed".to_string()),
+            WaitStatus::Signaled(libc::SIGTERM) => Some("✗ Terminated".to_string()),
+            WaitStatus::Signaled(signal) => Some(format!("✗ Killed by signal {}", signal)),
+        }
+    }
+}
+
+/// A captured command running with its output piped back to dshell
+pub struct CapturedProcess {
+    reaper: Reaper,
+    /// Lines from stdout and stderr, in the order they arrive
+    output: Receiver<String>,
+}
+
+impl CapturedProcess {
+    /// The process ID, which is also its process group ID
+    pub fn pid(&self) -> i32 {
+        self.reaper.pid()
+    }
+
+    /// Lines written since the last call, without waiting
+    pub fn read_lines(&self) -> Vec<String> {
+        self.output.try_iter().collect()
+    }
+
+    pub fn try_wait(&mut self) -> io::Result<Option<WaitStatus>> {
+        self.reaper.try_wait()
+    }
+
+    /// Collect the output still in flight after the command exited, giving
+    /// up after `timeout` in case something it started keeps the pipes open
+    pub fn drain(&self, timeout: Duration) -> Vec<String> {
+        let deadline = Instant::now() + timeout;
+        let mut lines = Vec::new();
+        while let Ok(line) = self.output.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Wait for the command to exit and return the rest of its output,
+    /// how it ended and what it used
+    pub fn finish(mut self) -> (Vec<String>, WaitStatus, Rusage) {
+        let (status, usage) = self.reaper.wait().unwrap_or((WaitStatus::Exited(-1), Rusage::default()));
+        // Blocks until both pipes are closed
+        (self.output.iter().collect(), status, usage)
+    }
 }
 
 pub struct Executor;
 
 impl Executor {
     /// Determine if a command should run in interactive mode
+    ///
+    /// The first matching rule in `interactive_rules` decides. Otherwise the
+    /// command is interactive if it matches an `interactive_commands` entry:
+    /// the program's basename, followed by any leading arguments listed.
     pub fn execution_mode(cmd: &ParsedCommand, config: &Config) -> ExecutionMode {
-        if config.interactive_commands.iter().any(|c| c == &cmd.command) {
+        let program = Self::program_name(cmd);
+
+        let interactive = match config.interactive_rules.iter().find(|rule| rule.matches(program, &cmd.args)) {
+            Some(rule) => rule.interactive,
+            None => config.interactive_commands.iter().any(|entry| {
+                let mut words = entry.split_whitespace();
+                words.next() == Some(program) && words.enumerate().all(|(i, word)| cmd.args.get(i).is_some_and(|a| a == word))
+            }),
+        };
+
+        if interactive {
             ExecutionMode::Interactive
         } else {
             ExecutionMode::Captured
         }
     }
 
-    /// Execute a command and capture its output with filtered environment
-    pub fn execute_captured(cmd: &ParsedCommand, permissions: &PermissionManager, custom_env: &HashMap<String, String>) -> CommandResult {
-        let mut command = Command::new(&cmd.command);
-        command.args(&cmd.args);
+    /// The basename of a command's program, which config entries match
+    fn program_name(cmd: &ParsedCommand) -> &str {
+        Path::new(&cmd.command)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(&cmd.command)
+    }
 
-        // Clear all environment variables and only add allowed ones
-        command.env_clear();
+    /// The resource limits a command runs with
+    pub fn resource_limits(cmd: &ParsedCommand, config: &Config) -> ResourceLimits {
+        limits::for_program(Self::program_name(cmd), config)
+    }
 
-        // Add custom env vars first
-        for (key, value) in custom_env {
-            command.env(key, value);
-        }
+    /// Execute a command and capture its output with filtered environment
+    /// and Landlock filesystem isolation
+    ///
+    /// A message is added when the command was stopped by one of its limits.
+    pub fn execute_captured(
+        cmd: &ParsedCommand,
+        permissions: &PermissionManager,
+        variables: &Variables,
+        config: &Config,
+        cgroups: Option<&CgroupManager>,
+    ) -> CommandResult {
+        let watchdog = Watchdog::start(Self::resource_limits(cmd, config), cgroups);
+        let spawned = IsolationReport::new().and_then(|report| {
+            let process = Self::spawn_captured(cmd, permissions, variables, config, &watchdog.child_limits(), report.sender())?;
+            Ok((process, report.receive()))
+        });
+        let (mut process, isolation) = match spawned {
+            Ok(spawned) => spawned,
+            Err(e) => {
+                return CommandResult {
+                    output: Self::spawn_error(cmd, &e),
+                    timing: None,
+                    isolation: None,
+                }
+            }
+        };
 
-        // Add system env vars (permissions respected)
-        for (key, value) in permissions.get_allowed_env_vars() {
-            // Only add if not redacted and not already in custom_env
-            if !value.starts_with("[REDACTED") && !custom_env.contains_key(&key) {
-                command.env(key, value);
+        // Kill the command once it runs past its timeout
+        while watchdog.deadline().is_some() && matches!(process.try_wait(), Ok(None)) {
+            if watchdog.expired() {
+                watchdog.kill(process.pid());
+                break;
             }
+            thread::sleep(Duration::from_millis(10));
         }
 
-        match command
+        let (mut output, status, usage) = process.finish();
+        output.extend(watchdog.describe(&status));
+        CommandResult { output, timing: Some(watchdog.timing(status, usage)), isolation }
+    }
+
+    /// Start a captured command with its output piped back line by line
+    ///
+    /// The command gets its own process group, so signals from Ctrl+C and
+    /// Ctrl+Z reach it and anything it starts, but not dshell. It runs with
+    /// the same Landlock filesystem isolation as interactive commands, and
+    /// the isolation it got is sent to `report`. Background jobs are started
+    /// the same way.
+    pub fn spawn_captured(
+        cmd: &ParsedCommand,
+        permissions: &PermissionManager,
+        variables: &Variables,
+        config: &Config,
+        limits: &ChildLimits,
+        report: IsolationSender,
+    ) -> io::Result<CapturedProcess> {
+        let isolation = Self::isolation(config)?;
+        let allowed_paths = Self::effective_allowed_paths(permissions, config);
+
+        let mut command = Command::new(&cmd.command);
+        command.args(&cmd.args);
+
+        // Clear all environment variables and only add allowed ones
+        Self::apply_filtered_env(&mut command, permissions, variables);
+        command.env("DSHELL_RESTRICTED", "1");
+
+        command
+            .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
-            .output()
-        {
-            Ok(output) => {
-                let mut lines = Vec::new();
-
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                for line in stdout.lines() {
-                    lines.push(line.to_string());
+            .process_group(0);
+        Self::restrict_child(&mut command, isolation, allowed_paths, limits, report);
+        let mut child = command.spawn()?;
+
+        // Don't prefix stderr with "Error:" - it contains all diagnostic output,
+        // not just errors (progress messages, warnings, info, etc.)
+        let (sender, output) = mpsc::channel();
+        let stdout: Box<dyn Read + Send> = Box::new(child.stdout.take().expect("stdout is piped"));
+        let stderr: Box<dyn Read + Send> = Box::new(child.stderr.take().expect("stderr is piped"));
+        for stream in [stdout, stderr] {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stream).split(b'\n').map_while(Result::ok) {
+                    let line = String::from_utf8_lossy(&line);
+                    let line = line.strip_suffix('\r').unwrap_or(&line);
+                    // Keep reading after the receiver is gone (a disowned
+                    // job), so the command never blocks on a full pipe
+                    let _ = sender.send(line.to_string());
                 }
+            });
+        }
 
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                for line in stderr.lines() {
-                    // Don't prefix with "Error:" - stderr contains all diagnostic output,
-                    // not just errors (progress messages, warnings, info, etc.)
-                    lines.push(line.to_string());
-                }
+        Ok(CapturedProcess { reaper: Reaper::new(child.id() as i32), output })
+    }
 
-                CommandResult {
-                    output: lines,
-                }
-            }
-            Err(e) => {
-                let mut error_msg = vec![format!("dshell: {}: {}", cmd.command, e)];
-
-                // Add helpful hints for common errors
-                if e.kind() == io::ErrorKind::NotFound {
-                    error_msg.push(format!("  Hint: Command '{}' not found in PATH", cmd.command));
-                    error_msg.push(format!("  Current PATH: {}", std::env::var("PATH").unwrap_or_else(|_| "not set".to_string())));
-                } else if e.kind() == io::ErrorKind::PermissionDenied {
-                    error_msg.push(format!("  Hint: Permission denied for '{}'", cmd.command));
-                    error_msg.push("  Check if the file is executable".to_string());
-                }
+    /// Start a captured command on a pseudo-terminal of the given size
+    ///
+    /// The command gets the same filtered environment and isolation as
+    /// `spawn_captured`, plus `TERM` so it knows what escape sequences the
+    /// terminal supports. It keeps them if it later takes over the terminal.
+    pub fn spawn_in_pty(
+        cmd: &ParsedCommand,
+        permissions: &PermissionManager,
+        variables: &Variables,
+        config: &Config,
+        size: (u16, u16),
+        limits: &ChildLimits,
+        report: IsolationSender,
+    ) -> io::Result<PtyProcess> {
+        let isolation = Self::isolation(config)?;
+        let allowed_paths = Self::effective_allowed_paths(permissions, config);
 
-                CommandResult {
-                    output: error_msg,
-                }
-            },
+        let mut command = Command::new(&cmd.command);
+        command.args(&cmd.args);
+        Self::apply_filtered_env(&mut command, permissions, variables);
+        command.env("DSHELL_RESTRICTED", "1").env("TERM", "xterm-256color");
+        Self::restrict_child(&mut command, isolation, allowed_paths, limits, report);
+
+        PtyProcess::spawn(command, size.0, size.1)
+    }
+
+    /// Apply resource limits and Landlock isolation in the child between
+    /// fork and exec, reporting the isolation it got
+    fn restrict_child(
+        command: &mut Command,
+        isolation: LandlockIsolation,
+        allowed_paths: Vec<String>,
+        limits: &ChildLimits,
+        report: IsolationSender,
+    ) {
+        let limits = limits.clone();
+        unsafe {
+            command.pre_exec(move || {
+                Self::restore_signals();
+                // Before Landlock, which would deny writing to the cgroup
+                limits.apply()?;
+                report.send(isolation.restrict_filesystem(&allowed_paths)?);
+                Ok(())
+            });
         }
     }
 
+    /// Error lines for a command that couldn't be started
+    pub fn spawn_error(cmd: &ParsedCommand, e: &io::Error) -> Vec<String> {
+        let mut error_msg = vec![format!("dshell: {}: {}", cmd.command, e)];
+
+        // Add helpful hints for common errors
+        if e.kind() == io::ErrorKind::NotFound {
+            error_msg.push(format!("  Hint: Command '{}' not found in PATH", cmd.command));
+            error_msg.push(format!("  Current PATH: {}", std::env::var("PATH").unwrap_or_else(|_| "not set".to_string())));
+        } else if e.kind() == io::ErrorKind::PermissionDenied {
+            error_msg.push(format!("  Hint: Permission denied for '{}'", cmd.command));
+            error_msg.push("  Check if the file is executable".to_string());
+        }
+        error_msg
+    }
+
     /// Execute a command in interactive mode with Landlock filesystem isolation
     ///
     /// This function will:
-    /// 1. Fork a child process
+    /// 1. Fork a child process into its own process group, which becomes
+    ///    the terminal's foreground group
     /// 2. Apply Landlock restrictions in the child (restricts to current directory)
     /// 3. Execute the command with filtered environment
-    /// 4. Return the exit code
+    /// 4. Wait until it exits, is stopped or runs past the watchdog's
+    ///    timeout, then take the terminal back
     ///
     /// If Landlock is not available, falls back to warning-only mode.
-    pub fn execute_interactive(cmd: &ParsedCommand, permissions: &PermissionManager, custom_env: &HashMap<String, String>, config: &Config) -> io::Result<IsolationStatus> {
+    pub fn execute_interactive(
+        cmd: &ParsedCommand,
+        permissions: &PermissionManager,
+        variables: &Variables,
+        config: &Config,
+        watchdog: &Watchdog,
+        report: IsolationSender,
+    ) -> io::Result<(WaitStatus, Rusage)> {
         let work_dir = env::current_dir()?;
 
         // Fork the process
@@ -118,51 +346,216 @@ impl Executor {
                 Err(err)
             }
             0 => {
-                // Child process - apply Landlock restrictions and execute command
-                let _isolation_status = Self::execute_in_isolated_child(cmd, permissions, custom_env, work_dir, config);
+                // Child process - take over the terminal in a new process group.
+                // Both sides do this, so neither depends on who runs first
+                unsafe {
+                    libc::setpgid(0, 0);
+                    Self::give_terminal(libc::getpid());
+                    Self::restore_signals();
+                }
+
+                // Apply Landlock restrictions and execute command
+                let _isolation_status = Self::execute_in_isolated_child(cmd, permissions, variables, work_dir, config, &watchdog.child_limits(), report);
 
                 // If we reach here, exec failed
                 eprintln!("Error: exec failed for command: {}", cmd.command);
                 std::process::exit(127);
             }
             child_pid => {
-                // Parent process - wait for child to complete
-                let mut status: libc::c_int = 0;
-                let wait_result = unsafe { libc::waitpid(child_pid, &mut status, 0) };
-
-                if wait_result == -1 {
-                    let err = io::Error::last_os_error();
-                    eprintln!("Error: waitpid failed: {}", err);
-                    return Err(err);
+                // Parent process - hand over the terminal and wait for the child
+                unsafe {
+                    libc::setpgid(child_pid, child_pid);
+                    Self::give_terminal(child_pid);
                 }
+                let status = Self::wait_foreground(child_pid, watchdog);
 
                 // Check if child exited with an error
-                if libc::WIFEXITED(status) {
-                    let exit_code = libc::WEXITSTATUS(status);
-                    if exit_code == 127 {
-                        eprintln!("Error: Command not found or failed to execute: {}", cmd.command);
+                if let Ok((WaitStatus::Exited(127), _)) = status {
+                    eprintln!("Error: Command not found or failed to execute: {}", cmd.command);
+                }
+                status
+            }
+        }
+    }
+
+    /// Continue a stopped interactive command in the foreground
+    pub fn resume_interactive(pid: i32, watchdog: &Watchdog) -> io::Result<(WaitStatus, Rusage)> {
+        unsafe { Self::give_terminal(pid) };
+        Self::signal_group(pid, libc::SIGCONT)?;
+        Self::wait_foreground(pid, watchdog)
+    }
+
+    /// Wait for a foreground child to exit or stop, then take the terminal back
+    ///
+    /// The child is killed if it is still running past the watchdog's
+    /// timeout.
+    fn wait_foreground(pid: i32, watchdog: &Watchdog) -> io::Result<(WaitStatus, Rusage)> {
+        let mut deadline = watchdog.deadline();
+        let result = loop {
+            let flags = if deadline.is_some() { libc::WUNTRACED | libc::WNOHANG } else { libc::WUNTRACED };
+            match timing::wait4(pid, flags) {
+                Ok(None) => {
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        watchdog.kill(pid);
+                        deadline = None;
                     }
+                    thread::sleep(Duration::from_millis(10));
                 }
+                Ok(Some(exit)) => break Ok(exit),
+                Err(err) => {
+                    eprintln!("Error: waitpid failed: {}", err);
+                    break Err(err);
+                }
+            }
+        };
+        unsafe { Self::give_terminal(libc::getpgrp()) };
+        result
+    }
 
-                // For now, return NotEnforced as parent doesn't know child's isolation status
-                // We could use a pipe to communicate this back if needed
-                Ok(IsolationStatus::NotEnforced)
+    /// Send a signal to a command's process group
+    pub fn signal_group(pgid: i32, signal: i32) -> io::Result<()> {
+        if unsafe { libc::killpg(pgid, signal) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Make dshell a job-control shell: it hands the terminal to foreground
+    /// commands and must not be stopped when taking it back
+    pub fn init_job_control() {
+        unsafe {
+            libc::signal(libc::SIGTSTP, libc::SIG_IGN);
+            libc::signal(libc::SIGTTIN, libc::SIG_IGN);
+            libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        }
+    }
+
+    /// Undo `init_job_control` in a child before it runs a command, since
+    /// ignored signals stay ignored across exec
+    fn restore_signals() {
+        unsafe {
+            for signal in [libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU] {
+                libc::signal(signal, libc::SIG_DFL);
             }
         }
     }
 
-    /// Execute command in isolated child process (called after fork)
-    fn execute_in_isolated_child(
-        cmd: &ParsedCommand,
+    /// Make a process group the terminal's foreground group
+    ///
+    /// # Safety
+    /// Must be called with SIGTTOU ignored, as after `init_job_control`.
+    unsafe fn give_terminal(pgid: i32) {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 1 {
+                libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+            }
+        }
+    }
+
+    /// Run a short-lived helper program under Landlock isolation and capture its stdout
+    ///
+    /// Used when dshell itself runs a program on the user's behalf (e.g. a
+    /// completion helper). The helper gets the same filtered environment and
+    /// filesystem restrictions as interactive commands, no stdin, and is killed
+    /// if it doesn't finish within `timeout`.
+    pub fn run_sandboxed_helper(
+        program: &str,
+        args: &[String],
         permissions: &PermissionManager,
-        custom_env: &HashMap<String, String>,
-        work_dir: std::path::PathBuf,
+        variables: &Variables,
         config: &Config,
-    ) -> IsolationStatus {
-        // Apply Landlock restrictions with allowed paths from permissions and config
-        let isolation = LandlockIsolation::new(work_dir.clone());
+        timeout: Duration,
+    ) -> io::Result<Vec<String>> {
+        let work_dir = env::current_dir()?;
+        let isolation = Self::isolation(config)?;
+        let allowed_paths = Self::effective_allowed_paths(permissions, config);
+
+        let mut command = Command::new(program);
+        command.args(args).current_dir(&work_dir);
+        Self::apply_filtered_env(&mut command, permissions, variables);
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .process_group(0);
+
+        // Restrict the child between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                Self::restore_signals();
+                isolation.restrict_filesystem(&allowed_paths).map(|_| ())
+            });
+        }
 
-        // Combine allowed paths from permissions and config
+        let mut child = command.spawn()?;
+        let pgid = child.id() as i32;
+        let timed_out = |child: &mut std::process::Child| {
+            // Kill the whole group, so processes the helper started go too
+            let _ = Self::signal_group(pgid, libc::SIGKILL);
+            let _ = child.wait();
+            io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out", program))
+        };
+
+        // Read stdout on a separate thread so a chatty helper can't fill the pipe
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let (sender, output) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = stdout.read_to_end(&mut buf);
+            let _ = sender.send(buf);
+        });
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            if child.try_wait()?.is_some() {
+                break;
+            }
+            if Instant::now() >= deadline {
+                return Err(timed_out(&mut child));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // A process the helper left behind may still hold the pipe open
+        let Ok(output) = output.recv_timeout(deadline.saturating_duration_since(Instant::now())) else {
+            return Err(timed_out(&mut child));
+        };
+        Ok(String::from_utf8_lossy(&output).lines().map(|l| l.to_string()).collect())
+    }
+
+    /// Clear a command's environment and add back only what permissions allow
+    ///
+    /// Exported shell variables take precedence over system ones, and
+    /// inherited variables removed with `unset` are left out.
+    /// Variables still awaiting permission are left out entirely.
+    pub fn apply_filtered_env(command: &mut Command, permissions: &PermissionManager, variables: &Variables) {
+        command.env_clear();
+        command.envs(Self::filtered_env(permissions, variables));
+    }
+
+    /// Names of the environment variables a command gets
+    pub fn filtered_env_names(permissions: &PermissionManager, variables: &Variables) -> Vec<String> {
+        let mut names: Vec<String> = Self::filtered_env(permissions, variables).into_iter().map(|(key, _)| key).collect();
+        names.sort();
+        names
+    }
+
+    fn filtered_env(permissions: &PermissionManager, variables: &Variables) -> Vec<(String, String)> {
+        // Add exported shell variables first
+        let mut vars: Vec<(String, String)> = variables.exported().map(|(key, value)| (key.clone(), value.clone())).collect();
+
+        // Add system env vars (permissions respected)
+        for (key, value) in permissions.get_allowed_env_vars() {
+            // Only add if not redacted, unset or set in this session
+            if !value.starts_with("[REDACTED") && !variables.is_unset(&key) && variables.get(&key).is_none() {
+                vars.push((key, value));
+            }
+        }
+        vars
+    }
+
+    /// Combine allowed paths from permissions and config, excluding denied paths
+    pub fn effective_allowed_paths(permissions: &PermissionManager, config: &Config) -> Vec<String> {
         let mut allowed_paths: Vec<String> = permissions.get_allowed_paths().iter().cloned().collect();
 
         // Add additional paths from config, but exclude any denied paths
@@ -187,6 +580,44 @@ impl Executor {
             }
         }
 
+        allowed_paths
+    }
+
+    /// Landlock isolation for a command started in the current directory
+    ///
+    /// Sandboxed commands must not be able to rewrite the audit trail, so
+    /// the log directory is kept out of every rule, including the working
+    /// directory's when it contains it.
+    pub fn isolation(config: &Config) -> io::Result<LandlockIsolation> {
+        Ok(LandlockIsolation::new(env::current_dir()?).excluding(Self::audit_log_dir(config)))
+    }
+
+    /// The directory holding the audit log, when it is enabled
+    pub fn audit_log_dir(config: &Config) -> Option<PathBuf> {
+        Config::state_dir().filter(|_| config.audit.enabled)
+    }
+
+    /// Execute command in isolated child process (called after fork)
+    fn execute_in_isolated_child(
+        cmd: &ParsedCommand,
+        permissions: &PermissionManager,
+        variables: &Variables,
+        work_dir: std::path::PathBuf,
+        config: &Config,
+        limits: &ChildLimits,
+        report: IsolationSender,
+    ) -> IsolationStatus {
+        // Resource limits first, since joining the cgroup writes to a file
+        // Landlock would deny
+        if let Err(e) = limits.apply() {
+            eprintln!("⚠️  Warning: Failed to apply resource limits: {}", e);
+        }
+
+        // Apply Landlock restrictions with allowed paths from permissions and config
+        let isolation = LandlockIsolation::new(work_dir.clone()).excluding(Self::audit_log_dir(config));
+
+        let allowed_paths = Self::effective_allowed_paths(permissions, config);
+
         let isolation_status = match isolation.restrict_filesystem(&allowed_paths) {
             Ok(status) => {
                 // Print status message
@@ -219,11 +650,14 @@ impl Executor {
                 IsolationStatus::NotEnforced
             }
         };
+        report.send(isolation_status);
 
         // Build command with filtered environment
         let mut command = Command::new(&cmd.command);
         command.args(&cmd.args);
-        command.env_clear();
+
+        // Add custom and permitted system env vars
+        Self::apply_filtered_env(&mut command, permissions, variables);
 
         // Set PWD and working directory
         if let Some(pwd) = work_dir.to_str() {
@@ -241,85 +675,56 @@ impl Executor {
         }
         command.current_dir(&work_dir);
 
-        // Add custom env vars
-        for (key, value) in custom_env {
-            command.env(key, value);
-        }
-
-        // Add system env vars (permissions respected)
-        for (key, value) in permissions.get_allowed_env_vars() {
-            if !value.starts_with("[REDACTED") && !custom_env.contains_key(&key) {
-                command.env(key, value);
-            }
-        }
-
-        // Execute the command
-        let status = match command
+        // Replace this process with the command, so job control signals
+        // (Ctrl+Z) reach the command itself and not a waiting middle process
+        let e = command
             .stdin(Stdio::inherit())
             .stdout(Stdio::inherit())
             .stderr(Stdio::inherit())
-            .status()
-        {
-            Ok(s) => s.code().unwrap_or(-1),
-            Err(e) => {
-                eprintln!("\ndshell: Failed to execute '{}': {}", cmd.command, e);
-
-                // Add helpful hints for common errors
-                match e.kind() {
-                    io::ErrorKind::NotFound => {
-                        eprintln!("  Hint: Command '{}' not found", cmd.command);
-                        eprintln!("  Possible reasons:");
-                        eprintln!("    - Command is not installed");
-                        eprintln!("    - Command is not in PATH");
-                        eprintln!("    - Landlock isolation preventing access to the binary");
-                        if let Ok(path) = std::env::var("PATH") {
-                            eprintln!("  Current PATH: {}", path);
-                        } else {
-                            eprintln!("  Warning: PATH environment variable is not set!");
-                        }
-                    }
-                    io::ErrorKind::PermissionDenied => {
-                        eprintln!("  Hint: Permission denied");
-                        eprintln!("  Possible reasons:");
-                        eprintln!("    - File is not executable");
-                        eprintln!("    - Landlock isolation blocking access");
-                        eprintln!("    - Insufficient permissions");
-                    }
-                    _ => {
-                        eprintln!("  Error type: {:?}", e.kind());
-                    }
+            .exec();
+
+        // exec only returns on failure
+        eprintln!("\ndshell: Failed to execute '{}': {}", cmd.command, e);
+
+        // Add helpful hints for common errors
+        match e.kind() {
+            io::ErrorKind::NotFound => {
+                eprintln!("  Hint: Command '{}' not found", cmd.command);
+                eprintln!("  Possible reasons:");
+                eprintln!("    - Command is not installed");
+                eprintln!("    - Command is not in PATH");
+                eprintln!("    - Landlock isolation preventing access to the binary");
+                if let Ok(path) = std::env::var("PATH") {
+                    eprintln!("  Current PATH: {}", path);
+                } else {
+                    eprintln!("  Warning: PATH environment variable is not set!");
                 }
-
-                127 // Standard "command not found" exit code
             }
-        };
+            io::ErrorKind::PermissionDenied => {
+                eprintln!("  Hint: Permission denied");
+                eprintln!("  Possible reasons:");
+                eprintln!("    - File is not executable");
+                eprintln!("    - Landlock isolation blocking access");
+                eprintln!("    - Insufficient permissions");
+            }
+            _ => {
+                eprintln!("  Error type: {:?}", e.kind());
+            }
+        }
 
-        // Exit child process with command's exit code
-        std::process::exit(status);
+        // Standard "command not found" exit code
+        std::process::exit(127);
     }
 
     /// Execute a command in interactive mode WITHOUT filesystem isolation (legacy)
     /// This is kept for backwards compatibility but not recommended
     #[allow(dead_code)]
-    pub fn execute_interactive_no_isolation(cmd: &ParsedCommand, permissions: &PermissionManager, custom_env: &HashMap<String, String>) -> io::Result<i32> {
+    pub fn execute_interactive_no_isolation(cmd: &ParsedCommand, permissions: &PermissionManager, variables: &Variables) -> io::Result<i32> {
         let mut command = Command::new(&cmd.command);
         command.args(&cmd.args);
 
         // Clear all environment variables and only add allowed ones
-        command.env_clear();
-
-        // Add custom env vars first
-        for (key, value) in custom_env {
-            command.env(key, value);
-        }
-
-        // Add system env vars (permissions respected)
-        for (key, value) in permissions.get_allowed_env_vars() {
-            // Only add if not redacted and not already in custom_env
-            if !value.starts_with("[REDACTED") && !custom_env.contains_key(&key) {
-                command.env(key, value);
-            }
-        }
+        Self::apply_filtered_env(&mut command, permissions, variables);
 
         let status = command
             .stdin(Stdio::inherit())
@@ -330,3 +735,93 @@ impl Executor {
         Ok(status.code().unwrap_or(-1))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(line: &str) -> ExecutionMode {
+        let cmd = ParsedCommand::parse(line).unwrap();
+        Executor::execution_mode(&cmd, &Config::default())
+    }
+
+    #[test]
+    fn test_commands_match_by_basename() {
+        assert_eq!(mode("vim notes.txt"), ExecutionMode::Interactive);
+        assert_eq!(mode("/usr/bin/vim notes.txt"), ExecutionMode::Interactive);
+        assert_eq!(mode("ls -la"), ExecutionMode::Captured);
+    }
+
+    #[test]
+    fn test_rules_match_subcommands_and_arguments() {
+        assert_eq!(mode("python"), ExecutionMode::Interactive);
+        assert_eq!(mode("python script.py"), ExecutionMode::Captured);
+        assert_eq!(mode("git log"), ExecutionMode::Interactive);
+        assert_eq!(mode("git status"), ExecutionMode::Captured);
+        assert_eq!(mode("git commit"), ExecutionMode::Interactive);
+        assert_eq!(mode("git commit -m 'fix'"), ExecutionMode::Captured);
+        assert_eq!(mode("git add -p"), ExecutionMode::Interactive);
+        assert_eq!(mode("kubectl exec -it pod -- sh"), ExecutionMode::Interactive);
+        assert_eq!(mode("kubectl exec pod -- ls -t"), ExecutionMode::Captured);
+    }
+
+    #[test]
+    fn test_rules_can_make_exceptions() {
+        let mut config = Config::default();
+        config.interactive_commands.push("cargo watch".to_string());
+        config.interactive_rules.insert(0, crate::config::InteractiveRule {
+            commands: vec!["ssh".to_string()],
+            args: vec!["-V".to_string()],
+            interactive: false,
+            ..Default::default()
+        });
+
+        let mode = |line: &str| Executor::execution_mode(&ParsedCommand::parse(line).unwrap(), &config);
+        assert_eq!(mode("ssh host"), ExecutionMode::Interactive);
+        assert_eq!(mode("ssh -V"), ExecutionMode::Captured);
+        assert_eq!(mode("cargo watch -x test"), ExecutionMode::Interactive);
+        assert_eq!(mode("cargo build"), ExecutionMode::Captured);
+    }
+
+    #[test]
+    fn test_captured_commands_are_isolated() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // A stand-in for python that no interactive rule matches
+        let dir = std::env::temp_dir().join(format!("dshell_captured_isolation_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("python");
+        std::fs::write(&program, "#!/bin/sh\necho \"restricted=$DSHELL_RESTRICTED\"\n").unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let cmd = ParsedCommand::parse(&format!("{} script.py", program.display())).unwrap();
+        let config = Config::default();
+        assert_eq!(Executor::execution_mode(&cmd, &config), ExecutionMode::Captured);
+
+        let report = IsolationReport::new().unwrap();
+        let limits = Watchdog::start(ResourceLimits::default(), None).child_limits();
+        let process = Executor::spawn_captured(&cmd, &PermissionManager::new(), &Variables::new(), &config, &limits, report.sender())
+            .unwrap();
+        let status = report.receive().expect("the child reports its isolation before exec");
+        if LandlockIsolation::is_available() {
+            assert!(status.is_enforced());
+        }
+        let (output, exit, _) = process.finish();
+        assert_eq!(output, vec!["restricted=1"]);
+        assert_eq!(exit, WaitStatus::Exited(0));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_helper_timeout_kills_leftover_processes() {
+        let permissions = PermissionManager::new();
+        let variables = Variables::new();
+        let args = vec!["-c".to_string(), "sleep 30 & echo started".to_string()];
+        let start = Instant::now();
+        let result =
+            Executor::run_sandboxed_helper("sh", &args, &permissions, &variables, &Config::default(), Duration::from_millis(300));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,187 @@
+//! Shell functions: `name() { ...; }` definitions and the `functions` builtin
+//!
+//! A function's body is kept as text and runs through the normal command
+//! pipeline when it is called, with the call's arguments as `$1`, `$#` and
+//! `$@`. Functions can't share a name with a builtin, so builtins can't be
+//! replaced; they do take precedence over external commands.
+
+use super::aliases::is_valid_name;
+use super::builtins::BUILTIN_NAMES;
+use super::parser::{self, Token, TokenKind, WordPartKind};
+use std::collections::BTreeMap;
+
+/// How deeply functions and sourced scripts may nest before a call fails
+pub const MAX_DEPTH: usize = 100;
+
+/// Defining, listing and removing functions
+#[derive(Debug, Clone, PartialEq)]
+pub enum FunctionCommand {
+    /// `name() { body }` or `function name { body }`
+    Define(String, String),
+    /// `functions [NAME...]`: show definitions, all without names
+    List(Vec<String>),
+    /// `unset -f NAME...`
+    Unset(Vec<String>),
+}
+
+impl FunctionCommand {
+    /// Parse a function definition
+    ///
+    /// Returns `None` when `input` doesn't define a function, and an error
+    /// when it starts like a definition but isn't one.
+    pub fn parse_definition(input: &str) -> Option<Result<Self, String>> {
+        let tokens = parser::tokenize(input);
+        let is = |i: usize, kind: TokenKind, value: &str| tokens.get(i).is_some_and(|t| t.kind == kind && t.value == value);
+
+        let (name, i) = if is(0, TokenKind::Word, "function") && tokens.get(1).is_some_and(|t| t.kind == TokenKind::Word) {
+            (&tokens[1], 2)
+        } else if tokens.first().is_some_and(|t| t.kind == TokenKind::Word) && is(1, TokenKind::Operator, "(") {
+            (&tokens[0], 1)
+        } else {
+            return None;
+        };
+        Some(Self::parse_body(input, &tokens, name, i))
+    }
+
+    fn parse_body(input: &str, tokens: &[Token], name: &Token, mut i: usize) -> Result<Self, String> {
+        let is = |i: usize, kind: TokenKind, value: &str| tokens.get(i).is_some_and(|t| t.kind == kind && t.value == value);
+        let plain = name.parts.iter().all(|part| part.kind == WordPartKind::Plain);
+        if !plain || !is_valid_name(&name.value) {
+            return Err(format!("{}: not a valid function name", name.value));
+        }
+        if is(i, TokenKind::Operator, "(") {
+            if !is(i + 1, TokenKind::Operator, ")") {
+                return Err(format!("{}: expected `()` after the name", name.value));
+            }
+            i += 2;
+        }
+        while is(i, TokenKind::Operator, "\n") {
+            i += 1;
+        }
+        if !is(i, TokenKind::Word, "{") {
+            return Err(format!("{}: expected a body in {{ }}", name.value));
+        }
+
+        let open = &tokens[i];
+        let mut depth = 0;
+        let close = tokens[i..].iter().position(|token| {
+            match (token.kind, token.value.as_str()) {
+                (TokenKind::Word, "{") => depth += 1,
+                (TokenKind::Word, "}") => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        });
+        let Some(close) = close.map(|close| close + i) else {
+            return Err(format!("{}: missing `}}`", name.value));
+        };
+        if tokens[close + 1..].iter().any(|token| token.value != "\n") {
+            return Err(format!("{}: unexpected text after `}}`", name.value));
+        }
+
+        let body = input[open.end..tokens[close].start].trim();
+        Ok(FunctionCommand::Define(name.value.clone(), body.to_string()))
+    }
+}
+
+/// The session's functions
+#[derive(Debug, Default)]
+pub struct Functions {
+    functions: BTreeMap<String, String>,
+}
+
+impl Functions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The body of a function
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.functions.get(name).map(String::as_str)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(String::as_str)
+    }
+
+    /// Define, list or remove functions, returning lines to show
+    pub fn run(&mut self, command: FunctionCommand) -> Result<Vec<String>, String> {
+        let mut lines = Vec::new();
+        match command {
+            FunctionCommand::Define(name, _) if BUILTIN_NAMES.contains(&name.as_str()) => {
+                return Err(format!("{}: is a builtin, so can't be a function name", name));
+            }
+            FunctionCommand::Define(name, body) => {
+                self.functions.insert(name, body);
+            }
+            FunctionCommand::List(names) if names.is_empty() => {
+                for (name, body) in &self.functions {
+                    lines.extend(Self::format(name, body));
+                }
+            }
+            FunctionCommand::List(names) => {
+                for name in names {
+                    let body = self.functions.get(&name).ok_or_else(|| format!("functions: {}: not found", name))?;
+                    lines.extend(Self::format(&name, body));
+                }
+            }
+            FunctionCommand::Unset(names) => {
+                for name in names {
+                    if self.functions.remove(&name).is_none() {
+                        return Err(format!("unset: {}: not a function", name));
+                    }
+                }
+            }
+        }
+        Ok(lines)
+    }
+
+    /// The definition of a function as `functions` shows it
+    fn format(name: &str, body: &str) -> Vec<String> {
+        let mut lines = vec![format!("{}() {{", name)];
+        lines.extend(body.lines().map(|line| format!("    {}", line.trim())));
+        lines.push("}".to_string());
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn define(input: &str) -> Option<Result<FunctionCommand, String>> {
+        FunctionCommand::parse_definition(input)
+    }
+
+    fn definition(name: &str, body: &str) -> Option<Result<FunctionCommand, String>> {
+        Some(Ok(FunctionCommand::Define(name.to_string(), body.to_string())))
+    }
+
+    #[test]
+    fn test_parse_definition() {
+        assert_eq!(define("greet() { echo hi $1; }"), definition("greet", "echo hi $1;"));
+        assert_eq!(define("greet () {\n  echo hi\n  ls\n}"), definition("greet", "echo hi\n  ls"));
+        assert_eq!(define("function up { cd ..; }"), definition("up", "cd ..;"));
+        assert_eq!(define("function up() { cd ..; }"), definition("up", "cd ..;"));
+        assert_eq!(define("outer() { inner() { echo; }; }"), definition("outer", "inner() { echo; };"));
+
+        assert_eq!(define("echo hi"), None);
+        assert!(define("echo (x)").unwrap().is_err());
+        assert!(define("f() echo").unwrap().is_err());
+        assert!(define("f() { echo").unwrap().is_err());
+        assert!(define("f() { echo; } extra").unwrap().is_err());
+        assert!(define("'f'() { echo; }").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let mut functions = Functions::new();
+        functions.run(FunctionCommand::Define("greet".to_string(), "echo hi $1".to_string())).unwrap();
+        assert_eq!(functions.get("greet"), Some("echo hi $1"));
+        assert!(functions.run(FunctionCommand::Define("export".to_string(), "ls".to_string())).is_err());
+        assert_eq!(functions.run(FunctionCommand::List(Vec::new())).unwrap(), vec!["greet() {", "    echo hi $1", "}"]);
+        assert!(functions.run(FunctionCommand::List(vec!["nope".to_string()])).is_err());
+        functions.run(FunctionCommand::Unset(vec!["greet".to_string()])).unwrap();
+        assert_eq!(functions.get("greet"), None);
+    }
+}
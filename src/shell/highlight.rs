@@ -0,0 +1,167 @@
+//! Syntax highlighting of the input line
+//!
+//! Classifies byte ranges of the line using the parser's tokenizer. The
+//! terminal renderer decides what each class looks like.
+
+use super::aliases::Aliases;
+use super::builtins::BUILTIN_NAMES;
+use super::executor::{ExecutionMode, Executor};
+use super::functions::Functions;
+use super::parser::{tokenize, ParsedCommand, TokenKind, WordPartKind};
+use super::variables::Variables;
+use crate::config::Config;
+use crate::security::PermissionManager;
+use std::env;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Highlight class of a span of the input line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HighlightStyle {
+    Builtin,
+    Command,
+    UnknownCommand,
+    Quoted,
+    Variable,
+    Operator,
+    Redirection,
+    /// A path the sandbox will not let the command reach
+    DeniedPath,
+}
+
+/// A highlighted byte range of the input line
+#[derive(Debug, Clone, PartialEq)]
+pub struct HighlightSpan {
+    pub start: usize,
+    pub end: usize,
+    pub style: HighlightStyle,
+}
+
+pub struct Highlighter<'a> {
+    permissions: &'a PermissionManager,
+    variables: &'a Variables,
+    config: &'a Config,
+    aliases: Option<&'a Aliases>,
+    functions: Option<&'a Functions>,
+}
+
+impl<'a> Highlighter<'a> {
+    pub fn new(permissions: &'a PermissionManager, variables: &'a Variables, config: &'a Config) -> Self {
+        Self { permissions, variables, config, aliases: None, functions: None }
+    }
+
+    /// Also treat the session's aliases and functions as commands
+    pub fn with_definitions(mut self, aliases: &'a Aliases, functions: &'a Functions) -> Self {
+        self.aliases = Some(aliases);
+        self.functions = Some(functions);
+        self
+    }
+
+    /// Compute highlight spans for `line`; unstyled text has no span
+    pub fn highlight(&self, line: &str) -> Vec<HighlightSpan> {
+        let mut spans = Vec::new();
+        let mut expect_command = true;
+        let mut sandboxed = false;
+        let mut is_path_builtin = false;
+
+        let isolation = Executor::isolation(self.config).ok();
+        let allowed_paths = Executor::effective_allowed_paths(self.permissions, self.config);
+        let denied_paths = self.permissions.list_denied_paths();
+
+        for token in tokenize(line) {
+            match token.kind {
+                TokenKind::Operator => {
+                    spans.push(HighlightSpan { start: token.start, end: token.end, style: HighlightStyle::Operator });
+                    expect_command = true;
+                    continue;
+                }
+                TokenKind::Redirection => {
+                    spans.push(HighlightSpan { start: token.start, end: token.end, style: HighlightStyle::Redirection });
+                    continue;
+                }
+                TokenKind::Word => {}
+            }
+
+            if expect_command {
+                expect_command = false;
+                // An alias is classified by the command it stands for
+                let alias = self
+                    .aliases
+                    .and_then(|aliases| aliases.expand(&line[token.start..token.end]))
+                    .and_then(|expanded| ParsedCommand::parse(&expanded));
+                let command = alias.as_ref().map_or(token.value.as_str(), |cmd| cmd.command.as_str());
+                let is_function = self.functions.is_some_and(|functions| functions.get(&token.value).is_some());
+                let style = if BUILTIN_NAMES.contains(&command) {
+                    HighlightStyle::Builtin
+                } else if is_function || self.command_exists(command) {
+                    HighlightStyle::Command
+                } else {
+                    HighlightStyle::UnknownCommand
+                };
+                spans.push(HighlightSpan { start: token.start, end: token.end, style });
+
+                // Only commands that run under Landlock get denied-path warnings
+                sandboxed = style == HighlightStyle::Command
+                    && !is_function
+                    && ParsedCommand::parse(command)
+                        .is_some_and(|cmd| Executor::execution_mode(&cmd, self.config) == ExecutionMode::Interactive);
+                is_path_builtin = matches!(token.value.as_str(), "allowpath" | "denypath");
+                continue;
+            }
+
+            if sandboxed
+                && !is_path_builtin
+                && let Some(isolation) = &isolation
+                && Self::looks_like_path(&token.value)
+            {
+                let path = Self::resolve(&token.value);
+                if !isolation.can_access(&path, &allowed_paths, &denied_paths) {
+                    spans.push(HighlightSpan { start: token.start, end: token.end, style: HighlightStyle::DeniedPath });
+                    continue;
+                }
+            }
+
+            for part in &token.parts {
+                let style = match part.kind {
+                    WordPartKind::SingleQuoted | WordPartKind::DoubleQuoted => HighlightStyle::Quoted,
+                    WordPartKind::Variable => HighlightStyle::Variable,
+                    WordPartKind::Plain | WordPartKind::Escape => continue,
+                };
+                spans.push(HighlightSpan { start: part.start, end: part.end, style });
+            }
+        }
+
+        spans
+    }
+
+    /// Does `name` resolve to an executable, searching the filtered PATH?
+    fn command_exists(&self, name: &str) -> bool {
+        if name.contains('/') {
+            return Self::is_executable(&Self::resolve(name));
+        }
+
+        let path_var = self.variables.value("PATH", self.permissions);
+
+        path_var.is_some_and(|paths| env::split_paths(&paths).any(|dir| Self::is_executable(&dir.join(name))))
+    }
+
+    fn looks_like_path(word: &str) -> bool {
+        word.starts_with('/') || word.starts_with('~') || word.starts_with("..") || word.contains('/')
+    }
+
+    fn resolve(word: &str) -> PathBuf {
+        let expanded = match (word.strip_prefix('~'), env::var("HOME")) {
+            (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", home, rest),
+            _ => word.to_string(),
+        };
+        env::current_dir()
+            .map(|cwd| cwd.join(&expanded))
+            .unwrap_or_else(|_| PathBuf::from(expanded))
+    }
+
+    fn is_executable(path: &Path) -> bool {
+        path.metadata()
+            .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    }
+}
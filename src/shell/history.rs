@@ -0,0 +1,300 @@
+//! History expansion and the `history` builtin
+//!
+//! Supports the bash-style designators `!!`, `!n`, `!-n`, `!prefix` and the
+//! `^old^new` quick substitution. Expansion happens on the raw input line,
+//! before it is handed to `ParsedCommand::parse`.
+
+use crate::security::redact;
+use std::path::PathBuf;
+
+/// A command line recorded in history
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub command: String,
+    /// Directory the command was run in
+    pub cwd: Option<PathBuf>,
+    /// The command contains a secret and must never be suggested
+    pub sensitive: bool,
+}
+
+impl HistoryEntry {
+    pub fn new(command: String, cwd: Option<PathBuf>, sensitive: bool) -> Self {
+        Self { command, cwd, sensitive }
+    }
+}
+
+/// Operations supported by the `history` builtin
+#[derive(Debug, Clone, PartialEq)]
+pub enum HistoryCommand {
+    /// List entries, optionally only the last N
+    List(Option<usize>),
+    /// List entries containing a pattern
+    Grep(String),
+    /// Delete the entry with the given (1-based) number
+    Delete(usize),
+    /// Clear the whole history
+    Clear,
+}
+
+impl HistoryCommand {
+    /// Parse the arguments of the `history` builtin
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        match args.first().map(|s| s.as_str()) {
+            None => Ok(HistoryCommand::List(None)),
+            Some("-c") => Ok(HistoryCommand::Clear),
+            Some("-d") => match args.get(1).and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if n > 0 => Ok(HistoryCommand::Delete(n)),
+                _ => Err("history: -d: expected a history entry number".to_string()),
+            },
+            Some("-g") | Some("grep") => {
+                if args.len() < 2 {
+                    Err("history: -g: expected a search pattern".to_string())
+                } else {
+                    Ok(HistoryCommand::Grep(args[1..].join(" ")))
+                }
+            }
+            Some(n) => match n.parse::<usize>() {
+                Ok(count) => Ok(HistoryCommand::List(Some(count))),
+                Err(_) => Err(format!("history: {}: invalid option (see 'help')", n)),
+            },
+        }
+    }
+
+    /// Run a read-only history operation and return its output lines
+    pub fn list(&self, history: &[HistoryEntry]) -> Vec<String> {
+        let numbered = history.iter().enumerate().map(|(i, entry)| (i + 1, &entry.command));
+
+        let lines: Vec<String> = match self {
+            HistoryCommand::List(None) => numbered.map(|(n, cmd)| format_entry(n, cmd)).collect(),
+            HistoryCommand::List(Some(count)) => numbered
+                .skip(history.len().saturating_sub(*count))
+                .map(|(n, cmd)| format_entry(n, cmd))
+                .collect(),
+            HistoryCommand::Grep(pattern) => numbered
+                .filter(|(_, cmd)| cmd.contains(pattern.as_str()))
+                .map(|(n, cmd)| format_entry(n, cmd))
+                .collect(),
+            HistoryCommand::Delete(_) | HistoryCommand::Clear => Vec::new(),
+        };
+
+        if lines.is_empty() && !matches!(self, HistoryCommand::Delete(_) | HistoryCommand::Clear) {
+            return vec!["(no matching history entries)".to_string()];
+        }
+        lines
+    }
+}
+
+/// Heuristic check for secrets typed directly into a command line
+///
+/// Catches `export GITHUB_TOKEN=...`-style assignments to variables whose
+/// names suggest credentials. Values of protected environment variables and
+/// credentials with a recognizable shape are checked separately by
+/// `PermissionManager::contains_secret` and `Redactor::contains_secret`.
+pub fn looks_sensitive(command: &str) -> bool {
+    command.split_whitespace().any(|word| {
+        word.split_once('=')
+            .is_some_and(|(name, value)| !value.is_empty() && redact::is_secret_name(name))
+    })
+}
+
+fn format_entry(number: usize, command: &str) -> String {
+    format!("{:>5}  {}", number, command)
+}
+
+/// Expand history references in `input`
+///
+/// Returns `Ok(None)` when the line contains no history references, so the
+/// caller can tell whether the user needs to confirm a rewritten command.
+pub fn expand(input: &str, history: &[HistoryEntry]) -> Result<Option<String>, String> {
+    if let Some(rest) = input.strip_prefix('^') {
+        return quick_substitution(rest, history).map(Some);
+    }
+
+    let chars: Vec<char> = input.chars().collect();
+    let mut result = String::with_capacity(input.len());
+    let mut expanded = false;
+    let mut in_single_quote = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c == '\\' && chars.get(i + 1) == Some(&'!') {
+            result.push('!');
+            i += 2;
+            continue;
+        }
+        if c == '\'' {
+            in_single_quote = !in_single_quote;
+        }
+        if c != '!' || in_single_quote {
+            result.push(c);
+            i += 1;
+            continue;
+        }
+
+        let next = chars.get(i + 1).copied();
+        match next {
+            Some('!') => {
+                result.push_str(last_entry(history, "!!")?);
+                i += 2;
+            }
+            Some(ch) if ch.is_ascii_digit() || ch == '-' => {
+                let start = if ch == '-' { i + 2 } else { i + 1 };
+                let mut end = start;
+                while end < chars.len() && chars[end].is_ascii_digit() {
+                    end += 1;
+                }
+                if end == start {
+                    // A lone "!-" is not an event designator
+                    result.push(c);
+                    i += 1;
+                    continue;
+                }
+
+                let designator: String = chars[i..end].iter().collect();
+                let n: usize = chars[start..end]
+                    .iter()
+                    .collect::<String>()
+                    .parse()
+                    .map_err(|_| event_not_found(&designator))?;
+                let index = if ch == '-' {
+                    history.len().checked_sub(n)
+                } else {
+                    n.checked_sub(1)
+                };
+                let entry = index
+                    .and_then(|idx| history.get(idx))
+                    .ok_or_else(|| event_not_found(&designator))?;
+                result.push_str(&entry.command);
+                i = end;
+            }
+            Some(ch) if is_prefix_char(ch) => {
+                let mut end = i + 1;
+                while end < chars.len() && is_prefix_char(chars[end]) {
+                    end += 1;
+                }
+                let prefix: String = chars[i + 1..end].iter().collect();
+                let entry = history
+                    .iter()
+                    .rev()
+                    .find(|entry| entry.command.starts_with(&prefix))
+                    .ok_or_else(|| event_not_found(&format!("!{}", prefix)))?;
+                result.push_str(&entry.command);
+                i = end;
+            }
+            _ => {
+                // "!" followed by whitespace, "=", "(" or end of line is literal
+                result.push(c);
+                i += 1;
+                continue;
+            }
+        }
+        expanded = true;
+    }
+
+    Ok(if expanded { Some(result) } else { None })
+}
+
+/// Handle `^old^new[^]`: replace the first `old` in the previous command
+fn quick_substitution(rest: &str, history: &[HistoryEntry]) -> Result<String, String> {
+    let mut parts = rest.splitn(3, '^');
+    let old = parts.next().unwrap_or_default();
+    let new = parts.next().unwrap_or_default();
+    let trailing = parts.next().unwrap_or_default();
+
+    if old.is_empty() {
+        return Err("^: no previous substitution".to_string());
+    }
+
+    let last = last_entry(history, "^")?;
+    if !last.contains(old) {
+        return Err(format!("^{}^{}: substitution failed", old, new));
+    }
+
+    Ok(format!("{}{}", last.replacen(old, new, 1), trailing))
+}
+
+fn last_entry<'a>(history: &'a [HistoryEntry], designator: &str) -> Result<&'a str, String> {
+    history
+        .last()
+        .map(|entry| entry.command.as_str())
+        .ok_or_else(|| event_not_found(designator))
+}
+
+fn is_prefix_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '=' | '(' | ')' | ';' | '|' | '&' | '"' | '\'' | '!')
+}
+
+fn event_not_found(designator: &str) -> String {
+    format!("{}: event not found", designator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> Vec<HistoryEntry> {
+        ["ls -la", "cargo build", "cargo test"]
+            .iter()
+            .map(|cmd| HistoryEntry::new(cmd.to_string(), None, false))
+            .collect()
+    }
+
+    #[test]
+    fn test_no_expansion() {
+        assert_eq!(expand("echo hello", &history()), Ok(None));
+        assert_eq!(expand("echo hi!", &history()), Ok(None));
+        assert_eq!(expand("echo '!!'", &history()), Ok(None));
+    }
+
+    #[test]
+    fn test_bang_bang() {
+        assert_eq!(expand("!!", &history()), Ok(Some("cargo test".to_string())));
+        assert_eq!(
+            expand("sudo !!", &history()),
+            Ok(Some("sudo cargo test".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_bang_number() {
+        assert_eq!(expand("!1", &history()), Ok(Some("ls -la".to_string())));
+        assert_eq!(expand("!-2", &history()), Ok(Some("cargo build".to_string())));
+        assert!(expand("!9", &history()).is_err());
+    }
+
+    #[test]
+    fn test_bang_prefix() {
+        assert_eq!(expand("!car", &history()), Ok(Some("cargo test".to_string())));
+        assert_eq!(expand("!ls", &history()), Ok(Some("ls -la".to_string())));
+        assert!(expand("!vim", &history()).is_err());
+    }
+
+    #[test]
+    fn test_quick_substitution() {
+        assert_eq!(
+            expand("^test^build", &history()),
+            Ok(Some("cargo build".to_string()))
+        );
+        assert!(expand("^nothere^x", &history()).is_err());
+    }
+
+    #[test]
+    fn test_looks_sensitive() {
+        assert!(looks_sensitive("export GITHUB_TOKEN=ghp_abc"));
+        assert!(looks_sensitive("DB_PASSWORD=hunter2 ./migrate"));
+        assert!(!looks_sensitive("export EDITOR=vim"));
+        assert!(!looks_sensitive("cargo test"));
+    }
+
+    #[test]
+    fn test_parse_history_command() {
+        assert_eq!(HistoryCommand::parse(&[]), Ok(HistoryCommand::List(None)));
+        assert_eq!(
+            HistoryCommand::parse(&["-d".to_string(), "3".to_string()]),
+            Ok(HistoryCommand::Delete(3))
+        );
+        assert!(HistoryCommand::parse(&["-d".to_string()]).is_err());
+    }
+}
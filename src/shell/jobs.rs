@@ -0,0 +1,492 @@
+//! Job table for background and stopped commands
+//!
+//! A command started with `&` or stopped with Ctrl+Z becomes a job with a
+//! number. Jobs are referred to as `%n`, `%%`/`%+` (the current job), `%-`
+//! (the previous one) or `%prefix` (by the start of the command). Output of
+//! a job that isn't in the foreground is kept in its own buffer until viewed.
+
+use super::audit::{AuditLog, CommandAudit};
+use super::executor::{CapturedProcess, Executor, WaitStatus};
+use super::limits::Watchdog;
+use super::pty::PtyProcess;
+use std::io;
+use std::time::Duration;
+
+/// How long to wait for a finished job's last output lines
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// How a job's process is attached to dshell
+pub enum JobProcess {
+    /// An interactive command that uses dshell's terminal, by process ID
+    Interactive(i32),
+    /// A command whose output is piped back
+    Captured(CapturedProcess),
+    /// A command running on its own pseudo-terminal
+    Pty(PtyProcess),
+}
+
+impl JobProcess {
+    /// The process ID, which is also its process group ID
+    pub fn pid(&self) -> i32 {
+        match self {
+            JobProcess::Interactive(pid) => *pid,
+            JobProcess::Captured(process) => process.pid(),
+            JobProcess::Pty(pty) => pty.pid(),
+        }
+    }
+
+    /// Check for a change of state without waiting
+    fn poll(&mut self) -> io::Result<Option<JobState>> {
+        match self {
+            JobProcess::Interactive(pid) => {
+                let mut status = 0;
+                let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+                match unsafe { libc::waitpid(*pid, &mut status, flags) } {
+                    -1 => Err(io::Error::last_os_error()),
+                    0 => Ok(None),
+                    _ if libc::WIFSTOPPED(status) => Ok(Some(JobState::Stopped)),
+                    _ if libc::WIFCONTINUED(status) => Ok(Some(JobState::Running)),
+                    _ if libc::WIFSIGNALED(status) => Ok(Some(JobState::Done(WaitStatus::Signaled(libc::WTERMSIG(status))))),
+                    _ => Ok(Some(JobState::Done(WaitStatus::Exited(libc::WEXITSTATUS(status))))),
+                }
+            }
+            JobProcess::Captured(process) => Ok(process.try_wait()?.map(JobState::Done)),
+            JobProcess::Pty(pty) => Ok(pty.try_wait()?.map(JobState::Done)),
+        }
+    }
+
+    /// Kill the command and everything it started
+    pub fn kill(self) {
+        let _ = Executor::signal_group(self.pid(), libc::SIGKILL);
+        match self {
+            JobProcess::Interactive(pid) => {
+                let mut status = 0;
+                unsafe { libc::waitpid(pid, &mut status, 0) };
+            }
+            JobProcess::Captured(process) => drop(process.finish()),
+            JobProcess::Pty(mut pty) => drop(pty.kill()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    Done(WaitStatus),
+}
+
+impl JobState {
+    fn label(&self, watchdog: &Watchdog) -> String {
+        match self {
+            JobState::Running => "Running".to_string(),
+            JobState::Stopped => "Stopped".to_string(),
+            JobState::Done(WaitStatus::Exited(0)) => "Done".to_string(),
+            JobState::Done(WaitStatus::Exited(code)) => format!("Exit {}", code),
+            JobState::Done(status @ WaitStatus::Signaled(_)) => watchdog
+                .describe(status)
+                .map(|message| message.trim_start_matches("✗ ").to_string())
+                .unwrap_or_default(),
+            JobState::Done(WaitStatus::Stopped(_)) => "Stopped".to_string(),
+        }
+    }
+}
+
+pub struct Job {
+    pub id: usize,
+    pub command: String,
+    pub state: JobState,
+    process: JobProcess,
+    /// Limits the job runs with; it is killed once past its timeout
+    watchdog: Watchdog,
+    /// Recorded in the audit log once the job finishes
+    audit: CommandAudit,
+    /// Output lines not yet shown
+    output: Vec<String>,
+    /// Start of a line from a PTY job that hasn't ended yet
+    partial: Vec<u8>,
+}
+
+impl Job {
+    /// Move unread output into the job's buffer
+    fn read_output(&mut self) {
+        match &mut self.process {
+            JobProcess::Interactive(_) => {}
+            JobProcess::Captured(process) => self.output.extend(process.read_lines()),
+            JobProcess::Pty(pty) => {
+                while let Some(bytes) = pty.read_available(Duration::ZERO) {
+                    if bytes.is_empty() {
+                        break;
+                    }
+                    self.partial.extend_from_slice(&bytes);
+                }
+                while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = self.partial.drain(..=end).collect();
+                    let line = String::from_utf8_lossy(&line[..end]);
+                    self.output.push(line.strip_suffix('\r').unwrap_or(&line).to_string());
+                }
+            }
+        }
+    }
+
+    /// Take the buffered output and the process, to bring the job to the
+    /// foreground
+    pub fn into_parts(mut self) -> (String, Vec<String>, JobProcess, Watchdog, CommandAudit) {
+        if !self.partial.is_empty() {
+            self.output.push(String::from_utf8_lossy(&self.partial).into_owned());
+        }
+        (self.command, self.output, self.process, self.watchdog, self.audit)
+    }
+}
+
+/// Operations of the job control builtins
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobCommand {
+    /// List jobs
+    List,
+    /// Show a job's buffered output
+    Output(Option<String>),
+    /// Bring a job to the foreground
+    Foreground(Option<String>),
+    /// Continue a stopped job in the background
+    Background(Option<String>),
+    /// Forget a job without killing it
+    Disown(Option<String>),
+    /// Send a signal to jobs (`%n`) or process IDs
+    Kill(i32, Vec<String>),
+    /// Wait for the given jobs, or all of them, to finish
+    Wait(Vec<String>),
+}
+
+impl JobCommand {
+    /// Parse the arguments of `jobs`, `fg`, `bg`, `disown`, `kill` or `wait`
+    pub fn parse(name: &str, args: &[String]) -> Result<Self, String> {
+        let single = |args: &[String]| match args {
+            [] => Ok(None),
+            [spec] => Ok(Some(spec.clone())),
+            _ => Err(format!("{}: too many arguments", name)),
+        };
+
+        match name {
+            "jobs" => match args.first().map(|s| s.as_str()) {
+                None => Ok(JobCommand::List),
+                Some("-o") => single(&args[1..]).map(JobCommand::Output),
+                Some(arg) => Err(format!("jobs: {}: invalid option (see 'help')", arg)),
+            },
+            "fg" => single(args).map(JobCommand::Foreground),
+            "bg" => single(args).map(JobCommand::Background),
+            "disown" => single(args).map(JobCommand::Disown),
+            "wait" => Ok(JobCommand::Wait(args.to_vec())),
+            "kill" => {
+                let (signal, targets) = match args.first().map(|s| s.as_str()) {
+                    Some("-s") => match args.get(1) {
+                        Some(name) => (parse_signal(name), &args[2..]),
+                        None => return Err("kill: -s: expected a signal name".to_string()),
+                    },
+                    Some(arg) if arg.starts_with('-') => (parse_signal(&arg[1..]), &args[1..]),
+                    _ => (Some(libc::SIGTERM), args),
+                };
+                match signal {
+                    _ if targets.is_empty() => Err("kill: usage: kill [-SIGNAL] %job|pid ...".to_string()),
+                    Some(signal) => Ok(JobCommand::Kill(signal, targets.to_vec())),
+                    None => Err(format!("kill: {}: invalid signal", args[0])),
+                }
+            }
+            _ => Err(format!("{}: not a job control command", name)),
+        }
+    }
+}
+
+/// Parse a signal given by number or name, with or without `SIG`
+pub fn parse_signal(name: &str) -> Option<i32> {
+    if let Ok(number) = name.parse::<i32>() {
+        return (1..32).contains(&number).then_some(number);
+    }
+    let name = name.to_ascii_uppercase();
+    let signal = match name.strip_prefix("SIG").unwrap_or(&name) {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "KILL" => libc::SIGKILL,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "PIPE" => libc::SIGPIPE,
+        "ALRM" => libc::SIGALRM,
+        "TERM" => libc::SIGTERM,
+        "CONT" => libc::SIGCONT,
+        "STOP" => libc::SIGSTOP,
+        "TSTP" => libc::SIGTSTP,
+        "WINCH" => libc::SIGWINCH,
+        _ => return None,
+    };
+    Some(signal)
+}
+
+/// Background and stopped jobs, by job number
+#[derive(Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+    /// Job numbers in the order they were started, stopped or continued;
+    /// the last is the current job
+    recent: Vec<usize>,
+}
+
+impl JobTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Add a job, returning its number
+    pub fn add(&mut self, command: String, process: JobProcess, state: JobState, watchdog: Watchdog, audit: CommandAudit) -> usize {
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        self.jobs.push(Job { id, command, state, process, watchdog, audit, output: Vec::new(), partial: Vec::new() });
+        self.touch(id);
+        id
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    /// Remove a job from the table
+    pub fn take(&mut self, id: usize) -> Option<Job> {
+        self.recent.retain(|&recent| recent != id);
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    /// Find the job a `%` spec (or bare job number) refers to; no spec means
+    /// the current job
+    pub fn resolve(&self, spec: Option<&str>) -> Result<usize, String> {
+        let spec = spec.unwrap_or("%%");
+        let name = spec.strip_prefix('%').unwrap_or(spec);
+        let found = match name {
+            "" | "%" | "+" => self.recent.last().copied(),
+            "-" => self.recent.iter().rev().nth(1).copied(),
+            _ => match name.parse::<usize>() {
+                Ok(id) => self.get(id).map(|job| job.id),
+                Err(_) => {
+                    let mut matches = self.jobs.iter().filter(|job| job.command.starts_with(name));
+                    match (matches.next(), matches.next()) {
+                        (Some(job), None) => Some(job.id),
+                        (Some(_), Some(_)) => return Err(format!("{}: ambiguous job spec", spec)),
+                        _ => None,
+                    }
+                }
+            },
+        };
+        match found {
+            Some(id) => Ok(id),
+            None if spec == "%%" => Err("no current job".to_string()),
+            None => Err(format!("{}: no such job", spec)),
+        }
+    }
+
+    /// Send a signal to a job's process group and note when it stops or
+    /// continues
+    pub fn signal(&mut self, id: usize, signal: i32) -> io::Result<()> {
+        let Some(job) = self.jobs.iter_mut().find(|job| job.id == id) else {
+            return Ok(());
+        };
+        Executor::signal_group(job.process.pid(), signal)?;
+        match signal {
+            libc::SIGSTOP | libc::SIGTSTP if job.state == JobState::Running => job.state = JobState::Stopped,
+            libc::SIGCONT if job.state == JobState::Stopped => job.state = JobState::Running,
+            _ => return Ok(()),
+        }
+        self.touch(id);
+        Ok(())
+    }
+
+    /// Collect new output and check which jobs stopped or finished
+    ///
+    /// Returns a notification line for each change. Finished jobs are
+    /// recorded in the audit log; those without unread output are removed,
+    /// the others stay until their output is viewed.
+    pub fn update(&mut self, log: &mut AuditLog) -> Vec<String> {
+        let mut changed = Vec::new();
+        for job in &mut self.jobs {
+            job.read_output();
+            if matches!(job.state, JobState::Done(_)) {
+                continue;
+            }
+            if job.watchdog.expired() {
+                // The process isn't reaped yet, so its group ID is still ours
+                job.watchdog.kill(job.process.pid());
+            }
+            let Ok(Some(state)) = job.process.poll() else {
+                continue;
+            };
+            if state == job.state {
+                continue;
+            }
+            if let JobState::Done(status) = state {
+                if let JobProcess::Captured(process) = &job.process {
+                    job.output.extend(process.drain(DRAIN_TIMEOUT));
+                }
+                let _ = log.record_command(&job.audit, Some(status), job.watchdog.elapsed());
+            }
+            job.state = state;
+            changed.push(job.id);
+        }
+
+        let mut notifications = Vec::new();
+        for id in changed {
+            let job = self.get(id).expect("changed job is in the table");
+            let (done, unread) = (matches!(job.state, JobState::Done(_)), job.output.len());
+            if !done {
+                self.touch(id);
+            }
+            let mut line = self.format(self.get(id).expect("changed job is in the table"));
+            if done && unread == 0 {
+                self.take(id);
+            } else if done {
+                let plural = if unread == 1 { "" } else { "s" };
+                line.push_str(&format!("  ({} line{} of output, 'jobs -o %{}' to view)", unread, plural, id));
+            }
+            notifications.push(line);
+        }
+        notifications
+    }
+
+    /// A line per job, as listed by `jobs`
+    pub fn list(&self) -> Vec<String> {
+        if self.jobs.is_empty() {
+            return vec!["(no jobs)".to_string()];
+        }
+        self.jobs
+            .iter()
+            .map(|job| {
+                let mut line = self.format(job);
+                if !job.output.is_empty() {
+                    line.push_str(&format!("  ({} unread)", job.output.len()));
+                }
+                line
+            })
+            .collect()
+    }
+
+    /// Take a job's buffered output, removing the job if it has finished
+    pub fn take_output(&mut self, id: usize) -> Vec<String> {
+        let Some(job) = self.jobs.iter_mut().find(|job| job.id == id) else {
+            return Vec::new();
+        };
+        job.read_output();
+        let output = std::mem::take(&mut job.output);
+        if matches!(job.state, JobState::Done(_)) {
+            self.take(id);
+        }
+        output
+    }
+
+    /// Whether any of the given jobs (or any job at all) is still running
+    pub fn any_running(&self, ids: &[usize]) -> bool {
+        self.jobs
+            .iter()
+            .any(|job| job.state == JobState::Running && (ids.is_empty() || ids.contains(&job.id)))
+    }
+
+    /// Kill every job that hasn't finished
+    pub fn kill_all(&mut self, log: &mut AuditLog) {
+        for job in self.jobs.drain(..) {
+            if !matches!(job.state, JobState::Done(_)) {
+                // The cgroup also holds what left the process group
+                job.watchdog.kill(job.process.pid());
+                job.process.kill();
+                let status = WaitStatus::Signaled(libc::SIGKILL);
+                let _ = log.record_command(&job.audit, Some(status), job.watchdog.elapsed());
+            }
+        }
+        self.recent.clear();
+    }
+
+    /// Make a job the current one
+    fn touch(&mut self, id: usize) {
+        self.recent.retain(|&recent| recent != id);
+        self.recent.push(id);
+    }
+
+    fn format(&self, job: &Job) -> String {
+        let marker = if self.recent.last() == Some(&job.id) {
+            '+'
+        } else if self.recent.iter().rev().nth(1) == Some(&job.id) {
+            '-'
+        } else {
+            ' '
+        };
+        let background = if job.state == JobState::Running { " &" } else { "" };
+        format!("[{}]{} {:<11} {}{}", job.id, marker, job.state.label(&job.watchdog), job.command, background)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::audit::RunMode;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn audit(command: &str) -> CommandAudit {
+        CommandAudit::new(args(&[command]), RunMode::Interactive, None, Vec::new())
+    }
+
+    /// A table of jobs that refer to no real process
+    fn table(commands: &[&str]) -> JobTable {
+        let mut table = JobTable::new();
+        for command in commands {
+            table.add(command.to_string(), JobProcess::Interactive(-1), JobState::Stopped, Watchdog::start(Default::default(), None), audit(command));
+        }
+        table
+    }
+
+    #[test]
+    fn test_parse_job_commands() {
+        assert_eq!(JobCommand::parse("jobs", &[]), Ok(JobCommand::List));
+        assert_eq!(JobCommand::parse("jobs", &args(&["-o", "%2"])), Ok(JobCommand::Output(Some("%2".to_string()))));
+        assert_eq!(JobCommand::parse("fg", &[]), Ok(JobCommand::Foreground(None)));
+        assert!(JobCommand::parse("bg", &args(&["%1", "%2"])).is_err());
+        assert_eq!(JobCommand::parse("wait", &args(&["%1"])), Ok(JobCommand::Wait(args(&["%1"]))));
+    }
+
+    #[test]
+    fn test_parse_kill() {
+        assert_eq!(JobCommand::parse("kill", &args(&["%1"])), Ok(JobCommand::Kill(libc::SIGTERM, args(&["%1"]))));
+        assert_eq!(JobCommand::parse("kill", &args(&["-9", "%1"])), Ok(JobCommand::Kill(libc::SIGKILL, args(&["%1"]))));
+        assert_eq!(
+            JobCommand::parse("kill", &args(&["-s", "sigstop", "42"])),
+            Ok(JobCommand::Kill(libc::SIGSTOP, args(&["42"])))
+        );
+        assert!(JobCommand::parse("kill", &args(&["-BOGUS", "%1"])).is_err());
+        assert!(JobCommand::parse("kill", &args(&["-INT"])).is_err());
+    }
+
+    #[test]
+    fn test_resolve_job_specs() {
+        let table = table(&["sleep 10", "vim notes.txt", "make"]);
+        assert_eq!(table.resolve(None), Ok(3));
+        assert_eq!(table.resolve(Some("%+")), Ok(3));
+        assert_eq!(table.resolve(Some("%-")), Ok(2));
+        assert_eq!(table.resolve(Some("%1")), Ok(1));
+        assert_eq!(table.resolve(Some("2")), Ok(2));
+        assert_eq!(table.resolve(Some("%vim")), Ok(2));
+        assert!(table.resolve(Some("%4")).is_err());
+        assert!(JobTable::new().resolve(None).is_err());
+    }
+
+    #[test]
+    fn test_job_numbers_and_listing() {
+        let mut table = table(&["sleep 10", "make"]);
+        assert!(table.take(1).is_some());
+        assert_eq!(table.list(), vec!["[2]+ Stopped     make"]);
+        // Numbers continue after the highest job still in the table
+        let id = table.add("top".to_string(), JobProcess::Interactive(-1), JobState::Stopped, Watchdog::start(Default::default(), None), audit("top"));
+        assert_eq!(id, 3);
+        assert_eq!(table.resolve(Some("%-")), Ok(2));
+    }
+}
@@ -0,0 +1,458 @@
+//! Resource limits for launched commands and the `limit` builtin
+//!
+//! CPU time, memory, file size and process count are enforced by the kernel
+//! through `setrlimit`, applied in the child before it runs the command.
+//! With a cgroup for the command, memory, process count and CPU share are
+//! enforced for its whole process tree there instead. The wall-clock
+//! timeout is enforced by dshell, which kills the command's process group
+//! (or cgroup) once it has run too long.
+
+use super::cgroup::{Cgroup, CgroupManager, Usage};
+use super::executor::{Executor, WaitStatus};
+use super::timing::{Rusage, Timing};
+use crate::config::{Config, ResourceLimits};
+use std::ffi::CString;
+use std::io;
+use std::time::{Duration, Instant};
+
+const MIB: u64 = 1024 * 1024;
+
+/// A limit that can be set with the `limit` builtin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Timeout,
+    Cpu,
+    Memory,
+    FileSize,
+    Processes,
+    CpuPercent,
+}
+
+impl Limit {
+    pub const ALL: [Limit; 6] =
+        [Limit::Timeout, Limit::Cpu, Limit::Memory, Limit::FileSize, Limit::Processes, Limit::CpuPercent];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Limit::Timeout => "timeout",
+            Limit::Cpu => "cpu",
+            Limit::Memory => "memory",
+            Limit::FileSize => "fsize",
+            Limit::Processes => "procs",
+            Limit::CpuPercent => "cpushare",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "timeout" | "time" => Some(Limit::Timeout),
+            "cpu" => Some(Limit::Cpu),
+            "memory" | "mem" => Some(Limit::Memory),
+            "fsize" | "filesize" => Some(Limit::FileSize),
+            "procs" | "nproc" | "processes" => Some(Limit::Processes),
+            "cpushare" | "cpupercent" => Some(Limit::CpuPercent),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, limits: &ResourceLimits) -> Option<u64> {
+        match self {
+            Limit::Timeout => limits.timeout_secs,
+            Limit::Cpu => limits.cpu_secs,
+            Limit::Memory => limits.memory_mb,
+            Limit::FileSize => limits.file_size_mb,
+            Limit::Processes => limits.max_processes,
+            Limit::CpuPercent => limits.cpu_percent,
+        }
+    }
+
+    pub fn set(&self, limits: &mut ResourceLimits, value: u64) {
+        let field = match self {
+            Limit::Timeout => &mut limits.timeout_secs,
+            Limit::Cpu => &mut limits.cpu_secs,
+            Limit::Memory => &mut limits.memory_mb,
+            Limit::FileSize => &mut limits.file_size_mb,
+            Limit::Processes => &mut limits.max_processes,
+            Limit::CpuPercent => &mut limits.cpu_percent,
+        };
+        *field = Some(value);
+    }
+
+    /// Parse a value: durations like `90`, `30s`, `5m`, `2h`; sizes in MiB
+    /// like `512`, `512M`, `2G`; CPU shares like `50%`; `none` for no limit
+    fn parse_value(&self, text: &str) -> Result<u64, String> {
+        if matches!(text, "none" | "unlimited") {
+            return Ok(0);
+        }
+        let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+        let (number, unit) = text.split_at(split);
+        let invalid = || format!("limit: {}: invalid {} value", text, self.name());
+        let number: u64 = number.parse().map_err(|_| invalid())?;
+
+        let scale = match (self, unit.to_ascii_lowercase().as_str()) {
+            (Limit::Timeout | Limit::Cpu, "" | "s") => 1,
+            (Limit::Timeout | Limit::Cpu, "m") => 60,
+            (Limit::Timeout | Limit::Cpu, "h") => 3600,
+            (Limit::Memory | Limit::FileSize, "" | "m" | "mb" | "mib") => 1,
+            (Limit::Memory | Limit::FileSize, "g" | "gb" | "gib") => 1024,
+            (Limit::Processes, "") => 1,
+            (Limit::CpuPercent, "" | "%") => 1,
+            _ => return Err(invalid()),
+        };
+        number.checked_mul(scale).ok_or_else(invalid)
+    }
+
+    /// Format a value for display; 0 or unset means no limit
+    pub fn format_value(&self, value: Option<u64>) -> String {
+        match (self, value) {
+            (_, None | Some(0)) => "none".to_string(),
+            (Limit::Timeout | Limit::Cpu, Some(secs)) => format_duration(secs),
+            (Limit::Memory | Limit::FileSize, Some(mb)) if mb % 1024 == 0 => format!("{} GiB", mb / 1024),
+            (Limit::Memory | Limit::FileSize, Some(mb)) => format!("{} MiB", mb),
+            (Limit::Processes, Some(count)) => count.to_string(),
+            (Limit::CpuPercent, Some(percent)) => format!("{}% of a CPU", percent),
+        }
+    }
+}
+
+/// Operations of the `limit` builtin
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitCommand {
+    /// Show the limits in effect
+    Show,
+    /// Change a global limit for the rest of the session
+    Set(Limit, u64),
+}
+
+impl LimitCommand {
+    /// Parse the arguments of the `limit` builtin
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        match args {
+            [] => Ok(LimitCommand::Show),
+            [name, value] => {
+                let limit = Limit::parse(name).ok_or_else(|| format!("limit: {}: unknown limit (see 'help')", name))?;
+                Ok(LimitCommand::Set(limit, limit.parse_value(value)?))
+            }
+            _ => Err("limit: usage: limit [timeout|cpu|memory|fsize|procs|cpushare VALUE]".to_string()),
+        }
+    }
+
+    /// Lines describing the global and per-command limits and how they are
+    /// enforced
+    pub fn show(config: &Config, cgroups: Option<&CgroupManager>) -> Vec<String> {
+        let mut lines = vec!["Resource limits:".to_string()];
+        for limit in Limit::ALL {
+            lines.push(format!("  {:<8} {}", limit.name(), limit.format_value(limit.get(&config.limits))));
+        }
+
+        lines.push(String::new());
+        match cgroups {
+            Some(cgroups) => {
+                let controllers = match cgroups.controllers() {
+                    [] => "no controllers".to_string(),
+                    controllers => controllers.join(", "),
+                };
+                lines.push(format!("Enforced with cgroup v2 ({}) in {}", controllers, cgroups.root().display()));
+            }
+            None if !config.use_cgroups => lines.push("Enforced with rlimits (cgroups disabled in config)".to_string()),
+            None => lines.push("Enforced with rlimits (no writable cgroup v2 subtree)".to_string()),
+        }
+        let has_cpu = cgroups.is_some_and(|cgroups| cgroups.controllers().iter().any(|c| c == "cpu"));
+        if config.limits.cpu_percent.is_some_and(|percent| percent > 0) && !has_cpu {
+            lines.push("⚠️  cpushare needs the cgroup cpu controller and is not enforced".to_string());
+        }
+
+        if !config.command_limits.is_empty() {
+            lines.push(String::new());
+            lines.push("Per-command limits (from config):".to_string());
+            for entry in &config.command_limits {
+                let set: Vec<String> = Limit::ALL
+                    .iter()
+                    .filter(|limit| limit.get(&entry.limits).is_some())
+                    .map(|limit| format!("{} {}", limit.name(), limit.format_value(limit.get(&entry.limits))))
+                    .collect();
+                lines.push(format!("  {}: {}", entry.commands.join(", "), set.join(", ")));
+            }
+        }
+        lines
+    }
+}
+
+/// The limits for a program, by basename: its `command_limits` entry, then
+/// the global ones
+pub fn for_program(program: &str, config: &Config) -> ResourceLimits {
+    match config.command_limits.iter().find(|entry| entry.commands.iter().any(|c| c == program)) {
+        Some(entry) => entry.limits.or(&config.limits),
+        None => config.limits.clone(),
+    }
+}
+
+/// Apply the kernel-enforced limits to the current process
+///
+/// Runs in the child between fork and exec, so it only makes system calls.
+pub fn apply(limits: &ResourceLimits) -> io::Result<()> {
+    let set = |value: Option<u64>| value.filter(|&v| v > 0);
+    let rlimits = [
+        // The hard limit is a second later, so SIGXCPU arrives before SIGKILL
+        (libc::RLIMIT_CPU, set(limits.cpu_secs), 1),
+        (libc::RLIMIT_AS, set(limits.memory_mb).map(|mb| mb.saturating_mul(MIB)), 0),
+        (libc::RLIMIT_FSIZE, set(limits.file_size_mb).map(|mb| mb.saturating_mul(MIB)), 0),
+        (libc::RLIMIT_NPROC, set(limits.max_processes), 0),
+    ];
+
+    for (resource, value, grace) in rlimits {
+        let Some(value) = value else {
+            continue;
+        };
+        let mut current = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+        if unsafe { libc::getrlimit(resource, &mut current) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // Only lower limits; raising the hard limit needs privileges
+        let max = value.saturating_add(grace).min(current.rlim_max);
+        let rlimit = libc::rlimit { rlim_cur: value.min(max), rlim_max: max };
+        if unsafe { libc::setrlimit(resource, &rlimit) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// A command's limits, with the time it started for the timeout and the
+/// cgroup it runs in, if any
+#[derive(Debug)]
+pub struct Watchdog {
+    pub limits: ResourceLimits,
+    started: Instant,
+    cgroup: Option<Cgroup>,
+}
+
+impl Watchdog {
+    /// Start watching a command, in its own cgroup when dshell has them
+    pub fn start(limits: ResourceLimits, cgroups: Option<&CgroupManager>) -> Self {
+        // Without a group the command still runs, under rlimits
+        let cgroup = cgroups.and_then(|cgroups| cgroups.create(&limits).ok());
+        Watchdog { limits, started: Instant::now(), cgroup }
+    }
+
+    /// What the child applies to itself before running the command
+    pub fn child_limits(&self) -> ChildLimits {
+        let mut rlimits = self.limits.clone();
+        if let Some(cgroup) = &self.cgroup {
+            // The cgroup limits the whole tree, and RLIMIT_AS would count
+            // reserved address space rather than memory used
+            if cgroup.has("memory") {
+                rlimits.memory_mb = None;
+            }
+            // RLIMIT_NPROC counts every process of the user, not the command's
+            if cgroup.has("pids") {
+                rlimits.max_processes = None;
+            }
+        }
+        ChildLimits { rlimits, cgroup_procs: self.cgroup.as_ref().map(Cgroup::procs_path) }
+    }
+
+    /// Kill the command and everything it started
+    ///
+    /// `cgroup.kill` also reaches processes that left the process group.
+    pub fn kill(&self, pgid: i32) {
+        if let Some(cgroup) = &self.cgroup
+            && cgroup.kill().is_ok()
+        {
+            return;
+        }
+        let _ = Executor::signal_group(pgid, libc::SIGKILL);
+    }
+
+    /// Resources the command's process tree has used, with a cgroup
+    pub fn usage(&self) -> Option<Usage> {
+        self.cgroup.as_ref().map(Cgroup::usage)
+    }
+
+    /// How long the command has taken and what it used, preferring the
+    /// cgroup's totals, which include processes it didn't wait for
+    pub fn timing(&self, status: WaitStatus, rusage: Rusage) -> Timing {
+        let mut timing = Timing {
+            wall: self.elapsed(),
+            user: rusage.user,
+            system: rusage.system,
+            max_rss: rusage.max_rss,
+            peak_memory: None,
+            status,
+        };
+        if let Some(usage) = self.usage() {
+            timing.user = usage.user;
+            timing.system = usage.system;
+            timing.peak_memory = usage.peak_memory;
+        }
+        timing
+    }
+
+    /// Time since the command started
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.limits.timeout_secs.filter(|&secs| secs > 0).map(Duration::from_secs)
+    }
+
+    /// When the command must be killed, if it has a timeout
+    pub fn deadline(&self) -> Option<Instant> {
+        self.timeout().map(|timeout| self.started + timeout)
+    }
+
+    /// Whether the command has run past its timeout
+    pub fn expired(&self) -> bool {
+        self.deadline().is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// A message for a command stopped by one of its limits, or the usual
+    /// message for a command killed by a signal
+    pub fn describe(&self, status: &WaitStatus) -> Option<String> {
+        let usage = self.usage().unwrap_or_default();
+        let memory = self.limits.memory_mb.filter(|&mb| mb > 0);
+        if usage.oom_kills > 0 {
+            return Some(match memory {
+                Some(_) => format!("✗ Exceeded memory limit ({})", Limit::Memory.format_value(memory)),
+                None => "✗ Killed: out of memory".to_string(),
+            });
+        }
+        if usage.process_limit_hits > 0 && *status != WaitStatus::Exited(0) {
+            return Some(format!(
+                "⚠️  Reached the process limit ({}); some processes could not start",
+                Limit::Processes.format_value(self.limits.max_processes)
+            ));
+        }
+
+        let WaitStatus::Signaled(signal) = *status else {
+            return status.describe();
+        };
+        let message = match signal {
+            libc::SIGKILL if self.expired() => {
+                format!("✗ Killed after {} (timeout)", Limit::Timeout.format_value(self.limits.timeout_secs))
+            }
+            libc::SIGXCPU => format!("✗ Exceeded CPU time limit ({})", Limit::Cpu.format_value(self.limits.cpu_secs)),
+            libc::SIGXFSZ => {
+                format!("✗ Exceeded file size limit ({})", Limit::FileSize.format_value(self.limits.file_size_mb))
+            }
+            // Allocations just fail under RLIMIT_AS, and most programs
+            // abort or crash when that happens
+            libc::SIGSEGV | libc::SIGABRT | libc::SIGBUS if memory.is_some() => {
+                format!("✗ Crashed (signal {}), probably exceeded memory limit ({})", signal, Limit::Memory.format_value(memory))
+            }
+            _ => return status.describe(),
+        };
+        Some(message)
+    }
+}
+
+/// Limits a child process applies to itself between fork and exec
+#[derive(Debug, Clone)]
+pub struct ChildLimits {
+    rlimits: ResourceLimits,
+    /// The `cgroup.procs` file of the command's cgroup
+    cgroup_procs: Option<CString>,
+}
+
+impl ChildLimits {
+    /// Join the command's cgroup and set its rlimits
+    ///
+    /// Runs in the child between fork and exec, so it only makes system calls.
+    pub fn apply(&self) -> io::Result<()> {
+        if let Some(procs) = &self.cgroup_procs {
+            // Writing 0 moves the writing process itself
+            let fd = unsafe { libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) };
+            if fd == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let written = unsafe { libc::write(fd, b"0".as_ptr().cast(), 1) };
+            let error = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            if written == -1 {
+                return Err(error);
+            }
+        }
+        apply(&self.rlimits)
+    }
+}
+
+/// Format seconds as e.g. `45s`, `5m` or `1h30m`
+fn format_duration(secs: u64) -> String {
+    match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, 0) => format!("{}m", m),
+        (0, m, s) => format!("{}m{}s", m, s),
+        (h, 0, 0) => format!("{}h", h),
+        (h, m, _) => format!("{}h{}m", h, m),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CommandLimits;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_limit_values() {
+        assert_eq!(LimitCommand::parse(&args(&["timeout", "5m"])), Ok(LimitCommand::Set(Limit::Timeout, 300)));
+        assert_eq!(LimitCommand::parse(&args(&["memory", "2G"])), Ok(LimitCommand::Set(Limit::Memory, 2048)));
+        assert_eq!(LimitCommand::parse(&args(&["fsize", "100"])), Ok(LimitCommand::Set(Limit::FileSize, 100)));
+        assert_eq!(LimitCommand::parse(&args(&["cpu", "none"])), Ok(LimitCommand::Set(Limit::Cpu, 0)));
+        assert!(LimitCommand::parse(&args(&["procs", "10G"])).is_err());
+        assert_eq!(LimitCommand::parse(&args(&["cpushare", "50%"])), Ok(LimitCommand::Set(Limit::CpuPercent, 50)));
+        assert!(LimitCommand::parse(&args(&["stack", "1"])).is_err());
+        assert_eq!(LimitCommand::parse(&[]), Ok(LimitCommand::Show));
+    }
+
+    #[test]
+    fn test_format_values() {
+        assert_eq!(Limit::Timeout.format_value(Some(90)), "1m30s");
+        assert_eq!(Limit::Timeout.format_value(Some(5400)), "1h30m");
+        assert_eq!(Limit::Memory.format_value(Some(2048)), "2 GiB");
+        assert_eq!(Limit::FileSize.format_value(Some(0)), "none");
+        assert_eq!(Limit::CpuPercent.format_value(Some(150)), "150% of a CPU");
+    }
+
+    #[test]
+    fn test_command_limits_override_global() {
+        let mut config = Config::default();
+        config.limits.timeout_secs = Some(30);
+        config.limits.memory_mb = Some(512);
+        config.command_limits.push(CommandLimits {
+            commands: vec!["cargo".to_string()],
+            limits: ResourceLimits { timeout_secs: Some(0), ..Default::default() },
+        });
+
+        let cargo = for_program("cargo", &config);
+        assert_eq!((cargo.timeout_secs, cargo.memory_mb), (Some(0), Some(512)));
+        assert_eq!(for_program("ls", &config), config.limits);
+    }
+
+    #[test]
+    fn test_child_limits_without_cgroup() {
+        let limits = ResourceLimits { memory_mb: Some(512), max_processes: Some(64), ..Default::default() };
+        let child = Watchdog::start(limits.clone(), None).child_limits();
+        // Without a cgroup, rlimits are all there is
+        assert_eq!(child.rlimits, limits);
+        assert!(child.cgroup_procs.is_none());
+    }
+
+    #[test]
+    fn test_describe_limit_violations() {
+        let limits = ResourceLimits { cpu_secs: Some(60), memory_mb: Some(512), ..Default::default() };
+        let watchdog = Watchdog::start(limits, None);
+        assert_eq!(
+            watchdog.describe(&WaitStatus::Signaled(libc::SIGXCPU)).as_deref(),
+            Some("✗ Exceeded CPU time limit (1m)")
+        );
+        assert!(watchdog.describe(&WaitStatus::Signaled(libc::SIGABRT)).unwrap().contains("memory limit (512 MiB)"));
+        // Without a timeout, SIGKILL is just a kill
+        assert_eq!(watchdog.describe(&WaitStatus::Signaled(libc::SIGKILL)).as_deref(), Some("✗ Killed"));
+        assert_eq!(Watchdog::start(ResourceLimits::default(), None).describe(&WaitStatus::Exited(1)), None);
+    }
+}
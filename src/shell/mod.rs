@@ -1,15 +1,38 @@
-/// Shell module - Core shell functionality
+//! Shell module - Core shell functionality
 
+pub mod aliases;
+pub mod audit;
 pub mod builtins;
+pub mod cgroup;
+pub mod completion;
+pub mod completion_spec;
+pub mod dotenv;
 pub mod executor;
+pub mod functions;
+pub mod highlight;
+pub mod history;
+pub mod jobs;
+pub mod limits;
 pub mod parser;
+pub mod pty;
+pub mod timing;
+pub mod variables;
 
 use builtins::{BuiltinCommand, Builtins};
+use cgroup::CgroupManager;
 use crate::config::Config;
 use crate::security::PermissionManager;
-use executor::{ExecutionMode, Executor};
+use aliases::{AliasCommand, Aliases};
+use audit::AuditCommand;
+use dotenv::DotenvCommand;
+use executor::{CommandResult, ExecutionMode, Executor};
+use functions::{FunctionCommand, Functions};
+use history::HistoryCommand;
+use jobs::JobCommand;
+use limits::LimitCommand;
 use parser::ParsedCommand;
-use std::collections::HashMap;
+use std::path::PathBuf;
+use variables::{VariableCommand, Variables};
 
 #[derive(Debug)]
 pub enum CommandAction {
@@ -18,21 +41,75 @@ pub enum CommandAction {
     ExecuteInteractive(ParsedCommand),
     ExecuteCaptured(ParsedCommand),
     ShowOutput(Vec<String>),
+    /// Show an error and fail with a non-zero status
+    Error(String),
     AllowEnvVar(String),
     DenyEnvVar(String),
     AllowAllEnvVars,
     DenyAllEnvVars,
-    SetEnvVar(String, String),
+    Variables(VariableCommand),
     AllowPath(String),
     DenyPath(String),
+    History(HistoryCommand),
+    Job(JobCommand),
+    Limit(LimitCommand),
+    /// Run a command and report its timing even below the threshold
+    Time(String),
+    Audit(AuditCommand),
+    /// List redacted values, or reveal one
+    Unmask(Option<usize>),
+    /// Review the variables in a `.env` file and load the chosen ones
+    Dotenv(DotenvCommand),
+    Alias(AliasCommand),
+    Function(FunctionCommand),
+    /// Run a function with these arguments
+    CallFunction(String, Vec<String>),
+    /// Run several commands an alias stands for
+    RunLine(String),
+    /// Run the commands in a script, with arguments as its positional
+    /// parameters
+    Source(PathBuf, Vec<String>),
 }
 
 pub struct Shell;
 
 impl Shell {
     /// Process a command input and determine what action to take
-    pub fn process_input(input: &str, permissions: &PermissionManager, custom_env: &HashMap<String, String>, config: &Config) -> Option<CommandAction> {
-        let cmd = ParsedCommand::parse(input)?;
+    ///
+    /// A function definition is kept as written. Otherwise aliases are
+    /// expanded, so the command they stand for is classified like any
+    /// other, then variables, so `nounset` errors stop the command.
+    pub fn process_input(
+        input: &str,
+        permissions: &PermissionManager,
+        variables: &Variables,
+        aliases: &Aliases,
+        functions: &Functions,
+        config: &Config,
+    ) -> Option<CommandAction> {
+        if let Some(definition) = FunctionCommand::parse_definition(input) {
+            return Some(match definition {
+                Ok(function_cmd) => CommandAction::Function(function_cmd),
+                Err(e) => CommandAction::Error(format!("dshell: {}", e)),
+            });
+        }
+
+        let expanded = aliases.expand(input);
+        if let Some(expanded) = &expanded
+            && (parser::split_commands(expanded).len() > 1 || parser::background(expanded).is_some())
+        {
+            return Some(CommandAction::RunLine(expanded.clone()));
+        }
+        let input = expanded.as_deref().unwrap_or(input);
+
+        let cmd = match Self::parse_expanded(input, permissions, variables) {
+            Ok(cmd) => cmd?,
+            Err(message) => return Some(CommandAction::Error(message)),
+        };
+
+        if functions.get(&cmd.command).is_some() {
+            return Some(CommandAction::CallFunction(cmd.command, cmd.args));
+        }
 
         // Check if it's a built-in command
         if let Some(builtin) = Builtins::parse(&cmd) {
@@ -44,16 +121,27 @@ impl Shell {
                 | BuiltinCommand::SecurityStatus
                 | BuiltinCommand::Echo(_)
                 | BuiltinCommand::ListAllowedPaths => {
-                    let output = Builtins::execute(&builtin, permissions, custom_env, config);
+                    let output = Builtins::execute(&builtin, permissions, variables, config);
                     CommandAction::ShowOutput(output)
                 }
+                BuiltinCommand::Invalid(message) => CommandAction::Error(message),
                 BuiltinCommand::Allow(var) => CommandAction::AllowEnvVar(var),
                 BuiltinCommand::Deny(var) => CommandAction::DenyEnvVar(var),
                 BuiltinCommand::AllowAll => CommandAction::AllowAllEnvVars,
                 BuiltinCommand::DenyAll => CommandAction::DenyAllEnvVars,
-                BuiltinCommand::Export(key, value) => CommandAction::SetEnvVar(key, value),
+                BuiltinCommand::Variables(variable_cmd) => CommandAction::Variables(variable_cmd),
                 BuiltinCommand::AllowPath(path) => CommandAction::AllowPath(path),
                 BuiltinCommand::DenyPath(path) => CommandAction::DenyPath(path),
+                BuiltinCommand::History(history_cmd) => CommandAction::History(history_cmd),
+                BuiltinCommand::Job(job_cmd) => CommandAction::Job(job_cmd),
+                BuiltinCommand::Limit(limit_cmd) => CommandAction::Limit(limit_cmd),
+                BuiltinCommand::Time(command) => CommandAction::Time(command),
+                BuiltinCommand::Audit(audit_cmd) => CommandAction::Audit(audit_cmd),
+                BuiltinCommand::Unmask(number) => CommandAction::Unmask(number),
+                BuiltinCommand::Dotenv(dotenv_cmd) => CommandAction::Dotenv(dotenv_cmd),
+                BuiltinCommand::Alias(alias_cmd) => CommandAction::Alias(alias_cmd),
+                BuiltinCommand::Function(function_cmd) => CommandAction::Function(function_cmd),
+                BuiltinCommand::Source(file, args) => CommandAction::Source(PathBuf::from(file), args),
             });
         }
 
@@ -65,8 +153,99 @@ impl Shell {
     }
 
     /// Execute a captured command with environment filtering
-    pub fn execute_captured(cmd: &ParsedCommand, permissions: &PermissionManager, custom_env: &HashMap<String, String>) -> Vec<String> {
-        let result = Executor::execute_captured(cmd, permissions, custom_env);
-        result.output
+    pub fn execute_captured(
+        cmd: &ParsedCommand,
+        permissions: &PermissionManager,
+        variables: &Variables,
+        config: &Config,
+        cgroups: Option<&CgroupManager>,
+    ) -> CommandResult {
+        Executor::execute_captured(cmd, permissions, variables, config, cgroups)
+    }
+
+    /// Parse a command with its variable references expanded, as the user
+    /// would see them; an error (such as an unset variable under `set -u`)
+    /// comes back ready to show
+    pub fn parse_expanded(input: &str, permissions: &PermissionManager, variables: &Variables) -> Result<Option<ParsedCommand>, String> {
+        ParsedCommand::parse_expanded(input, |reference| variables.expand(reference, permissions)).map_err(|e| format!("dshell: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::redact::Redactor;
+    use crate::terminal::renderer;
+
+    #[test]
+    fn test_echoed_secrets_are_redacted() {
+        let permissions = PermissionManager::new();
+        let mut variables = Variables::new();
+        variables.set("API_TOKEN", "hunter2hunter2".to_string()).unwrap();
+        let config = Config::default();
+
+        let action =
+            Shell::process_input("echo key $API_TOKEN", &permissions, &variables, &Aliases::new(), &Functions::new(), &config);
+        let Some(CommandAction::ShowOutput(lines)) = action else {
+            panic!("expected echo's output, got {:?}", action);
+        };
+        // Builtin output goes through the redactor like command output
+        let (mut redactor, _) = Redactor::new(&config.redaction);
+        let lines = redactor.redact(renderer::plain_lines(lines), &permissions, &variables);
+        assert_eq!(lines[0].text, "key [redacted #1: $API_TOKEN]");
+    }
+
+    #[test]
+    fn test_alias_keeps_classification() {
+        let permissions = PermissionManager::new();
+        let variables = Variables::new();
+        let functions = Functions::new();
+        let config = Config::default();
+        let mut aliases = Aliases::new();
+        aliases
+            .run(AliasCommand::Alias(vec![("v".to_string(), Some("vim".to_string())), ("both".to_string(), Some("v a; v b".to_string()))]))
+            .unwrap();
+
+        let action = Shell::process_input("v notes.txt", &permissions, &variables, &aliases, &functions, &config);
+        assert!(matches!(action, Some(CommandAction::ExecuteInteractive(cmd)) if cmd.command == "vim" && cmd.args == ["notes.txt"]));
+
+        let action = Shell::process_input("both", &permissions, &variables, &aliases, &functions, &config);
+        let Some(CommandAction::RunLine(line)) = action else {
+            panic!("expected the alias's commands, got {:?}", action);
+        };
+        for part in parser::split_commands(&line) {
+            let action = Shell::process_input(part, &permissions, &variables, &aliases, &functions, &config);
+            assert!(matches!(action, Some(CommandAction::ExecuteInteractive(cmd)) if cmd.command == "vim"));
+        }
+    }
+
+    #[test]
+    fn test_background_commands_expand_variables() {
+        use crate::security::IsolationReport;
+        use limits::Watchdog;
+
+        let dir = std::env::temp_dir().join(format!("dshell_background_expand_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("found.txt"), "").unwrap();
+        let permissions = PermissionManager::new();
+        let mut variables = Variables::new();
+        variables.set("DIR", dir.display().to_string()).unwrap();
+        let config = Config::default();
+
+        // Started the way `cmd &` starts a job
+        let cmd = Shell::parse_expanded("ls $DIR", &permissions, &variables).unwrap().unwrap();
+        assert_eq!(cmd.args, [dir.display().to_string()]);
+        let report = IsolationReport::new().unwrap();
+        let limits = Watchdog::start(config.limits.clone(), None).child_limits();
+        let process = Executor::spawn_captured(&cmd, &permissions, &variables, &config, &limits, report.sender()).unwrap();
+        let (output, status, _) = process.finish();
+        assert_eq!(output, ["found.txt"]);
+        assert_eq!(status.code(), 0);
+
+        variables.options.nounset = true;
+        let error = Shell::parse_expanded("ls $MISSING", &permissions, &variables).unwrap_err();
+        assert!(error.starts_with("dshell: "), "{}", error);
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -1,4 +1,9 @@
-/// Command parsing module
+//! Command parsing module
+//!
+//! The tokenizer here is shared by the parser and the input line
+//! highlighter, so what gets colored is exactly what gets executed.
+
+use std::ops::Range;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ParsedCommand {
@@ -7,24 +12,447 @@ pub struct ParsedCommand {
     pub raw_input: String,  // Store original input with spaces preserved
 }
 
+/// Kind of a shell token
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Word,
+    /// Control operators: `|`, `||`, `&&`, `&`, `;`, `(`, `)`
+    Operator,
+    /// Redirections: `>`, `>>`, `<`, `<<`, `2>`, `&>`, `2>&1`, ...
+    Redirection,
+}
+
+/// Kind of a piece of a word, for highlighting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WordPartKind {
+    Plain,
+    SingleQuoted,
+    DoubleQuoted,
+    Variable,
+    Escape,
+}
+
+/// A contiguous piece of a word, as a byte range of the input
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordPart {
+    pub kind: WordPartKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// Byte range of the token in the input
+    pub start: usize,
+    pub end: usize,
+    /// Text with quotes removed and escapes resolved
+    pub value: String,
+    pub parts: Vec<WordPart>,
+    /// Byte ranges in `value` of `$VAR` references outside single quotes,
+    /// which expansion replaces
+    pub variables: Vec<Range<usize>>,
+    /// The token ends inside an open quote
+    pub unterminated: bool,
+}
+
+/// Split a command line into tokens
+///
+/// Quotes group words and are removed from `value`; `$VAR` references are
+/// kept literally (expansion happens later). An unterminated quote runs to
+/// the end of the input. A newline separates commands like `;`, except
+/// right after another operator, and backslash-newline joins lines.
+pub fn tokenize(input: &str) -> Vec<Token> {
+    let bytes: Vec<(usize, char)> = input.char_indices().collect();
+    let end_of = |i: usize| bytes.get(i).map_or(input.len(), |(pos, _)| *pos);
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let (start, c) = bytes[i];
+
+        // Line continuation
+        if c == '\\' && bytes.get(i + 1).map(|(_, n)| *n) == Some('\n') {
+            i += 2;
+            continue;
+        }
+
+        // A newline ends a command, unless an operator already did
+        if c == '\n' {
+            let after_operator = tokens.last().is_none_or(|t: &Token| t.kind == TokenKind::Operator);
+            if !after_operator {
+                tokens.push(Token {
+                    kind: TokenKind::Operator,
+                    start,
+                    end: start + 1,
+                    value: "\n".to_string(),
+                    parts: Vec::new(),
+                    variables: Vec::new(),
+                    unterminated: false,
+                });
+            }
+            i += 1;
+            continue;
+        }
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        // Control operators
+        if matches!(c, '|' | '&' | ';' | '(' | ')') && !is_redirect_start(&bytes, i) {
+            let doubled = matches!(c, '|' | '&') && bytes.get(i + 1).map(|(_, n)| *n) == Some(c);
+            let len = if doubled { 2 } else { 1 };
+            let end = end_of(i + len);
+            tokens.push(Token {
+                kind: TokenKind::Operator,
+                start,
+                end,
+                value: input[start..end].to_string(),
+                parts: Vec::new(),
+                variables: Vec::new(),
+                unterminated: false,
+            });
+            i += len;
+            continue;
+        }
+
+        // Redirections, with an optional fd number or & in front
+        if is_redirect_start(&bytes, i) {
+            let mut j = i;
+            while j < bytes.len() && bytes[j].1.is_ascii_digit() {
+                j += 1;
+            }
+            if bytes.get(j).map(|(_, ch)| *ch) == Some('&') {
+                j += 1;
+            }
+            let op = bytes[j].1;
+            j += 1;
+            if bytes.get(j).map(|(_, ch)| *ch) == Some(op) {
+                j += 1;
+            }
+            // 2>&1 style duplication
+            if bytes.get(j).map(|(_, ch)| *ch) == Some('&') {
+                j += 1;
+                while j < bytes.len() && (bytes[j].1.is_ascii_digit() || bytes[j].1 == '-') {
+                    j += 1;
+                }
+            }
+            let end = end_of(j);
+            tokens.push(Token {
+                kind: TokenKind::Redirection,
+                start,
+                end,
+                value: input[start..end].to_string(),
+                parts: Vec::new(),
+                variables: Vec::new(),
+                unterminated: false,
+            });
+            i = j;
+            continue;
+        }
+
+        // A word: runs until unquoted whitespace or an operator
+        let mut value = String::new();
+        let mut parts = Vec::new();
+        let mut variables = Vec::new();
+        let mut plain_start: Option<usize> = None;
+        let mut unterminated = false;
+
+        let flush_plain = |parts: &mut Vec<WordPart>, plain_start: &mut Option<usize>, end: usize| {
+            if let Some(s) = plain_start.take() {
+                parts.push(WordPart { kind: WordPartKind::Plain, start: s, end });
+            }
+        };
+
+        while i < bytes.len() {
+            let (pos, ch) = bytes[i];
+            if ch.is_whitespace() || matches!(ch, '|' | '&' | ';' | '(' | ')' | '<' | '>') {
+                break;
+            }
+
+            match ch {
+                '\'' => {
+                    flush_plain(&mut parts, &mut plain_start, pos);
+                    i += 1;
+                    while i < bytes.len() && bytes[i].1 != '\'' {
+                        value.push(bytes[i].1);
+                        i += 1;
+                    }
+                    unterminated |= i == bytes.len();
+                    i = (i + 1).min(bytes.len());
+                    parts.push(WordPart { kind: WordPartKind::SingleQuoted, start: pos, end: end_of(i) });
+                }
+                '"' => {
+                    flush_plain(&mut parts, &mut plain_start, pos);
+                    let mut seg_start = pos;
+                    i += 1;
+                    while i < bytes.len() && bytes[i].1 != '"' {
+                        let (p, c2) = bytes[i];
+                        if c2 == '\\' && bytes.get(i + 1).map(|(_, n)| *n) == Some('\n') {
+                            i += 2;
+                        } else if c2 == '\\' && i + 1 < bytes.len() && matches!(bytes[i + 1].1, '"' | '\\' | '$' | '`') {
+                            value.push(bytes[i + 1].1);
+                            i += 2;
+                        } else if c2 == '$' && is_var_start(&bytes, i) {
+                            parts.push(WordPart { kind: WordPartKind::DoubleQuoted, start: seg_start, end: p });
+                            let var_end = scan_variable(&bytes, i);
+                            let var_start = value.len();
+                            value.push_str(&input[p..end_of(var_end)]);
+                            variables.push(var_start..value.len());
+                            parts.push(WordPart { kind: WordPartKind::Variable, start: p, end: end_of(var_end) });
+                            i = var_end;
+                            seg_start = end_of(i);
+                        } else {
+                            value.push(c2);
+                            i += 1;
+                        }
+                    }
+                    unterminated |= i == bytes.len();
+                    i = (i + 1).min(bytes.len());
+                    if end_of(i) > seg_start {
+                        parts.push(WordPart { kind: WordPartKind::DoubleQuoted, start: seg_start, end: end_of(i) });
+                    }
+                }
+                '\\' => {
+                    flush_plain(&mut parts, &mut plain_start, pos);
+                    if let Some((_, escaped)) = bytes.get(i + 1) {
+                        if *escaped != '\n' {
+                            value.push(*escaped);
+                        }
+                        i += 2;
+                    } else {
+                        i += 1;
+                    }
+                    parts.push(WordPart { kind: WordPartKind::Escape, start: pos, end: end_of(i) });
+                }
+                '$' if is_var_start(&bytes, i) => {
+                    flush_plain(&mut parts, &mut plain_start, pos);
+                    let var_end = scan_variable(&bytes, i);
+                    let var_start = value.len();
+                    value.push_str(&input[pos..end_of(var_end)]);
+                    variables.push(var_start..value.len());
+                    parts.push(WordPart { kind: WordPartKind::Variable, start: pos, end: end_of(var_end) });
+                    i = var_end;
+                }
+                _ => {
+                    if plain_start.is_none() {
+                        plain_start = Some(pos);
+                    }
+                    value.push(ch);
+                    i += 1;
+                }
+            }
+        }
+        flush_plain(&mut parts, &mut plain_start, end_of(i));
+
+        tokens.push(Token {
+            kind: TokenKind::Word,
+            start,
+            end: end_of(i),
+            value,
+            parts,
+            variables,
+            unterminated,
+        });
+    }
+
+    tokens
+}
+
+/// Quote a word so that it reads back as itself, for showing commands
+pub fn quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | ',' | ':' | '=' | '+' | '@' | '%'));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// Check whether a command line is incomplete and should continue on the
+/// next line: an open quote, a trailing backslash, a trailing `|`, `||` or
+/// `&&`, or an unclosed `{` or `(` block
+pub fn needs_continuation(input: &str) -> bool {
+    let tokens = tokenize(input);
+
+    // A lone trailing backslash escapes the newline that hasn't been typed yet
+    let trailing_backslash = input.ends_with('\\')
+        && (input.len() - input.trim_end_matches('\\').len()) % 2 == 1
+        && tokens.last().is_some_and(|t| !t.unterminated);
+    if trailing_backslash {
+        return true;
+    }
+
+    let Some(last) = tokens.iter().rev().find(|t| t.value != "\n") else {
+        return false;
+    };
+    if last.unterminated {
+        return true;
+    }
+    if last.kind == TokenKind::Operator && matches!(last.value.as_str(), "|" | "||" | "&&") {
+        return true;
+    }
+
+    let mut depth: i32 = 0;
+    for token in &tokens {
+        match (token.kind, token.value.as_str()) {
+            (TokenKind::Word, "{") | (TokenKind::Operator, "(") => depth += 1,
+            (TokenKind::Word, "}") | (TokenKind::Operator, ")") => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+/// Split a command line into the commands separated by `;`, `&` or newlines
+///
+/// A command ended by `&` keeps it, so `background` can tell it apart.
+pub fn split_commands(input: &str) -> Vec<&str> {
+    let mut commands = Vec::new();
+    let mut start = 0;
+    let mut depth: i32 = 0;
+
+    for token in tokenize(input) {
+        match (token.kind, token.value.as_str()) {
+            (TokenKind::Word, "{") | (TokenKind::Operator, "(") => depth += 1,
+            (TokenKind::Word, "}") | (TokenKind::Operator, ")") => depth -= 1,
+            // Separators inside a block belong to the block
+            (TokenKind::Operator, ";" | "\n") if depth <= 0 => {
+                commands.push(&input[start..token.start]);
+                start = token.end;
+            }
+            (TokenKind::Operator, "&") if depth <= 0 => {
+                commands.push(&input[start..token.end]);
+                start = token.end;
+            }
+            _ => {}
+        }
+    }
+    commands.push(&input[start..]);
+
+    commands
+        .into_iter()
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .collect()
+}
+
+/// The command to start in the background, if `command` ends with `&`
+pub fn background(command: &str) -> Option<&str> {
+    let last = tokenize(command).pop()?;
+    if last.kind == TokenKind::Operator && last.value == "&" {
+        Some(command[..last.start].trim())
+    } else {
+        None
+    }
+}
+
+/// Does a redirection operator start at index `i`?
+fn is_redirect_start(chars: &[(usize, char)], i: usize) -> bool {
+    let mut j = i;
+    // Only a leading fd number that starts a token counts (e.g. "2>")
+    while j < chars.len() && chars[j].1.is_ascii_digit() {
+        j += 1;
+    }
+    if j > i && i > 0 && !chars[i - 1].1.is_whitespace() {
+        return false;
+    }
+    if j == i && chars[j].1 == '&' {
+        j += 1;
+    }
+    matches!(chars.get(j).map(|(_, c)| *c), Some('<') | Some('>'))
+}
+
+fn is_var_start(chars: &[(usize, char)], i: usize) -> bool {
+    matches!(chars.get(i + 1).map(|(_, c)| *c), Some(c) if c == '{' || c == '_' || c.is_alphanumeric() || matches!(c, '?' | '#' | '@' | '*'))
+}
+
+/// Return the index just past a `$VAR`, `${VAR}`, `$?` or positional
+/// parameter (`$1`, `$#`, `$@`, `$*`) starting at `i`
+fn scan_variable(chars: &[(usize, char)], i: usize) -> usize {
+    let mut j = i + 1;
+    match chars.get(j).map(|(_, c)| *c) {
+        Some('{') => {
+            while j < chars.len() && chars[j].1 != '}' {
+                j += 1;
+            }
+            (j + 1).min(chars.len())
+        }
+        Some('?' | '#' | '@' | '*') => j + 1,
+        Some(c) if c.is_ascii_digit() => j + 1,
+        _ => {
+            while j < chars.len() && (chars[j].1.is_alphanumeric() || chars[j].1 == '_') {
+                j += 1;
+            }
+            j
+        }
+    }
+}
+
 impl ParsedCommand {
     /// Parse a command line string into command and arguments
     pub fn parse(input: &str) -> Option<Self> {
+        Self::parse_expanded(input, |reference| Ok(reference.to_string())).unwrap_or(None)
+    }
+
+    /// Parse a command line, replacing `$VAR` references outside single
+    /// quotes with what `expand` returns for them
+    ///
+    /// A word that was nothing but an unquoted reference is dropped when it
+    /// expands to nothing, as in other shells. `expand` separates the
+    /// arguments in `$@` with NUL: a word that is just `$@` (quoted or not)
+    /// becomes one word per argument, elsewhere they are joined by spaces.
+    ///
+    /// Pipes, `&&`, `||`, redirections and subshells are errors rather than
+    /// arguments; commands are split on `;`, `&` and newlines beforehand.
+    pub fn parse_expanded(input: &str, expand: impl Fn(&str) -> Result<String, String>) -> Result<Option<Self>, String> {
         let input = input.trim();
 
-        if input.is_empty() {
-            return None;
-        }
+        let mut words = Vec::new();
+        for token in tokenize(input) {
+            match (token.kind, token.value.as_str()) {
+                (TokenKind::Operator, ";" | "&" | "\n") | (TokenKind::Word, _) => {}
+                (TokenKind::Operator, "(" | ")") => return Err(format!("{}: subshells are not supported", token.value)),
+                _ => return Err(format!("{}: pipelines and redirections are not supported", token.value)),
+            }
+
+            let mut word = String::new();
+            let mut copied = 0;
+            for range in &token.variables {
+                word.push_str(&token.value[copied..range.start]);
+                word.push_str(&expand(&token.value[range.clone()])?);
+                copied = range.end;
+            }
+            word.push_str(&token.value[copied..]);
 
-        let mut parts = input.split_whitespace();
-        let command = parts.next()?.to_string();
-        let args: Vec<String> = parts.map(|s| s.to_string()).collect();
+            if word.contains('\0') {
+                let whole_word = matches!(token.variables.as_slice(), [range] if range.len() == token.value.len());
+                if whole_word {
+                    words.extend(word.split('\0').map(str::to_string));
+                    continue;
+                }
+                word = word.replace('\0', " ");
+            }
 
-        Some(ParsedCommand {
+            let bare_reference = matches!(token.parts.as_slice(), [part] if part.kind == WordPartKind::Variable);
+            if !(bare_reference && word.is_empty()) {
+                words.push(word);
+            }
+        }
+
+        let mut words = words.into_iter();
+        let Some(command) = words.next() else {
+            return Ok(None);
+        };
+        Ok(Some(ParsedCommand {
             command,
-            args,
+            args: words.collect(),
             raw_input: input.to_string(),
-        })
+        }))
     }
 }
 
@@ -51,4 +479,116 @@ mod tests {
         assert!(ParsedCommand::parse("").is_none());
         assert!(ParsedCommand::parse("   ").is_none());
     }
+
+    #[test]
+    fn test_parse_quoted_args() {
+        let cmd = ParsedCommand::parse(r#"grep "hello  world" 'it''s' a\ b"#).unwrap();
+        assert_eq!(cmd.command, "grep");
+        assert_eq!(cmd.args, vec!["hello  world", "its", "a b"]);
+    }
+
+    #[test]
+    fn test_tokenize_operators_and_redirections() {
+        let tokens = tokenize("ls -l | grep x && cat 2>&1 >out.txt");
+        let kinds: Vec<(TokenKind, &str)> = tokens.iter().map(|t| (t.kind, t.value.as_str())).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (TokenKind::Word, "ls"),
+                (TokenKind::Word, "-l"),
+                (TokenKind::Operator, "|"),
+                (TokenKind::Word, "grep"),
+                (TokenKind::Word, "x"),
+                (TokenKind::Operator, "&&"),
+                (TokenKind::Word, "cat"),
+                (TokenKind::Redirection, "2>&1"),
+                (TokenKind::Redirection, ">"),
+                (TokenKind::Word, "out.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_needs_continuation() {
+        assert!(!needs_continuation("ls -la"));
+        assert!(needs_continuation("echo 'unterminated"));
+        assert!(needs_continuation("echo \"still open"));
+        assert!(needs_continuation("ls \\"));
+        assert!(!needs_continuation("ls \\\\"));
+        assert!(needs_continuation("cat file |"));
+        assert!(needs_continuation("make &&"));
+        assert!(needs_continuation("greet() {"));
+        assert!(!needs_continuation("greet() { echo hi; }"));
+        assert!(!needs_continuation("echo 'a\nb'"));
+    }
+
+    #[test]
+    fn test_split_commands() {
+        assert_eq!(split_commands("echo a; echo b"), vec!["echo a", "echo b"]);
+        assert_eq!(split_commands("echo a\necho b\n"), vec!["echo a", "echo b"]);
+        assert_eq!(split_commands("echo 'a;b'"), vec!["echo 'a;b'"]);
+        assert_eq!(split_commands("cat x |\n grep y"), vec!["cat x |\n grep y"]);
+        assert_eq!(split_commands("f() {\n echo a\n}; f"), vec!["f() {\n echo a\n}", "f"]);
+    }
+
+    #[test]
+    fn test_background_commands() {
+        assert_eq!(split_commands("sleep 1 & echo a"), vec!["sleep 1 &", "echo a"]);
+        assert_eq!(split_commands("a && b &"), vec!["a && b &"]);
+        assert_eq!(background("sleep 1 &"), Some("sleep 1"));
+        assert_eq!(background("echo '&'"), None);
+        assert_eq!(background("a && b"), None);
+        assert!(!needs_continuation("sleep 1 &"));
+    }
+
+    #[test]
+    fn test_line_continuation() {
+        let cmd = ParsedCommand::parse("echo a \\\nb").unwrap();
+        assert_eq!(cmd.args, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_tokenize_word_parts() {
+        let tokens = tokenize(r#"echo "hi $USER" $HOME"#);
+        let kinds: Vec<WordPartKind> = tokens[1].parts.iter().map(|p| p.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![WordPartKind::DoubleQuoted, WordPartKind::Variable, WordPartKind::DoubleQuoted]
+        );
+        assert_eq!(tokens[1].value, "hi $USER");
+        assert_eq!(tokens[2].parts[0].kind, WordPartKind::Variable);
+    }
+
+    #[test]
+    fn test_parse_expanded() {
+        let expand = |reference: &str| match reference {
+            "$HOME" | "${HOME}" => Ok("/home/me".to_string()),
+            "$UNSET" => Ok(String::new()),
+            _ => Err(format!("{}: unbound variable", reference)),
+        };
+        let cmd = ParsedCommand::parse_expanded(r#"ls $HOME/src "${HOME} x" '$HOME' \$HOME $UNSET "$UNSET""#, expand)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cmd.args, vec!["/home/me/src", "/home/me x", "$HOME", "$HOME", ""]);
+        assert_eq!(cmd.raw_input, r#"ls $HOME/src "${HOME} x" '$HOME' \$HOME $UNSET "$UNSET""#);
+        assert!(ParsedCommand::parse_expanded("echo $OTHER", expand).is_err());
+
+        let positional = |reference: &str| Ok(if reference == "$@" { "a b\0c".to_string() } else { "2".to_string() });
+        let cmd = ParsedCommand::parse_expanded(r#"f "$@" x$@ $1x $#"#, positional).unwrap().unwrap();
+        assert_eq!(cmd.args, vec!["a b", "c", "xa b c", "2x", "2"]);
+    }
+
+    #[test]
+    fn test_parse_rejects_pipelines_and_redirections() {
+        let expand = |reference: &str| Ok(reference.to_string());
+        for input in ["ls | grep x", "make && make install", "true || false", "ls > out.txt", "sort < in", "cmd 2>&1", "(cd /tmp)"] {
+            assert!(ParsedCommand::parse_expanded(input, expand).is_err(), "{}", input);
+        }
+        let error = ParsedCommand::parse_expanded("ls | wc -l", expand).unwrap_err();
+        assert_eq!(error, "|: pipelines and redirections are not supported");
+
+        // Quoted or escaped operators are ordinary arguments
+        let cmd = ParsedCommand::parse_expanded(r#"echo '|' "&&" \>"#, expand).unwrap().unwrap();
+        assert_eq!(cmd.args, vec!["|", "&&", ">"]);
+    }
 }
@@ -0,0 +1,169 @@
+//! Pseudo-terminal support for captured commands
+//!
+//! A captured command normally writes into pipes, so it sees no terminal:
+//! colors and progress bars are turned off and prompts can't be answered.
+//! Running it on a PTY instead makes it behave as it would in a terminal,
+//! while dshell still reads everything it writes.
+
+use super::executor::WaitStatus;
+use super::timing::{Reaper, Rusage};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem::ManuallyDrop;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A child process running on the slave side of a PTY
+pub struct PtyProcess {
+    master: File,
+    reaper: Reaper,
+}
+
+impl PtyProcess {
+    /// Spawn a command with a new PTY of the given size as its controlling
+    /// terminal and stdin, stdout and stderr
+    pub fn spawn(mut command: Command, cols: u16, rows: u16) -> io::Result<Self> {
+        let (master, slave) = open_pty()?;
+        set_size(&master, cols, rows)?;
+
+        command
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+
+        // Start a new session so the PTY becomes the controlling terminal
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = command.spawn()?;
+        // The command's copies of the slave were moved into it and closed
+        // here, so reads report EOF/EIO once the program exits
+        drop(command);
+        Ok(PtyProcess { master, reaper: Reaper::new(child.id() as i32) })
+    }
+
+    /// Read whatever output is available, waiting up to `timeout` for some
+    ///
+    /// Returns `None` once the program has closed the terminal.
+    pub fn read_available(&mut self, timeout: Duration) -> Option<Vec<u8>> {
+        // Linux reports EIO when the slave side has been closed
+        read_ready(&mut self.master, timeout).ok()?
+    }
+
+    /// Send input to the program as if typed on its terminal
+    pub fn write_input(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.master.write_all(bytes)
+    }
+
+    /// Tell the program its terminal changed size
+    pub fn resize(&self, cols: u16, rows: u16) -> io::Result<()> {
+        set_size(&self.master, cols, rows)
+    }
+
+    /// Whether the program turned off canonical (line-by-line) input, as
+    /// full-screen programs and line editors do
+    pub fn in_raw_mode(&self) -> bool {
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        // On Linux the master reports the slave's settings
+        unsafe { libc::tcgetattr(self.master.as_raw_fd(), &mut termios) == 0 && termios.c_lflag & libc::ICANON == 0 }
+    }
+
+    /// The process ID, which is also its process group and session ID
+    pub fn pid(&self) -> i32 {
+        self.reaper.pid()
+    }
+
+    pub fn try_wait(&mut self) -> io::Result<Option<WaitStatus>> {
+        self.reaper.try_wait()
+    }
+
+    /// Reap the program after it closed its terminal, killing it if it
+    /// hasn't exited within a moment
+    pub fn finish(&mut self) -> io::Result<(WaitStatus, Rusage)> {
+        let deadline = Instant::now() + Duration::from_millis(200);
+        while Instant::now() < deadline {
+            if self.reaper.try_wait()?.is_some() {
+                return self.reaper.wait();
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.reaper.kill()?;
+        self.reaper.wait()
+    }
+
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.reaper.kill()?;
+        self.reaper.wait().map(|_| ())
+    }
+}
+
+/// Read raw bytes typed on dshell's own terminal, without waiting
+///
+/// Used to pass keys straight through to a program that has taken over
+/// the terminal.
+pub fn read_terminal_input() -> io::Result<Vec<u8>> {
+    // Borrow stdin without closing it when done
+    let mut stdin = ManuallyDrop::new(unsafe { File::from_raw_fd(libc::STDIN_FILENO) });
+    Ok(read_ready(&mut stdin, Duration::ZERO)?.unwrap_or_default())
+}
+
+/// Read from `file` if data arrives within `timeout`; returns an empty
+/// buffer on timeout and `None` at end of file
+fn read_ready(file: &mut File, timeout: Duration) -> io::Result<Option<Vec<u8>>> {
+    let mut fds = libc::pollfd { fd: file.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+    let ready = unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) };
+    if ready <= 0 {
+        return Ok(Some(Vec::new()));
+    }
+
+    let mut buf = [0u8; 8192];
+    match file.read(&mut buf) {
+        Ok(0) => Ok(None),
+        Ok(n) => Ok(Some(buf[..n].to_vec())),
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok(Some(Vec::new())),
+        Err(e) => Err(e),
+    }
+}
+
+/// Open a new PTY pair, returning the master and slave ends
+fn open_pty() -> io::Result<(File, File)> {
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC);
+        if master == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = File::from_raw_fd(master);
+
+        if libc::grantpt(master.as_raw_fd()) == -1 || libc::unlockpt(master.as_raw_fd()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut name = [0 as libc::c_char; 128];
+        if libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let slave = libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC);
+        if slave == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((master, File::from_raw_fd(slave)))
+    }
+}
+
+fn set_size(master: &File, cols: u16, rows: u16) -> io::Result<()> {
+    let size = libc::winsize { ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0 };
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
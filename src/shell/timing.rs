@@ -0,0 +1,193 @@
+//! Timing and resource usage of finished commands
+//!
+//! Commands are reaped with `wait4`, which also returns the CPU time and
+//! largest resident set of the command's process and the children it
+//! waited for. Wall time comes from the command's watchdog; with a cgroup,
+//! CPU time and peak memory cover the whole process tree instead.
+
+use super::executor::WaitStatus;
+use std::io;
+use std::time::Duration;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Resources a reaped process used, from `wait4`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rusage {
+    pub user: Duration,
+    pub system: Duration,
+    /// Largest resident set of the process or a child it waited for, in bytes
+    pub max_rss: u64,
+}
+
+impl Rusage {
+    fn from_libc(usage: &libc::rusage) -> Self {
+        let duration = |time: libc::timeval| Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000);
+        Rusage {
+            user: duration(usage.ru_utime),
+            system: duration(usage.ru_stime),
+            // Linux reports kilobytes
+            max_rss: usage.ru_maxrss as u64 * 1024,
+        }
+    }
+}
+
+/// Wait for a child like `waitpid`, also returning its resource usage
+///
+/// Returns `None` if `WNOHANG` is given and the child hasn't changed state.
+pub fn wait4(pid: i32, flags: i32) -> io::Result<Option<(WaitStatus, Rusage)>> {
+    let mut status: libc::c_int = 0;
+    let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+    loop {
+        match unsafe { libc::wait4(pid, &mut status, flags, &mut usage) } {
+            0 => return Ok(None),
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            _ => return Ok(Some((WaitStatus::from_raw(pid, status), Rusage::from_libc(&usage)))),
+        }
+    }
+}
+
+/// A child process reaped with `wait4`, so its usage is kept
+///
+/// Used instead of `Child::wait`, which throws the usage away.
+#[derive(Debug)]
+pub struct Reaper {
+    pid: i32,
+    exit: Option<(WaitStatus, Rusage)>,
+}
+
+impl Reaper {
+    pub fn new(pid: i32) -> Self {
+        Reaper { pid, exit: None }
+    }
+
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    /// Whether the child has been reaped, so its ID may belong to another
+    /// process now
+    pub fn is_reaped(&self) -> bool {
+        self.exit.is_some()
+    }
+
+    /// How the child ended, if it has, without waiting
+    pub fn try_wait(&mut self) -> io::Result<Option<WaitStatus>> {
+        if self.exit.is_none() {
+            self.exit = wait4(self.pid, libc::WNOHANG)?;
+        }
+        Ok(self.exit.map(|(status, _)| status))
+    }
+
+    /// Wait for the child to exit
+    pub fn wait(&mut self) -> io::Result<(WaitStatus, Rusage)> {
+        if let Some(exit) = self.exit {
+            return Ok(exit);
+        }
+        let exit = wait4(self.pid, 0)?.expect("wait4 without WNOHANG waits");
+        self.exit = Some(exit);
+        Ok(exit)
+    }
+
+    /// Kill the child, unless it was already reaped
+    pub fn kill(&mut self) -> io::Result<()> {
+        if !self.is_reaped() && unsafe { libc::kill(self.pid, libc::SIGKILL) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// What a finished command took and how it ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timing {
+    pub wall: Duration,
+    pub user: Duration,
+    pub system: Duration,
+    /// Largest resident set of a single process, in bytes
+    pub max_rss: u64,
+    /// Peak memory of the whole process tree, with a cgroup
+    pub peak_memory: Option<u64>,
+    pub status: WaitStatus,
+}
+
+impl Timing {
+    /// A one-line summary, e.g. "⏱ 2.04s (user 1.90s, sys 0.10s), max RSS 45 MiB, exit 0"
+    pub fn report(&self) -> String {
+        let memory = match self.peak_memory {
+            Some(peak) => format!("peak memory {} MiB", peak.div_ceil(MIB)),
+            None => format!("max RSS {} MiB", self.max_rss.div_ceil(MIB)),
+        };
+        let status = match self.status {
+            WaitStatus::Exited(code) => format!("exit {}", code),
+            WaitStatus::Signaled(signal) => format!("signal {}", signal),
+            WaitStatus::Stopped(_) => "stopped".to_string(),
+        };
+        format!(
+            "⏱ {} (user {:.2}s, sys {:.2}s), {}, {}",
+            format_wall(self.wall),
+            self.user.as_secs_f64(),
+            self.system.as_secs_f64(),
+            memory,
+            status
+        )
+    }
+}
+
+/// Format wall time as e.g. `0.42s`, `12.3s` or `2m05s`
+pub fn format_wall(wall: Duration) -> String {
+    let secs = wall.as_secs();
+    match secs {
+        0..10 => format!("{:.2}s", wall.as_secs_f64()),
+        10..60 => format!("{:.1}s", wall.as_secs_f64()),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_reaper_keeps_usage() {
+        let pid = Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap().id();
+        let mut reaper = Reaper::new(pid as i32);
+        let (status, usage) = reaper.wait().unwrap();
+        assert_eq!(status, WaitStatus::Exited(3));
+        assert!(usage.max_rss > 0);
+        // Later calls return the same result instead of waiting again
+        assert_eq!(reaper.try_wait().unwrap(), Some(WaitStatus::Exited(3)));
+        assert!(reaper.kill().is_ok());
+    }
+
+    #[test]
+    fn test_timing_report() {
+        let timing = Timing {
+            wall: Duration::from_millis(2040),
+            user: Duration::from_millis(1900),
+            system: Duration::from_millis(100),
+            max_rss: 45 * MIB,
+            peak_memory: None,
+            status: WaitStatus::Exited(0),
+        };
+        assert_eq!(timing.report(), "⏱ 2.04s (user 1.90s, sys 0.10s), max RSS 45 MiB, exit 0");
+
+        let tree = Timing { peak_memory: Some(80 * MIB), status: WaitStatus::Signaled(9), ..timing };
+        assert_eq!(tree.report(), "⏱ 2.04s (user 1.90s, sys 0.10s), peak memory 80 MiB, signal 9");
+    }
+
+    #[test]
+    fn test_format_wall() {
+        assert_eq!(format_wall(Duration::from_millis(420)), "0.42s");
+        assert_eq!(format_wall(Duration::from_millis(12_340)), "12.3s");
+        assert_eq!(format_wall(Duration::from_secs(125)), "2m05s");
+        assert_eq!(format_wall(Duration::from_secs(5400)), "1h30m");
+    }
+}
@@ -0,0 +1,505 @@
+//! Shell variables and `set -o` options
+//!
+//! `NAME=value` sets a shell variable that only dshell sees; `export`
+//! also passes it to commands. Variables dshell inherited are looked up
+//! through the permission manager, so only allowed ones are visible, and
+//! they count as exported. `unset` on an inherited variable hides it for
+//! the rest of the session; `readonly` variables can't be changed or unset.
+
+use crate::security::{Permission, PermissionManager};
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+
+/// A variable set in this session
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub value: String,
+    /// Passed to commands
+    pub exported: bool,
+    pub readonly: bool,
+}
+
+/// An option changed with `set -o NAME` / `set +o NAME`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShellOption {
+    /// Stop running a command line or script after a command fails
+    Errexit,
+    /// Treat expanding an unset variable as an error
+    Nounset,
+    /// A pipeline fails if any command in it fails; stored and listed, but
+    /// with no effect until dshell runs pipelines
+    Pipefail,
+    /// Show each command, expanded, before it runs
+    Xtrace,
+}
+
+impl ShellOption {
+    const ALL: [ShellOption; 4] = [ShellOption::Errexit, ShellOption::Nounset, ShellOption::Pipefail, ShellOption::Xtrace];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ShellOption::Errexit => "errexit",
+            ShellOption::Nounset => "nounset",
+            ShellOption::Pipefail => "pipefail",
+            ShellOption::Xtrace => "xtrace",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|option| option.name() == name)
+    }
+
+    /// The single-letter flag, as in `set -e`
+    fn from_flag(flag: char) -> Option<Self> {
+        match flag {
+            'e' => Some(ShellOption::Errexit),
+            'u' => Some(ShellOption::Nounset),
+            'x' => Some(ShellOption::Xtrace),
+            _ => None,
+        }
+    }
+}
+
+/// Options set with `set`; all off by default
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ShellOptions {
+    pub errexit: bool,
+    pub nounset: bool,
+    pub pipefail: bool,
+    pub xtrace: bool,
+}
+
+impl ShellOptions {
+    pub fn set(&mut self, option: ShellOption, on: bool) {
+        match option {
+            ShellOption::Errexit => self.errexit = on,
+            ShellOption::Nounset => self.nounset = on,
+            ShellOption::Pipefail => self.pipefail = on,
+            ShellOption::Xtrace => self.xtrace = on,
+        }
+    }
+
+    pub fn get(&self, option: ShellOption) -> bool {
+        match option {
+            ShellOption::Errexit => self.errexit,
+            ShellOption::Nounset => self.nounset,
+            ShellOption::Pipefail => self.pipefail,
+            ShellOption::Xtrace => self.xtrace,
+        }
+    }
+
+    /// Lines for `set -o`
+    pub fn list(&self) -> Vec<String> {
+        ShellOption::ALL
+            .into_iter()
+            .map(|option| format!("{:<12}{}", option.name(), if self.get(option) { "on" } else { "off" }))
+            .collect()
+    }
+}
+
+/// Variable builtins: assignments, `export`, `readonly`, `unset` and `set`
+#[derive(Debug, Clone, PartialEq)]
+pub enum VariableCommand {
+    /// `NAME=value...`, shell variables unless already exported
+    Assign(Vec<(String, String)>),
+    /// `export NAME[=value]...`; without names, list exported variables
+    Export(Vec<(String, Option<String>)>),
+    /// `readonly NAME[=value]...`; without names, list readonly variables
+    Readonly(Vec<(String, Option<String>)>),
+    Unset(Vec<String>),
+    /// `set`: list the session's variables
+    List,
+    /// `set -o`: list options
+    ListOptions,
+    /// `set -o NAME`, `set +o NAME`, `set -eux`
+    SetOptions(Vec<(ShellOption, bool)>),
+}
+
+impl VariableCommand {
+    /// Parse `export`, `readonly`, `unset` or `set` and their arguments
+    ///
+    /// Quotes were already removed by the tokenizer, so values may contain
+    /// `=`, `#` and spaces; a word starting with `#` begins a comment.
+    pub fn parse(command: &str, args: &[String]) -> Result<Self, String> {
+        let args: Vec<&String> = args.iter().take_while(|arg| !arg.starts_with('#')).collect();
+        match command {
+            "export" | "readonly" => {
+                let mut vars = Vec::new();
+                for arg in args {
+                    let (name, value) = match arg.split_once('=') {
+                        Some((name, value)) => (name, Some(value.to_string())),
+                        None => (arg.as_str(), None),
+                    };
+                    if !is_valid_name(name) {
+                        return Err(format!("{}: {}: not a valid variable name", command, name));
+                    }
+                    vars.push((name.to_string(), value));
+                }
+                Ok(if command == "export" { VariableCommand::Export(vars) } else { VariableCommand::Readonly(vars) })
+            }
+            "unset" => {
+                let names: Vec<String> = args.into_iter().filter(|arg| *arg != "-v").cloned().collect();
+                if names.is_empty() {
+                    return Err("unset: usage: unset NAME...".to_string());
+                }
+                if let Some(name) = names.iter().find(|name| !is_valid_name(name)) {
+                    return Err(format!("unset: {}: not a valid variable name", name));
+                }
+                Ok(VariableCommand::Unset(names))
+            }
+            "set" => Self::parse_set(&args),
+            _ => Err(format!("{}: not a variable builtin", command)),
+        }
+    }
+
+    fn parse_set(args: &[&String]) -> Result<Self, String> {
+        let usage = || "set: usage: set [-o|+o OPTION] [-eux|+eux]".to_string();
+        match args {
+            [] => return Ok(VariableCommand::List),
+            [flag] if *flag == "-o" || *flag == "+o" => return Ok(VariableCommand::ListOptions),
+            _ => {}
+        }
+
+        let mut options = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let on = match arg.chars().next() {
+                Some('-') => true,
+                Some('+') => false,
+                _ => return Err(usage()),
+            };
+            if arg.len() < 2 {
+                return Err(usage());
+            }
+            if arg[1..] == *"o" {
+                let name = args.next().ok_or_else(usage)?;
+                let option = ShellOption::from_name(name).ok_or_else(|| format!("set: {}: unknown option", name))?;
+                options.push((option, on));
+                continue;
+            }
+            for flag in arg[1..].chars() {
+                let option = ShellOption::from_flag(flag).ok_or_else(|| format!("set: -{}: unknown option", flag))?;
+                options.push((option, on));
+            }
+        }
+        Ok(VariableCommand::SetOptions(options))
+    }
+
+    /// `NAME=value...` when every word of a command is an assignment
+    pub fn parse_assignments(command: &str, args: &[String]) -> Option<Self> {
+        std::iter::once(command)
+            .chain(args.iter().map(String::as_str))
+            .map(|word| word.split_once('=').filter(|(name, _)| is_valid_name(name)))
+            .map(|assignment| assignment.map(|(name, value)| (name.to_string(), value.to_string())))
+            .collect::<Option<Vec<_>>>()
+            .map(VariableCommand::Assign)
+    }
+}
+
+/// Whether `name` can be used as a variable name
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The session's variables, options and last exit status
+#[derive(Debug, Default)]
+pub struct Variables {
+    vars: BTreeMap<String, Variable>,
+    /// Inherited variables removed with `unset`
+    unset: HashSet<String>,
+    pub options: ShellOptions,
+    /// Exit status of the last command, for `$?`
+    status: i32,
+    /// Arguments of the function being run, for `$1`, `$#` and `$@`
+    positional: Vec<String>,
+}
+
+impl Variables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A variable set in this session
+    pub fn get(&self, name: &str) -> Option<&Variable> {
+        self.vars.get(name)
+    }
+
+    /// Variables set in this session, by name
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Variable)> {
+        self.vars.iter()
+    }
+
+    /// Value of a variable as dshell sees it: set in this session, or
+    /// inherited and allowed
+    pub fn value(&self, name: &str, permissions: &PermissionManager) -> Option<String> {
+        if let Some(var) = self.vars.get(name) {
+            return Some(var.value.clone());
+        }
+        if self.unset.contains(name) || permissions.check_env_var(name) != Permission::Allowed {
+            return None;
+        }
+        env::var(name).ok()
+    }
+
+    /// Names of the variables `value` finds
+    pub fn names(&self, permissions: &PermissionManager) -> Vec<String> {
+        let mut names: Vec<String> = self.vars.keys().cloned().collect();
+        for (name, _) in env::vars() {
+            if !self.vars.contains_key(&name)
+                && !self.unset.contains(&name)
+                && permissions.check_env_var(&name) == Permission::Allowed
+            {
+                names.push(name);
+            }
+        }
+        names.sort();
+        names
+    }
+
+    /// Whether an inherited variable was removed with `unset`
+    pub fn is_unset(&self, name: &str) -> bool {
+        self.unset.contains(name)
+    }
+
+    /// Exported variables set in this session
+    pub fn exported(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.vars.iter().filter(|(_, var)| var.exported).map(|(name, var)| (name, &var.value))
+    }
+
+    /// Set a variable, keeping whether it's exported; inherited variables
+    /// stay exported
+    pub fn set(&mut self, name: &str, value: String) -> Result<(), String> {
+        match self.vars.get_mut(name) {
+            Some(var) if var.readonly => Err(format!("{}: readonly variable", name)),
+            Some(var) => {
+                var.value = value;
+                Ok(())
+            }
+            None => {
+                let exported = env::var_os(name).is_some();
+                self.unset.remove(name);
+                self.vars.insert(name.to_string(), Variable { value, exported, readonly: false });
+                Ok(())
+            }
+        }
+    }
+
+    /// Export a variable, setting it first when a value is given
+    ///
+    /// Without a value the variable must be set in this session; inherited
+    /// variables are exported already, as far as permissions allow.
+    pub fn export(&mut self, name: &str, value: Option<String>) -> Result<(), String> {
+        if let Some(value) = value {
+            self.set(name, value)?;
+        }
+        match self.vars.get_mut(name) {
+            Some(var) => {
+                var.exported = true;
+                Ok(())
+            }
+            None => Err(format!("{}: not set", name)),
+        }
+    }
+
+    /// Make a variable readonly, setting it first when a value is given
+    pub fn readonly(&mut self, name: &str, value: Option<String>) -> Result<(), String> {
+        if let Some(value) = value {
+            self.set(name, value)?;
+        }
+        match self.vars.get_mut(name) {
+            Some(var) => {
+                var.readonly = true;
+                Ok(())
+            }
+            None => Err(format!("{}: not set", name)),
+        }
+    }
+
+    /// Remove a variable; an inherited one is hidden for the session
+    pub fn unset(&mut self, name: &str) -> Result<(), String> {
+        if self.vars.get(name).is_some_and(|var| var.readonly) {
+            return Err(format!("{}: readonly variable", name));
+        }
+        self.vars.remove(name);
+        if env::var_os(name).is_some() {
+            self.unset.insert(name.to_string());
+        }
+        Ok(())
+    }
+
+    pub fn status(&self) -> i32 {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: i32) {
+        self.status = status;
+    }
+
+    /// Replace the positional parameters, returning the previous ones
+    pub fn set_positional(&mut self, args: Vec<String>) -> Vec<String> {
+        std::mem::replace(&mut self.positional, args)
+    }
+
+    /// Expand a `$NAME`, `${NAME}`, `${NAME:-default}` or `$?` reference,
+    /// or a positional parameter: `$1`, `$#`, `$*`, `$@`
+    ///
+    /// The arguments in `$@` are separated by NUL, which can't occur in an
+    /// argument, so the parser can make each a word of its own. With
+    /// `nounset`, a reference to an unset variable without a default is an
+    /// error.
+    pub fn expand(&self, reference: &str, permissions: &PermissionManager) -> Result<String, String> {
+        let reference = reference.strip_prefix('$').unwrap_or(reference);
+        let reference = match reference.strip_prefix('{') {
+            Some(braced) => braced.strip_suffix('}').unwrap_or(braced),
+            None => reference,
+        };
+        match reference {
+            "?" => return Ok(self.status.to_string()),
+            "#" => return Ok(self.positional.len().to_string()),
+            "@" => return Ok(self.positional.join("\0")),
+            "*" => return Ok(self.positional.join(" ")),
+            "0" => return Ok("dshell".to_string()),
+            _ => {}
+        }
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        let value = match name.parse::<usize>() {
+            Ok(number) => self.positional.get(number.wrapping_sub(1)).cloned(),
+            Err(_) => self.value(name, permissions),
+        };
+        match (value, default) {
+            (Some(value), Some(default)) if value.is_empty() => Ok(default.to_string()),
+            (Some(value), _) => Ok(value),
+            (None, Some(default)) => Ok(default.to_string()),
+            (None, None) if self.options.nounset => Err(format!("{}: unbound variable", name)),
+            (None, None) => Ok(String::new()),
+        }
+    }
+
+    /// Lines for `set`, `export` or `readonly` without arguments
+    pub fn list(&self, filter: impl Fn(&Variable) -> bool) -> Vec<String> {
+        self.vars
+            .iter()
+            .filter(|(_, var)| filter(var))
+            .map(|(name, var)| {
+                let mut flags = Vec::new();
+                if var.exported {
+                    flags.push("exported");
+                }
+                if var.readonly {
+                    flags.push("readonly");
+                }
+                if flags.is_empty() {
+                    format!("{}={}", name, var.value)
+                } else {
+                    format!("{}={}  ({})", name, var.value, flags.join(", "))
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            VariableCommand::parse("export", &args(&["A=x=y#z", "B", "#", "comment"])),
+            Ok(VariableCommand::Export(vec![("A".to_string(), Some("x=y#z".to_string())), ("B".to_string(), None)]))
+        );
+        assert!(VariableCommand::parse("export", &args(&["1X=2"])).is_err());
+        assert_eq!(VariableCommand::parse("unset", &args(&["-v", "A"])), Ok(VariableCommand::Unset(args(&["A"]))));
+        assert!(VariableCommand::parse("unset", &[]).is_err());
+        assert_eq!(
+            VariableCommand::parse("set", &args(&["-eu", "+o", "xtrace", "-o", "pipefail"])),
+            Ok(VariableCommand::SetOptions(vec![
+                (ShellOption::Errexit, true),
+                (ShellOption::Nounset, true),
+                (ShellOption::Xtrace, false),
+                (ShellOption::Pipefail, true),
+            ]))
+        );
+        assert_eq!(VariableCommand::parse("set", &args(&["-o"])), Ok(VariableCommand::ListOptions));
+        let mut options = ShellOptions::default();
+        options.set(ShellOption::Pipefail, true);
+        assert!(options.list().contains(&"pipefail    on".to_string()));
+        assert!(VariableCommand::parse("set", &args(&["-o", "vi"])).is_err());
+        assert!(VariableCommand::parse("set", &args(&["-q"])).is_err());
+
+        assert_eq!(
+            VariableCommand::parse_assignments("A=1", &args(&["B=x y"])),
+            Some(VariableCommand::Assign(vec![("A".to_string(), "1".to_string()), ("B".to_string(), "x y".to_string())]))
+        );
+        assert_eq!(VariableCommand::parse_assignments("A=1", &args(&["ls"])), None);
+    }
+
+    #[test]
+    fn test_shell_and_exported_variables() {
+        let mut vars = Variables::new();
+        vars.set("DSHELL_TEST_LOCAL", "1".to_string()).unwrap();
+        vars.export("DSHELL_TEST_EXPORTED", Some("2".to_string())).unwrap();
+        let exported: Vec<_> = vars.exported().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(exported, vec!["DSHELL_TEST_EXPORTED"]);
+
+        vars.export("DSHELL_TEST_LOCAL", None).unwrap();
+        assert!(vars.get("DSHELL_TEST_LOCAL").unwrap().exported);
+        assert!(vars.export("DSHELL_TEST_MISSING", None).is_err());
+
+        vars.readonly("DSHELL_TEST_LOCAL", None).unwrap();
+        assert!(vars.set("DSHELL_TEST_LOCAL", "3".to_string()).is_err());
+        assert!(vars.unset("DSHELL_TEST_LOCAL").is_err());
+        vars.unset("DSHELL_TEST_EXPORTED").unwrap();
+        assert!(vars.get("DSHELL_TEST_EXPORTED").is_none());
+    }
+
+    #[test]
+    fn test_unset_hides_inherited_variables() {
+        let mut permissions = PermissionManager::new();
+        permissions.set_env_access(Permission::Allowed);
+        let mut vars = Variables::new();
+        // PATH is inherited in any test environment
+        assert!(vars.value("PATH", &permissions).is_some());
+        vars.unset("PATH").unwrap();
+        assert_eq!(vars.value("PATH", &permissions), None);
+        assert!(!vars.names(&permissions).contains(&"PATH".to_string()));
+        vars.set("PATH", "/bin".to_string()).unwrap();
+        assert!(vars.get("PATH").unwrap().exported);
+        assert!(!vars.is_unset("PATH"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let permissions = PermissionManager::new();
+        let mut vars = Variables::new();
+        vars.set("DSHELL_TEST_NAME", "dshell".to_string()).unwrap();
+        vars.set("DSHELL_TEST_EMPTY", String::new()).unwrap();
+        vars.set_status(3);
+        assert_eq!(vars.expand("$DSHELL_TEST_NAME", &permissions), Ok("dshell".to_string()));
+        assert_eq!(vars.expand("${DSHELL_TEST_EMPTY:-fallback}", &permissions), Ok("fallback".to_string()));
+        assert_eq!(vars.expand("$?", &permissions), Ok("3".to_string()));
+        assert_eq!(vars.expand("$DSHELL_TEST_UNSET", &permissions), Ok(String::new()));
+
+        let previous = vars.set_positional(vec!["a b".to_string(), "c".to_string()]);
+        assert!(previous.is_empty());
+        assert_eq!(vars.expand("$1", &permissions), Ok("a b".to_string()));
+        assert_eq!(vars.expand("${2}", &permissions), Ok("c".to_string()));
+        assert_eq!(vars.expand("$#", &permissions), Ok("2".to_string()));
+        assert_eq!(vars.expand("$@", &permissions), Ok("a b\0c".to_string()));
+        assert_eq!(vars.expand("$3", &permissions), Ok(String::new()));
+
+        vars.options.set(ShellOption::Nounset, true);
+        assert!(vars.expand("$3", &permissions).is_err());
+        assert!(vars.expand("${DSHELL_TEST_UNSET}", &permissions).is_err());
+        assert_eq!(vars.expand("${DSHELL_TEST_UNSET:-x}", &permissions), Ok("x".to_string()));
+    }
+}
@@ -0,0 +1,307 @@
+//! ANSI escape sequence handling for captured command output
+//!
+//! SGR sequences (colors, bold, underline, ...) become styled spans. Every
+//! other escape sequence is dropped: cursor movement, screen clearing, OSC
+//! window titles and clipboard writes could otherwise take over the
+//! terminal dshell draws on. Carriage returns overwrite the line from the
+//! start, so progress output shows its final state.
+
+use super::screen::Style;
+use crossterm::style::Color;
+use unicode_width::UnicodeWidthChar;
+
+/// Tab stops are every this many columns
+const TAB_WIDTH: usize = 8;
+
+/// A styled byte range of a line
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyledSpan {
+    pub start: usize,
+    pub end: usize,
+    pub style: Style,
+}
+
+/// A line of output text with its styling; text has no escape sequences
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StyledLine {
+    pub text: String,
+    /// Non-default styles, sorted by position
+    pub spans: Vec<StyledSpan>,
+}
+
+impl StyledLine {
+    pub fn plain(text: String) -> Self {
+        StyledLine { text, spans: Vec::new() }
+    }
+
+    /// Style of the character starting at byte `pos`
+    pub fn style_at(&self, pos: usize) -> Style {
+        self.spans
+            .iter()
+            .find(|span| span.start <= pos && pos < span.end)
+            .map_or(Style::default(), |span| span.style)
+    }
+}
+
+/// Parser state carried between lines, since styles can span lines
+#[derive(Debug, Default)]
+pub struct AnsiParser {
+    style: Style,
+}
+
+impl AnsiParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse one line of output (without its newline)
+    pub fn parse_line(&mut self, line: &str) -> StyledLine {
+        let chars: Vec<char> = line.chars().collect();
+        let mut cells: Vec<(char, Style)> = Vec::new();
+        let mut col: usize = 0;
+        let mut i = 0;
+
+        let put = |cells: &mut Vec<(char, Style)>, col: &mut usize, c: char, style: Style| {
+            if *col < cells.len() {
+                cells[*col] = (c, style);
+            } else {
+                cells.push((c, style));
+            }
+            *col += 1;
+        };
+
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '\x1b' => {
+                    i = self.escape(&chars, i + 1);
+                    continue;
+                }
+                // 8-bit CSI and OSC introducers
+                '\u{9b}' => {
+                    i = self.csi(&chars, i + 1);
+                    continue;
+                }
+                '\u{9d}' => {
+                    i = skip_string(&chars, i + 1);
+                    continue;
+                }
+                '\r' => col = 0,
+                '\x08' => col = col.saturating_sub(1),
+                '\t' => {
+                    let width: usize = cells[..col.min(cells.len())].iter().map(|(c, _)| c.width().unwrap_or(0)).sum();
+                    for _ in 0..TAB_WIDTH - width % TAB_WIDTH {
+                        put(&mut cells, &mut col, ' ', self.style);
+                    }
+                }
+                c if c.is_control() => {}
+                c => put(&mut cells, &mut col, c, self.style),
+            }
+            i += 1;
+        }
+
+        let mut styled = StyledLine::default();
+        for (c, style) in cells {
+            let start = styled.text.len();
+            styled.text.push(c);
+            if style == Style::default() {
+                continue;
+            }
+            match styled.spans.last_mut() {
+                Some(span) if span.end == start && span.style == style => span.end = styled.text.len(),
+                _ => styled.spans.push(StyledSpan { start, end: styled.text.len(), style }),
+            }
+        }
+        styled
+    }
+
+    /// Handle the sequence after an ESC, returning the index after it
+    fn escape(&mut self, chars: &[char], i: usize) -> usize {
+        match chars.get(i) {
+            Some('[') => self.csi(chars, i + 1),
+            // OSC, DCS, SOS, PM and APC carry strings terminated by BEL or ST
+            Some(']' | 'P' | 'X' | '^' | '_') => skip_string(chars, i + 1),
+            Some(_) => {
+                // nF sequences have intermediate bytes before the final byte
+                let mut j = i;
+                while chars.get(j).is_some_and(|c| ('\x20'..='\x2f').contains(c)) {
+                    j += 1;
+                }
+                (j + 1).min(chars.len())
+            }
+            None => i,
+        }
+    }
+
+    /// Handle a CSI sequence; only SGR is applied, the rest is dropped
+    fn csi(&mut self, chars: &[char], i: usize) -> usize {
+        let mut j = i;
+        while chars.get(j).is_some_and(|c| ('\x20'..='\x3f').contains(c)) {
+            j += 1;
+        }
+        let Some(&last) = chars.get(j) else {
+            return j;
+        };
+
+        let params: String = chars[i..j].iter().collect();
+        let is_sgr = last == 'm' && params.chars().all(|c| c.is_ascii_digit() || c == ';' || c == ':');
+        if is_sgr {
+            apply_sgr(&mut self.style, &params);
+        }
+        j + 1
+    }
+}
+
+/// Apply an SGR (Select Graphic Rendition) parameter list to a style
+pub fn apply_sgr(style: &mut Style, params: &str) {
+    let codes: Vec<u16> = params
+        .split([';', ':'])
+        .map(|p| p.parse().unwrap_or(0))
+        .collect();
+    let mut k = 0;
+
+    while k < codes.len() {
+        let code = codes[k];
+        match code {
+            0 => *style = Style::default(),
+            1 => style.bold = true,
+            2 => style.dim = true,
+            3 => style.italic = true,
+            4 => style.underline = true,
+            7 => style.reverse = true,
+            22 => {
+                style.bold = false;
+                style.dim = false;
+            }
+            23 => style.italic = false,
+            24 => style.underline = false,
+            27 => style.reverse = false,
+            30..=37 => style.fg = Some(basic_color(code - 30)),
+            39 => style.fg = None,
+            40..=47 => style.bg = Some(basic_color(code - 40)),
+            49 => style.bg = None,
+            90..=97 => style.fg = Some(bright_color(code - 90)),
+            100..=107 => style.bg = Some(bright_color(code - 100)),
+            38 | 48 => {
+                let (color, used) = extended_color(&codes[k + 1..]);
+                if let Some(color) = color {
+                    if code == 38 {
+                        style.fg = Some(color);
+                    } else {
+                        style.bg = Some(color);
+                    }
+                }
+                k += used;
+            }
+            _ => {}
+        }
+        k += 1;
+    }
+}
+
+/// Parse raw output lines, keeping style state from one line to the next
+pub fn parse_lines(lines: &[String]) -> Vec<StyledLine> {
+    let mut parser = AnsiParser::new();
+    lines.iter().map(|line| parser.parse_line(line)).collect()
+}
+
+/// Skip an escape string up to BEL or ST (ESC \)
+fn skip_string(chars: &[char], mut i: usize) -> usize {
+    while i < chars.len() {
+        match chars[i] {
+            '\x07' | '\u{9c}' => return i + 1,
+            '\x1b' if chars.get(i + 1) == Some(&'\\') => return i + 2,
+            _ => i += 1,
+        }
+    }
+    i
+}
+
+fn basic_color(n: u16) -> Color {
+    match n {
+        0 => Color::Black,
+        1 => Color::DarkRed,
+        2 => Color::DarkGreen,
+        3 => Color::DarkYellow,
+        4 => Color::DarkBlue,
+        5 => Color::DarkMagenta,
+        6 => Color::DarkCyan,
+        _ => Color::Grey,
+    }
+}
+
+fn bright_color(n: u16) -> Color {
+    match n {
+        0 => Color::DarkGrey,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Yellow,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        _ => Color::White,
+    }
+}
+
+/// Parse `5;n` or `2;r;g;b` after 38/48, returning the color and how many
+/// parameters it used
+fn extended_color(params: &[u16]) -> (Option<Color>, usize) {
+    match params {
+        [5, n, ..] => (Some(Color::AnsiValue(*n as u8)), 2),
+        [2, r, g, b, ..] => (Some(Color::Rgb { r: *r as u8, g: *g as u8, b: *b as u8 }), 4),
+        _ => (None, params.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> StyledLine {
+        AnsiParser::new().parse_line(line)
+    }
+
+    #[test]
+    fn test_plain_text_is_unchanged() {
+        assert_eq!(parse("hello world"), StyledLine::plain("hello world".to_string()));
+    }
+
+    #[test]
+    fn test_sgr_colors_become_spans() {
+        let line = parse("a \x1b[1;31merror\x1b[0m b");
+        assert_eq!(line.text, "a error b");
+        assert_eq!(line.spans.len(), 1);
+        assert_eq!((line.spans[0].start, line.spans[0].end), (2, 7));
+        assert_eq!(line.spans[0].style.fg, Some(Color::DarkRed));
+        assert!(line.spans[0].style.bold);
+    }
+
+    #[test]
+    fn test_extended_colors() {
+        let line = parse("\x1b[38;5;208mx\x1b[48;2;1;2;3my");
+        assert_eq!(line.style_at(0).fg, Some(Color::AnsiValue(208)));
+        assert_eq!(line.style_at(1).bg, Some(Color::Rgb { r: 1, g: 2, b: 3 }));
+    }
+
+    #[test]
+    fn test_style_carries_across_lines() {
+        let lines = parse_lines(&["\x1b[32mgreen".to_string(), "still".to_string()]);
+        assert_eq!(lines[1].style_at(0).fg, Some(Color::DarkGreen));
+    }
+
+    #[test]
+    fn test_unsafe_sequences_are_stripped() {
+        // Cursor movement, screen clear, window title, clipboard write
+        let line = parse("a\x1b[2J\x1b[10;10Hb\x1b]0;pwned\x07c\x1b]52;c;ZXZpbA==\x1b\\d\x1b(Be");
+        assert_eq!(line.text, "abcde");
+        assert!(line.spans.is_empty());
+        assert_eq!(parse("x\x1b[?1049hy\x07\x00z").text, "xyz");
+    }
+
+    #[test]
+    fn test_carriage_return_overwrites() {
+        assert_eq!(parse("progress 10%\rprogress 100%").text, "progress 100%");
+        assert_eq!(parse("abcdef\rXY").text, "XYcdef");
+        assert_eq!(parse("a\tb").text, "a       b");
+    }
+}
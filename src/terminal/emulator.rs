@@ -0,0 +1,526 @@
+//! Terminal emulator model for commands run under a pseudo-terminal
+//!
+//! Programs on a PTY expect a real terminal: they move the cursor, redraw
+//! progress bars in place and erase lines. The emulator applies all of that
+//! to a grid of cells so dshell can show the program's screen while it runs
+//! and keep the final rendered text in the output buffer afterwards.
+//!
+//! Only the subset of xterm that line-oriented programs use is modeled.
+//! Full-screen programs switch to the alternate screen, whose contents are
+//! thrown away when they exit, just like in a real terminal.
+
+use super::ansi::{apply_sgr, StyledLine, StyledSpan};
+use super::screen::Style;
+use unicode_width::UnicodeWidthChar;
+
+/// Tab stops are every this many columns
+const TAB_WIDTH: usize = 8;
+
+/// Marks the second column of a wide character
+const WIDE_CONTINUATION: char = '\0';
+
+#[derive(Debug, Clone, Default)]
+struct Line {
+    cells: Vec<(char, Style)>,
+    /// The line was soft-wrapped and continues on the next one
+    wrapped: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    /// ESC followed by intermediate bytes
+    EscapeIntermediate,
+    Csi(String),
+    /// OSC, DCS and similar strings, skipped up to BEL or ST
+    String,
+    /// ESC seen inside a string, possibly starting ST
+    StringEscape,
+}
+
+/// A saved main screen while the alternate screen is active
+#[derive(Debug, Clone)]
+struct SavedScreen {
+    lines: Vec<Line>,
+    top: usize,
+    cursor: (usize, usize),
+}
+
+/// Everything the renderer needs to show a running program
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmulatorView {
+    pub lines: Vec<StyledLine>,
+    /// Cursor position as (index into `lines`, column)
+    pub cursor: (usize, usize),
+}
+
+pub struct TerminalEmulator {
+    width: usize,
+    height: usize,
+    /// Scrollback and screen; the screen is the `height` lines from `top`
+    lines: Vec<Line>,
+    top: usize,
+    /// Absolute line index and column
+    cursor: (usize, usize),
+    saved_cursor: (usize, usize),
+    style: Style,
+    state: State,
+    /// Bytes of an incomplete UTF-8 sequence from the previous read
+    pending: Vec<u8>,
+    alternate: Option<SavedScreen>,
+    /// Replies to terminal queries, to be written back to the program
+    responses: Vec<u8>,
+}
+
+impl TerminalEmulator {
+    pub fn new(width: usize, height: usize) -> Self {
+        TerminalEmulator {
+            width: width.max(1),
+            height: height.max(1),
+            lines: vec![Line::default()],
+            top: 0,
+            cursor: (0, 0),
+            saved_cursor: (0, 0),
+            style: Style::default(),
+            state: State::Ground,
+            pending: Vec::new(),
+            alternate: None,
+            responses: Vec::new(),
+        }
+    }
+
+    /// Change the screen size; existing lines are not reflowed
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width.max(1);
+        self.height = height.max(1);
+        self.top = self.top.max((self.cursor.0 + 1).saturating_sub(self.height));
+        self.cursor.1 = self.cursor.1.min(self.width - 1);
+    }
+
+    /// Whether the program is showing the alternate screen
+    pub fn in_alternate_screen(&self) -> bool {
+        self.alternate.is_some()
+    }
+
+    /// Take the replies to terminal queries (cursor position, device
+    /// attributes) that should be written back to the program
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.responses)
+    }
+
+    /// Process output from the program
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+        let data = std::mem::take(&mut self.pending);
+
+        let mut rest = &data[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(text) => {
+                    text.chars().for_each(|c| self.process(c));
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    // Safe: from_utf8 just validated this prefix
+                    std::str::from_utf8(valid).unwrap_or_default().chars().for_each(|c| self.process(c));
+                    match e.error_len() {
+                        Some(len) => {
+                            self.process(char::REPLACEMENT_CHARACTER);
+                            rest = &after[len..];
+                        }
+                        None => {
+                            // Incomplete sequence; wait for the rest
+                            self.pending = after.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The current screen contents for display while the program runs;
+    /// lines that scrolled off the top only show up in `into_lines`
+    pub fn view(&self) -> EmulatorView {
+        let lines = self.lines[self.top..].iter().map(Self::styled).collect();
+        EmulatorView { lines, cursor: (self.cursor.0 - self.top, self.cursor.1) }
+    }
+
+    /// The final text: the main screen and its scrollback, with soft-wrapped
+    /// lines joined back together and trailing blank lines removed
+    pub fn into_lines(self) -> Vec<StyledLine> {
+        let lines = match self.alternate {
+            Some(saved) => saved.lines,
+            None => self.lines,
+        };
+
+        let mut result: Vec<StyledLine> = Vec::new();
+        let mut joining = false;
+        for line in &lines {
+            let styled = Self::styled(line);
+            match result.last_mut() {
+                Some(previous) if joining => {
+                    let offset = previous.text.len();
+                    previous.text.push_str(&styled.text);
+                    previous.spans.extend(styled.spans.into_iter().map(|span| StyledSpan {
+                        start: span.start + offset,
+                        end: span.end + offset,
+                        style: span.style,
+                    }));
+                }
+                _ => result.push(styled),
+            }
+            joining = line.wrapped;
+        }
+
+        while result.last().is_some_and(|line| line.text.is_empty()) {
+            result.pop();
+        }
+        result
+    }
+
+    fn styled(line: &Line) -> StyledLine {
+        // Trailing blanks are padding, not output
+        let len = line
+            .cells
+            .iter()
+            .rposition(|(c, style)| *c != ' ' || *style != Style::default())
+            .map_or(0, |i| i + 1);
+
+        let mut styled = StyledLine::default();
+        for (c, style) in &line.cells[..len] {
+            if *c == WIDE_CONTINUATION {
+                continue;
+            }
+            let start = styled.text.len();
+            styled.text.push(*c);
+            if *style == Style::default() {
+                continue;
+            }
+            match styled.spans.last_mut() {
+                Some(span) if span.end == start && span.style == *style => span.end = styled.text.len(),
+                _ => styled.spans.push(StyledSpan { start, end: styled.text.len(), style: *style }),
+            }
+        }
+        styled
+    }
+
+    fn process(&mut self, c: char) {
+        match std::mem::replace(&mut self.state, State::Ground) {
+            State::Ground => self.ground(c),
+            State::Escape => self.escape(c),
+            State::EscapeIntermediate => {
+                if ('\x20'..='\x2f').contains(&c) {
+                    self.state = State::EscapeIntermediate;
+                }
+            }
+            State::Csi(mut params) => {
+                if ('\x20'..='\x3f').contains(&c) {
+                    params.push(c);
+                    self.state = State::Csi(params);
+                } else {
+                    self.csi(&params, c);
+                }
+            }
+            State::String => {
+                self.state = match c {
+                    '\x07' | '\u{9c}' => State::Ground,
+                    '\x1b' => State::StringEscape,
+                    _ => State::String,
+                };
+            }
+            State::StringEscape => {
+                if c != '\\' {
+                    self.state = State::String;
+                }
+            }
+        }
+    }
+
+    fn ground(&mut self, c: char) {
+        match c {
+            '\x1b' => self.state = State::Escape,
+            '\u{9b}' => self.state = State::Csi(String::new()),
+            '\u{9d}' | '\u{90}' => self.state = State::String,
+            '\r' => self.cursor.1 = 0,
+            '\n' | '\x0b' | '\x0c' => self.line_feed(),
+            '\x08' => self.cursor.1 = self.cursor.1.saturating_sub(1),
+            '\t' => {
+                let next = (self.cursor.1 / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.cursor.1 = next.min(self.width - 1);
+            }
+            c if c.is_control() => {}
+            c => self.print(c),
+        }
+    }
+
+    fn escape(&mut self, c: char) {
+        match c {
+            '[' => self.state = State::Csi(String::new()),
+            ']' | 'P' | 'X' | '^' | '_' => self.state = State::String,
+            '7' => self.saved_cursor = self.cursor,
+            '8' => self.cursor = self.saved_cursor,
+            'D' => self.line_feed(),
+            'E' => {
+                self.line_feed();
+                self.cursor.1 = 0;
+            }
+            'M' if self.cursor.0 > self.top => self.cursor.0 -= 1,
+            'c' => {
+                self.style = Style::default();
+                self.erase_display(2);
+                self.cursor = (self.top, 0);
+            }
+            '\x20'..='\x2f' => self.state = State::EscapeIntermediate,
+            _ => {}
+        }
+    }
+
+    fn csi(&mut self, params: &str, action: char) {
+        let private = params.starts_with(['?', '>', '<', '=']);
+        let numbers: Vec<usize> = params
+            .trim_start_matches(['?', '>', '<', '='])
+            .split(';')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect();
+        let arg = |i: usize| numbers.get(i).copied().filter(|n| *n > 0).unwrap_or(1);
+        let bottom = self.top + self.height - 1;
+
+        match (private, action) {
+            (false, 'm') => apply_sgr(&mut self.style, params),
+            (false, 'A') => self.cursor.0 = self.cursor.0.saturating_sub(arg(0)).max(self.top),
+            (false, 'B') => self.move_to_row((self.cursor.0 + arg(0)).min(bottom)),
+            (false, 'C') => self.cursor.1 = (self.cursor.1 + arg(0)).min(self.width - 1),
+            (false, 'D') => self.cursor.1 = self.cursor.1.saturating_sub(arg(0)),
+            (false, 'E') => {
+                self.move_to_row((self.cursor.0 + arg(0)).min(bottom));
+                self.cursor.1 = 0;
+            }
+            (false, 'F') => {
+                self.cursor.0 = self.cursor.0.saturating_sub(arg(0)).max(self.top);
+                self.cursor.1 = 0;
+            }
+            (false, 'G' | '`') => self.cursor.1 = (arg(0) - 1).min(self.width - 1),
+            (false, 'H' | 'f') => {
+                self.move_to_row((self.top + arg(0) - 1).min(bottom));
+                self.cursor.1 = (arg(1) - 1).min(self.width - 1);
+            }
+            (false, 'd') => self.move_to_row((self.top + arg(0) - 1).min(bottom)),
+            (false, 'J') => self.erase_display(numbers[0]),
+            (false, 'K') => self.erase_line(numbers[0]),
+            (false, 'X') => {
+                let (row, col) = self.cursor;
+                self.fill(row, col, col + arg(0));
+            }
+            (false, 'P') => {
+                let (row, col) = self.cursor;
+                let cells = &mut self.lines[row].cells;
+                if col < cells.len() {
+                    cells.drain(col..(col + arg(0)).min(cells.len()));
+                }
+            }
+            (false, '@') => {
+                let (row, col) = self.cursor;
+                let width = self.width;
+                let cells = &mut self.lines[row].cells;
+                if col < cells.len() {
+                    for _ in 0..arg(0) {
+                        cells.insert(col, (' ', Style::default()));
+                    }
+                    cells.truncate(width);
+                }
+            }
+            (false, 's') => self.saved_cursor = self.cursor,
+            (false, 'u') => self.cursor = self.saved_cursor,
+            (false, 'n') if numbers[0] == 6 => {
+                let reply = format!("\x1b[{};{}R", self.cursor.0 - self.top + 1, self.cursor.1 + 1);
+                self.responses.extend_from_slice(reply.as_bytes());
+            }
+            (false, 'c') => self.responses.extend_from_slice(b"\x1b[?1;2c"),
+            (true, 'h') if numbers.iter().any(|n| matches!(n, 47 | 1047 | 1049)) => self.enter_alternate_screen(),
+            (true, 'l') if numbers.iter().any(|n| matches!(n, 47 | 1047 | 1049)) => self.leave_alternate_screen(),
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, c: char) {
+        let w = c.width().unwrap_or(0);
+        if w == 0 {
+            return;
+        }
+        if self.cursor.1 + w > self.width {
+            // Autowrap onto the next line
+            self.lines[self.cursor.0].wrapped = true;
+            self.line_feed();
+            self.cursor.1 = 0;
+        }
+
+        let (row, col) = self.cursor;
+        self.fill(row, self.lines[row].cells.len(), col + w);
+        let cells = &mut self.lines[row].cells;
+        cells[col] = (c, self.style);
+        if w == 2 {
+            cells[col + 1] = (WIDE_CONTINUATION, self.style);
+        }
+        self.cursor.1 += w;
+    }
+
+    /// Move down a line, scrolling the screen at the bottom
+    fn line_feed(&mut self) {
+        let row = self.cursor.0 + 1;
+        self.move_to_row(row);
+        if row >= self.top + self.height {
+            self.top += 1;
+        }
+    }
+
+    fn move_to_row(&mut self, row: usize) {
+        while self.lines.len() <= row {
+            self.lines.push(Line::default());
+        }
+        self.cursor.0 = row;
+    }
+
+    /// Set cells `from..to` of a row to blanks, growing the row if needed
+    fn fill(&mut self, row: usize, from: usize, to: usize) {
+        let to = to.min(self.width);
+        let cells = &mut self.lines[row].cells;
+        if cells.len() < to {
+            cells.resize(to, (' ', Style::default()));
+        }
+        for cell in cells.iter_mut().take(to).skip(from) {
+            *cell = (' ', Style::default());
+        }
+    }
+
+    fn erase_line(&mut self, mode: usize) {
+        let (row, col) = self.cursor;
+        match mode {
+            0 => self.lines[row].cells.truncate(col),
+            1 => self.fill(row, 0, col + 1),
+            _ => self.lines[row].cells.clear(),
+        }
+        if mode != 1 {
+            self.lines[row].wrapped = false;
+        }
+    }
+
+    fn erase_display(&mut self, mode: usize) {
+        let (row, _) = self.cursor;
+        let screen_end = (self.top + self.height).min(self.lines.len());
+        match mode {
+            0 => {
+                self.erase_line(0);
+                for line in &mut self.lines[row + 1..screen_end] {
+                    *line = Line::default();
+                }
+            }
+            1 => {
+                self.erase_line(1);
+                for line in &mut self.lines[self.top..row] {
+                    *line = Line::default();
+                }
+            }
+            _ => {
+                for line in &mut self.lines[self.top..screen_end] {
+                    *line = Line::default();
+                }
+            }
+        }
+    }
+
+    fn enter_alternate_screen(&mut self) {
+        if self.alternate.is_none() {
+            let saved = SavedScreen {
+                lines: std::mem::replace(&mut self.lines, vec![Line::default()]),
+                top: self.top,
+                cursor: self.cursor,
+            };
+            self.alternate = Some(saved);
+            self.top = 0;
+            self.cursor = (0, 0);
+        }
+    }
+
+    fn leave_alternate_screen(&mut self) {
+        if let Some(saved) = self.alternate.take() {
+            self.lines = saved.lines;
+            self.top = saved.top;
+            self.cursor = saved.cursor;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(width: usize, output: &str) -> Vec<String> {
+        let mut emulator = TerminalEmulator::new(width, 5);
+        emulator.feed(output.as_bytes());
+        emulator.into_lines().into_iter().map(|line| line.text).collect()
+    }
+
+    #[test]
+    fn test_plain_lines() {
+        assert_eq!(run(80, "one\r\ntwo\r\n"), vec!["one", "two"]);
+    }
+
+    #[test]
+    fn test_progress_bar_redraws_in_place() {
+        assert_eq!(run(80, "[   ] 0%\r[## ] 50%\r[###] 100%\r\ndone\r\n"), vec!["[###] 100%", "done"]);
+        // Erase to end of line after moving back
+        assert_eq!(run(80, "downloading...\r\x1b[Kdone"), vec!["done"]);
+    }
+
+    #[test]
+    fn test_cursor_movement() {
+        assert_eq!(run(80, "a\r\nb\r\n\x1b[2Ax\x1b[1;3Hy"), vec!["x y", "b"]);
+    }
+
+    #[test]
+    fn test_soft_wrapped_lines_are_joined() {
+        assert_eq!(run(4, "abcdefgh\r\nij"), vec!["abcdefgh", "ij"]);
+    }
+
+    #[test]
+    fn test_colors_are_kept() {
+        let mut emulator = TerminalEmulator::new(80, 5);
+        emulator.feed(b"\x1b[31mred\x1b[0m plain");
+        let lines = emulator.into_lines();
+        assert_eq!(lines[0].text, "red plain");
+        assert_eq!(lines[0].spans.len(), 1);
+        assert_eq!(lines[0].spans[0].end, 3);
+    }
+
+    #[test]
+    fn test_split_utf8_and_escape_sequences() {
+        let mut emulator = TerminalEmulator::new(80, 5);
+        let bytes = "é\x1b[1mb".as_bytes();
+        for chunk in bytes.chunks(1) {
+            emulator.feed(chunk);
+        }
+        let lines = emulator.into_lines();
+        assert_eq!(lines[0].text, "éb");
+        assert!(lines[0].spans[0].style.bold);
+    }
+
+    #[test]
+    fn test_alternate_screen_is_discarded() {
+        let mut emulator = TerminalEmulator::new(80, 5);
+        emulator.feed(b"before\r\n\x1b[?1049hfull screen app");
+        assert!(emulator.in_alternate_screen());
+        emulator.feed(b"\x1b[?1049lafter");
+        let lines: Vec<String> = emulator.into_lines().into_iter().map(|l| l.text).collect();
+        assert_eq!(lines, vec!["before", "after"]);
+    }
+
+    #[test]
+    fn test_cursor_position_query_is_answered() {
+        let mut emulator = TerminalEmulator::new(80, 5);
+        emulator.feed(b"ab\x1b[6n");
+        assert_eq!(emulator.take_responses(), b"\x1b[1;3R");
+    }
+}
@@ -1,21 +1,41 @@
-/// Input handling and line editing
+//! Input handling and line editing
 
 use crate::config::MAX_HISTORY_SIZE;
+use crate::shell::completion::{common_prefix, Candidate, Completion};
+use crate::shell::history::HistoryEntry;
+use crate::shell::parser::needs_continuation;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use arboard::Clipboard;
+use std::path::Path;
 
 pub struct InputEditor {
     buffer: String,
     cursor_pos: usize,
-    history: Vec<String>,
+    history: Vec<HistoryEntry>,
     history_index: Option<usize>,
     saved_buffer: Option<String>,
+    completion_menu: Option<CompletionMenu>,
+}
+
+/// Candidates shown below the input line after an ambiguous Tab
+#[derive(Debug, Clone)]
+pub struct CompletionMenu {
+    pub candidates: Vec<Candidate>,
+    pub selected: Option<usize>,
+    /// Byte range in the buffer currently occupied by the completed word
+    start: usize,
+    end: usize,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum InputAction {
     None,
     Submit(String),
+    Complete,
+    /// Text pasted from the clipboard, to be reviewed before insertion
+    Paste(String),
+    /// Ctrl+C: the line being edited was abandoned
+    Cancel(String),
     Exit,
 }
 
@@ -27,6 +47,7 @@ impl InputEditor {
             history: Vec::new(),
             history_index: None,
             saved_buffer: None,
+            completion_menu: None,
         }
     }
 
@@ -46,22 +67,85 @@ impl InputEditor {
         self.cursor_pos = 0;
         self.history_index = None;
         self.saved_buffer = None;
+        self.completion_menu = None;
+    }
+
+    /// Get the completion menu, if one is showing
+    pub fn completion_menu(&self) -> Option<&CompletionMenu> {
+        self.completion_menu.as_ref()
+    }
+
+    /// Apply the result of completing the word before the cursor
+    ///
+    /// A unique candidate is inserted directly. Otherwise the common prefix is
+    /// inserted and the candidates are kept for the menu; further Tab presses
+    /// cycle through them.
+    pub fn apply_completion(&mut self, completion: Completion) {
+        let Completion { start, candidates } = completion;
+        let end = self.cursor_pos;
+
+        match candidates.len() {
+            0 => {}
+            1 => self.replace_range(start, end, &candidates[0].replacement),
+            _ => {
+                let prefix = common_prefix(&candidates);
+                let new_end = if prefix.len() > end - start {
+                    self.replace_range(start, end, &prefix);
+                    start + prefix.len()
+                } else {
+                    end
+                };
+                self.completion_menu = Some(CompletionMenu {
+                    candidates,
+                    selected: None,
+                    start,
+                    end: new_end,
+                });
+            }
+        }
+    }
+
+    /// Select the next (or previous) menu candidate and insert it
+    fn cycle_completion(&mut self, forward: bool) {
+        let Some(mut menu) = self.completion_menu.take() else {
+            return;
+        };
+
+        let count = menu.candidates.len();
+        let next = match (menu.selected, forward) {
+            (None, true) => 0,
+            (None, false) => count - 1,
+            (Some(i), true) => (i + 1) % count,
+            (Some(i), false) => (i + count - 1) % count,
+        };
+
+        let replacement = menu.candidates[next].replacement.clone();
+        self.replace_range(menu.start, menu.end, &replacement);
+        menu.end = menu.start + replacement.len();
+        menu.selected = Some(next);
+        self.completion_menu = Some(menu);
+    }
+
+    /// Replace a byte range of the buffer and leave the cursor after it
+    fn replace_range(&mut self, start: usize, end: usize, text: &str) {
+        self.buffer.replace_range(start..end, text);
+        self.cursor_pos = start + text.len();
     }
 
     /// Add a command to history
-    pub fn add_to_history(&mut self, command: String) {
-        if command.is_empty() {
+    pub fn add_to_history(&mut self, entry: HistoryEntry) {
+        if entry.command.is_empty() {
             return;
         }
 
         // Don't add duplicate consecutive entries
-        if let Some(last) = self.history.last() {
-            if last == &command {
-                return;
-            }
+        if let Some(last) = self.history.last()
+            && last.command == entry.command
+        {
+            return;
         }
 
-        self.history.push(command);
+        self.history.push(entry);
 
         // Limit history size
         if self.history.len() > MAX_HISTORY_SIZE {
@@ -69,33 +153,119 @@ impl InputEditor {
         }
     }
 
+    /// Get the command history, oldest first
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+
+    /// Get the autosuggestion for the current input: the untyped remainder of
+    /// the most recent matching history entry
+    ///
+    /// Entries run in `cwd` are preferred over ones from other directories,
+    /// and entries that contain secrets are never suggested.
+    pub fn suggestion(&self, cwd: Option<&Path>) -> Option<&str> {
+        if self.buffer.is_empty() || self.cursor_pos != self.buffer.len() || self.history_index.is_some() {
+            return None;
+        }
+
+        let candidates = || {
+            self.history.iter().rev().filter(|entry| {
+                !entry.sensitive
+                    && entry.command.len() > self.buffer.len()
+                    && entry.command.starts_with(&self.buffer)
+            })
+        };
+
+        candidates()
+            .find(|entry| cwd.is_some() && entry.cwd.as_deref() == cwd)
+            .or_else(|| candidates().next())
+            .map(|entry| &entry.command[self.buffer.len()..])
+    }
+
+    /// Accept the whole autosuggestion, or just its first word
+    fn accept_suggestion(&mut self, whole: bool) -> bool {
+        let cwd = std::env::current_dir().ok();
+        let Some(suggestion) = self.suggestion(cwd.as_deref()) else {
+            return false;
+        };
+
+        let accepted = if whole {
+            suggestion.to_string()
+        } else {
+            // Leading whitespace plus the next word
+            let trimmed = suggestion.trim_start();
+            let leading = suggestion.len() - trimmed.len();
+            let word_len = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+            suggestion[..leading + word_len].to_string()
+        };
+
+        self.buffer.push_str(&accepted);
+        self.cursor_pos = self.buffer.len();
+        true
+    }
+
+    /// Delete a history entry by its 1-based number
+    pub fn delete_history_entry(&mut self, number: usize) -> bool {
+        if number == 0 || number > self.history.len() {
+            return false;
+        }
+        self.history.remove(number - 1);
+        self.history_index = None;
+        true
+    }
+
+    /// Remove all history entries
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+        self.history_index = None;
+    }
+
     /// Handle a key event and return the action to take
     pub fn handle_key(&mut self, event: KeyEvent) -> InputAction {
+        match event.code {
+            KeyCode::Tab if self.completion_menu.is_some() => {
+                self.cycle_completion(true);
+                return InputAction::None;
+            }
+            KeyCode::BackTab if self.completion_menu.is_some() => {
+                self.cycle_completion(false);
+                return InputAction::None;
+            }
+            KeyCode::Tab => return InputAction::Complete,
+            // Any other key dismisses the menu
+            _ => self.completion_menu = None,
+        }
+
         match event.code {
             KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => {
-                InputAction::Exit
+                InputAction::Cancel(self.take_line().0)
             }
             KeyCode::Char('d') if event.modifiers.contains(KeyModifiers::CONTROL) => {
                 InputAction::Exit
             }
             KeyCode::Char('v') if event.modifiers.contains(KeyModifiers::CONTROL) => {
                 // Ctrl+V: Paste from clipboard
-                self.paste_from_clipboard();
-                InputAction::None
+                Self::paste_from_clipboard()
             }
             KeyCode::Char('V') if event.modifiers.contains(KeyModifiers::CONTROL | KeyModifiers::SHIFT) => {
                 // Ctrl+Shift+V: Paste from clipboard (common terminal shortcut)
-                self.paste_from_clipboard();
+                Self::paste_from_clipboard()
+            }
+            KeyCode::Char('f') if event.modifiers.contains(KeyModifiers::ALT) => {
+                // Alt+F: accept one word of the autosuggestion
+                self.accept_suggestion(false);
                 InputAction::None
             }
+            // Other control keys, such as Ctrl+Z and Ctrl+\ with no command
+            // running, do nothing rather than type their letter
+            KeyCode::Char(_) if event.modifiers.contains(KeyModifiers::CONTROL) => InputAction::None,
             KeyCode::Char(c) => {
-                self.buffer.insert(self.cursor_pos, c);
-                self.cursor_pos += 1;
+                self.insert_char(c);
                 InputAction::None
             }
             KeyCode::Backspace => {
                 if self.cursor_pos > 0 {
-                    self.cursor_pos -= 1;
+                    self.cursor_pos = self.prev_boundary();
                     self.buffer.remove(self.cursor_pos);
                 }
                 InputAction::None
@@ -107,34 +277,55 @@ impl InputEditor {
                 InputAction::None
             }
             KeyCode::Left => {
-                if self.cursor_pos > 0 {
-                    self.cursor_pos -= 1;
-                }
+                self.cursor_pos = self.prev_boundary();
                 InputAction::None
             }
             KeyCode::Right => {
                 if self.cursor_pos < self.buffer.len() {
-                    self.cursor_pos += 1;
+                    self.cursor_pos = self.next_boundary();
+                } else {
+                    self.accept_suggestion(true);
                 }
                 InputAction::None
             }
             KeyCode::Home => {
-                self.cursor_pos = 0;
+                self.cursor_pos = self.line_start();
                 InputAction::None
             }
             KeyCode::End => {
-                self.cursor_pos = self.buffer.len();
+                if self.cursor_pos == self.buffer.len() {
+                    self.accept_suggestion(true);
+                }
+                self.cursor_pos = self.line_end();
                 InputAction::None
             }
             KeyCode::Up => {
-                self.navigate_history_up();
+                // Move between lines of a multi-line command before history
+                if self.line_start() > 0 {
+                    self.move_vertically(false);
+                } else {
+                    self.navigate_history_up();
+                }
                 InputAction::None
             }
             KeyCode::Down => {
-                self.navigate_history_down();
+                if self.line_end() < self.buffer.len() {
+                    self.move_vertically(true);
+                } else {
+                    self.navigate_history_down();
+                }
+                InputAction::None
+            }
+            KeyCode::Enter if event.modifiers.contains(KeyModifiers::ALT) => {
+                // Alt+Enter: literal newline
+                self.insert_char('\n');
                 InputAction::None
             }
             KeyCode::Enter => {
+                if needs_continuation(&self.buffer) {
+                    self.insert_char('\n');
+                    return InputAction::None;
+                }
                 let command = self.buffer.clone();
                 InputAction::Submit(command)
             }
@@ -142,6 +333,55 @@ impl InputEditor {
         }
     }
 
+    fn insert_char(&mut self, c: char) {
+        self.buffer.insert(self.cursor_pos, c);
+        self.cursor_pos += c.len_utf8();
+    }
+
+    /// Byte index of the character before the cursor
+    fn prev_boundary(&self) -> usize {
+        self.buffer[..self.cursor_pos]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    /// Byte index of the character after the cursor
+    fn next_boundary(&self) -> usize {
+        self.buffer[self.cursor_pos..]
+            .chars()
+            .next()
+            .map_or(self.cursor_pos, |c| self.cursor_pos + c.len_utf8())
+    }
+
+    /// Byte index where the cursor's line starts
+    fn line_start(&self) -> usize {
+        self.buffer[..self.cursor_pos].rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    /// Byte index where the cursor's line ends (before its newline)
+    fn line_end(&self) -> usize {
+        self.buffer[self.cursor_pos..]
+            .find('\n')
+            .map_or(self.buffer.len(), |i| self.cursor_pos + i)
+    }
+
+    /// Move the cursor to the same column of the next or previous line
+    fn move_vertically(&mut self, down: bool) {
+        let column = self.buffer[self.line_start()..self.cursor_pos].chars().count();
+        let target_start = if down {
+            self.line_end() + 1
+        } else {
+            self.buffer[..self.line_start() - 1].rfind('\n').map_or(0, |i| i + 1)
+        };
+        let target_line = self.buffer[target_start..].split('\n').next().unwrap_or_default();
+        let offset = target_line
+            .char_indices()
+            .nth(column)
+            .map_or(target_line.len(), |(i, _)| i);
+        self.cursor_pos = target_start + offset;
+    }
+
     /// Navigate up in command history
     fn navigate_history_up(&mut self) {
         if self.history.is_empty() {
@@ -153,13 +393,13 @@ impl InputEditor {
                 // Save current buffer and move to last history item
                 self.saved_buffer = Some(self.buffer.clone());
                 self.history_index = Some(self.history.len() - 1);
-                self.buffer = self.history[self.history.len() - 1].clone();
+                self.buffer = self.history[self.history.len() - 1].command.clone();
                 self.cursor_pos = self.buffer.len();
             }
             Some(idx) if idx > 0 => {
                 // Move to previous history item
                 self.history_index = Some(idx - 1);
-                self.buffer = self.history[idx - 1].clone();
+                self.buffer = self.history[idx - 1].command.clone();
                 self.cursor_pos = self.buffer.len();
             }
             _ => {}
@@ -172,7 +412,7 @@ impl InputEditor {
             Some(idx) if idx < self.history.len() - 1 => {
                 // Move to next history item
                 self.history_index = Some(idx + 1);
-                self.buffer = self.history[idx + 1].clone();
+                self.buffer = self.history[idx + 1].command.clone();
                 self.cursor_pos = self.buffer.len();
             }
             Some(_) => {
@@ -187,21 +427,31 @@ impl InputEditor {
         }
     }
 
-    /// Paste content from clipboard at cursor position
-    fn paste_from_clipboard(&mut self) {
-        if let Ok(mut clipboard) = Clipboard::new() {
-            if let Ok(text) = clipboard.get_text() {
-                // Filter out newlines and control characters for single-line input
-                let sanitized: String = text
-                    .chars()
-                    .filter(|c| !c.is_control() || *c == ' ')
-                    .collect();
+    /// Read the clipboard; the caller reviews the text before inserting it
+    fn paste_from_clipboard() -> InputAction {
+        Clipboard::new()
+            .and_then(|mut clipboard| clipboard.get_text())
+            .map_or(InputAction::None, InputAction::Paste)
+    }
 
-                // Insert at cursor position
-                self.buffer.insert_str(self.cursor_pos, &sanitized);
-                self.cursor_pos += sanitized.len();
-            }
-        }
+    /// Insert text at the cursor position
+    pub fn insert_text(&mut self, text: &str) {
+        self.buffer.insert_str(self.cursor_pos, text);
+        self.cursor_pos += text.len();
+    }
+
+    /// Take the current line, leaving the editor empty
+    pub fn take_line(&mut self) -> (String, usize) {
+        let line = (std::mem::take(&mut self.buffer), self.cursor_pos);
+        self.clear();
+        line
+    }
+
+    /// Restore a line saved with `take_line`
+    pub fn restore_line(&mut self, (buffer, cursor_pos): (String, usize)) {
+        self.clear();
+        self.cursor_pos = cursor_pos.min(buffer.len());
+        self.buffer = buffer;
     }
 }
 
@@ -210,3 +460,60 @@ impl Default for InputEditor {
         Self::new()
     }
 }
+
+/// Encode a key press the way a terminal sends it to a program
+pub fn key_to_bytes(key: &KeyEvent) -> Vec<u8> {
+    let mut bytes = match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => match c.to_ascii_lowercase() {
+            c @ 'a'..='z' => vec![c as u8 - b'a' + 1],
+            '[' => vec![0x1b],
+            // Terminals report Ctrl+\ as Ctrl+4
+            '\\' | '4' => vec![0x1c],
+            ']' => vec![0x1d],
+            ' ' | '@' => vec![0],
+            _ => Vec::new(),
+        },
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => b"\r".to_vec(),
+        KeyCode::Tab => b"\t".to_vec(),
+        KeyCode::BackTab => b"\x1b[Z".to_vec(),
+        KeyCode::Backspace => vec![0x7f],
+        KeyCode::Esc => vec![0x1b],
+        KeyCode::Up => b"\x1b[A".to_vec(),
+        KeyCode::Down => b"\x1b[B".to_vec(),
+        KeyCode::Right => b"\x1b[C".to_vec(),
+        KeyCode::Left => b"\x1b[D".to_vec(),
+        KeyCode::Home => b"\x1b[H".to_vec(),
+        KeyCode::End => b"\x1b[F".to_vec(),
+        KeyCode::Insert => b"\x1b[2~".to_vec(),
+        KeyCode::Delete => b"\x1b[3~".to_vec(),
+        KeyCode::PageUp => b"\x1b[5~".to_vec(),
+        KeyCode::PageDown => b"\x1b[6~".to_vec(),
+        KeyCode::F(n @ 1..=4) => vec![0x1b, b'O', b'P' + n - 1],
+        _ => Vec::new(),
+    };
+
+    // Alt sends an ESC prefix
+    if key.modifiers.contains(KeyModifiers::ALT) && !bytes.is_empty() {
+        bytes.insert(0, 0x1b);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_keys_at_prompt_insert_nothing() {
+        let mut editor = InputEditor::new();
+        editor.handle_key(KeyEvent::new(KeyCode::Char('l'), KeyModifiers::NONE));
+        editor.handle_key(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::NONE));
+
+        for c in ['z', '\\', '4'] {
+            assert_eq!(editor.handle_key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)), InputAction::None);
+        }
+        assert_eq!(editor.buffer(), "ls");
+        assert_eq!(editor.cursor_pos(), 2);
+    }
+}
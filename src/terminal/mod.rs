@@ -1,7 +1,11 @@
-/// Terminal module - UI and input handling
+//! Terminal module - UI and input handling
 
+pub mod ansi;
+pub mod emulator;
 pub mod input;
+pub mod paste;
 pub mod renderer;
+pub mod screen;
 
 pub use input::{InputAction, InputEditor};
-pub use renderer::TerminalRenderer;
+pub use renderer::{InputView, TerminalRenderer};
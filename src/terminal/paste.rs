@@ -0,0 +1,185 @@
+//! Paste safety review
+//!
+//! Pasted text is inserted into the input line as a whole and never runs on
+//! its own. Pastes that could hide what they do — multiple lines, control
+//! characters, invisible Unicode, or well-known dangerous commands — are
+//! shown to the user for confirmation first.
+
+/// Maximum number of preview lines shown for a paste under review
+const MAX_PREVIEW_LINES: usize = 10;
+
+/// Command fragments that deserve a second look before they are inserted
+const DANGEROUS_PATTERNS: &[(&str, &str)] = &[
+    ("rm -rf /", "recursively deletes from the filesystem root"),
+    ("rm -fr /", "recursively deletes from the filesystem root"),
+    ("mkfs", "formats a filesystem"),
+    ("dd if=", "writes raw data with dd"),
+    ("> /dev/sd", "writes directly to a disk device"),
+    (":(){", "looks like a fork bomb"),
+    ("chmod -r 777", "makes files world-writable"),
+    ("sudo ", "runs a command as root"),
+    ("base64 -d", "decodes hidden content"),
+    ("eval ", "evaluates generated code"),
+];
+
+/// Programs that should not receive downloaded code on stdin
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh", "fish", "python", "python3", "perl", "ruby", "node"];
+
+/// Why a paste needs confirmation
+#[derive(Debug, Clone, PartialEq)]
+pub enum PasteWarning {
+    /// The paste spans several lines
+    MultipleLines(usize),
+    /// The paste contains terminal control characters or escape sequences
+    ControlCharacters,
+    /// The paste contains invisible or direction-changing Unicode
+    HiddenCharacters,
+    /// The paste matches a dangerous command pattern
+    Dangerous(&'static str),
+}
+
+impl PasteWarning {
+    pub fn describe(&self) -> String {
+        match self {
+            PasteWarning::MultipleLines(n) => format!("contains {} lines", n),
+            PasteWarning::ControlCharacters => "contains control characters or escape sequences".to_string(),
+            PasteWarning::HiddenCharacters => "contains invisible or bidirectional Unicode characters".to_string(),
+            PasteWarning::Dangerous(reason) => reason.to_string(),
+        }
+    }
+}
+
+/// Check pasted text, returning every reason it needs confirmation
+pub fn review(text: &str) -> Vec<PasteWarning> {
+    let mut warnings = Vec::new();
+    let text = normalize_newlines(text);
+
+    let lines = text.trim_end_matches('\n').split('\n').count();
+    if lines > 1 || text.ends_with('\n') {
+        warnings.push(PasteWarning::MultipleLines(lines));
+    }
+    if text.chars().any(|c| c.is_control() && !matches!(c, '\n' | '\t')) {
+        warnings.push(PasteWarning::ControlCharacters);
+    }
+    if text.chars().any(is_hidden) {
+        warnings.push(PasteWarning::HiddenCharacters);
+    }
+
+    let lower = text.to_lowercase();
+    for (pattern, reason) in DANGEROUS_PATTERNS {
+        if lower.contains(pattern) && !warnings.contains(&PasteWarning::Dangerous(reason)) {
+            warnings.push(PasteWarning::Dangerous(reason));
+        }
+    }
+    if pipes_download_to_shell(&lower) {
+        warnings.push(PasteWarning::Dangerous("pipes downloaded content into an interpreter"));
+    }
+
+    warnings
+}
+
+/// Text that is safe to put in the input buffer: newlines kept, tabs turned
+/// into spaces, control and invisible characters removed
+pub fn sanitize(text: &str) -> String {
+    normalize_newlines(text)
+        .trim_end_matches('\n')
+        .chars()
+        .map(|c| if c == '\t' { ' ' } else { c })
+        .filter(|c| (!c.is_control() || *c == '\n') && !is_hidden(*c))
+        .collect()
+}
+
+/// Lines to show the user, with control and hidden characters made visible
+pub fn preview(text: &str) -> Vec<String> {
+    let text = normalize_newlines(text);
+    let lines: Vec<&str> = text.trim_end_matches('\n').split('\n').collect();
+
+    let mut preview: Vec<String> = lines
+        .iter()
+        .take(MAX_PREVIEW_LINES)
+        .map(|line| {
+            line.chars()
+                .map(|c| match c {
+                    '\t' => "    ".to_string(),
+                    '\x1b' => "^[".to_string(),
+                    c if c.is_control() && (c as u32) < 0x20 => format!("^{}", (c as u8 + b'@') as char),
+                    c if c.is_control() || is_hidden(c) => format!("<U+{:04X}>", c as u32),
+                    c => c.to_string(),
+                })
+                .collect::<String>()
+        })
+        .map(|line| format!("  │ {}", line))
+        .collect();
+
+    if lines.len() > MAX_PREVIEW_LINES {
+        preview.push(format!("  │ … and {} more lines", lines.len() - MAX_PREVIEW_LINES));
+    }
+    preview
+}
+
+fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Zero-width and bidirectional formatting characters
+fn is_hidden(c: char) -> bool {
+    matches!(c, '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2064}' | '\u{2066}'..='\u{2069}' | '\u{FEFF}')
+}
+
+/// `curl ... | sh`, `wget -O- ... | bash` and friends
+fn pipes_download_to_shell(lower: &str) -> bool {
+    lower.split('\n').any(|line| {
+        let mut stages = line.split('|').map(str::trim);
+        let Some(first) = stages.next() else {
+            return false;
+        };
+        let downloads = first.split_whitespace().any(|w| matches!(w, "curl" | "wget" | "fetch"));
+        downloads
+            && stages.any(|stage| {
+                let mut words = stage.split_whitespace();
+                let program = match words.next() {
+                    Some("sudo") => words.next(),
+                    program => program,
+                };
+                program.is_some_and(|p| SHELLS.contains(&p.rsplit('/').next().unwrap_or(p)))
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_paste_is_clean() {
+        assert!(review("ls -la ~/projects").is_empty());
+        assert!(review("git commit -m 'fix: thing'").is_empty());
+    }
+
+    #[test]
+    fn test_multiple_lines() {
+        assert_eq!(review("echo a\necho b"), vec![PasteWarning::MultipleLines(2)]);
+        // A trailing newline would have run the command in other shells
+        assert_eq!(review("ls\r\n"), vec![PasteWarning::MultipleLines(1)]);
+    }
+
+    #[test]
+    fn test_control_and_hidden_characters() {
+        assert!(review("ls\x1b[2J").contains(&PasteWarning::ControlCharacters));
+        assert!(review("ls \u{202E}txt.exe").contains(&PasteWarning::HiddenCharacters));
+    }
+
+    #[test]
+    fn test_dangerous_patterns() {
+        assert!(!review("curl -fsSL https://example.com/install.sh | sh").is_empty());
+        assert!(!review("wget -qO- example.com | sudo bash").is_empty());
+        assert!(!review("sudo rm -rf / --no-preserve-root").is_empty());
+        assert!(review("curl example.com | jq .").is_empty());
+    }
+
+    #[test]
+    fn test_sanitize_and_preview() {
+        assert_eq!(sanitize("echo\ta\r\nls\x1b\u{200B}\n"), "echo a\nls");
+        assert_eq!(preview("a\x1bb"), vec!["  │ a^[b"]);
+    }
+}
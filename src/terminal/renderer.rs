@@ -1,104 +1,666 @@
-/// Terminal rendering module
+//! Terminal rendering module
 
-use crate::config::PROMPT;
+use super::ansi::StyledLine;
+use super::emulator::EmulatorView;
+use super::input::CompletionMenu;
+use super::screen::{Screen, Style};
+use crate::config::CONTINUATION_PROMPT;
+use crate::shell::highlight::{HighlightSpan, HighlightStyle};
 use crossterm::{
-    cursor, execute, queue,
-    style::{Color, Print, ResetColor, SetForegroundColor},
+    cursor, execute,
+    style::Color,
     terminal::{self, ClearType},
 };
-use std::io::{self, Write};
+use std::io;
+use std::ops::Range;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Maximum number of screen rows the completion menu may take
+const MAX_MENU_ROWS: usize = 6;
+
+/// Tab stops in output are every this many columns
+const TAB_WIDTH: usize = 8;
+
+/// Everything the renderer needs to draw the input area
+pub struct InputView<'a> {
+    pub prompt: &'a str,
+    pub buffer: &'a str,
+    pub cursor_pos: usize,
+    /// Syntax highlighting for `buffer`, sorted by position
+    pub highlights: &'a [HighlightSpan],
+    /// Autosuggestion shown as ghost text after the cursor
+    pub suggestion: Option<&'a str>,
+    pub menu: Option<&'a CompletionMenu>,
+}
+
+/// How a single cell of the input area is drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CellStyle {
+    Plain,
+    Prompt,
+    Highlight(HighlightStyle),
+    Suggestion,
+}
+
+/// The input area broken into screen rows, with the cursor's row and column
+struct InputLayout {
+    rows: Vec<Vec<(char, CellStyle)>>,
+    cursor: (usize, usize),
+}
+
+/// A search of the output buffer and the match being shown
+struct OutputSearch {
+    query: String,
+    /// Line index and byte range of every match, oldest first
+    matches: Vec<(usize, usize, usize)>,
+    current: usize,
+}
 
 pub struct TerminalRenderer {
-    output_buffer: Vec<String>,
+    output_buffer: Vec<StyledLine>,
     last_printed_index: usize,
+    /// Number of lines scrolled back from the bottom of the output
+    scroll_offset: usize,
+    /// Number of output lines that fit on screen at the last render
+    visible_lines: usize,
+    search: Option<OutputSearch>,
+    /// The frame currently on the terminal, for differential redraws
+    previous_frame: Option<Screen>,
+    /// Screen of a program running on a PTY, shown below the output
+    live_output: Option<EmulatorView>,
 }
 
 impl TerminalRenderer {
     pub fn new(welcome_message: String) -> Self {
         TerminalRenderer {
-            output_buffer: vec![welcome_message],
+            output_buffer: vec![StyledLine::plain(welcome_message)],
             last_printed_index: 0,
+            scroll_offset: 0,
+            visible_lines: 0,
+            search: None,
+            previous_frame: None,
+            live_output: None,
         }
     }
 
-    /// Add a line to the output buffer
+    /// Add a line to the output buffer, splitting embedded newlines
     pub fn add_output(&mut self, line: String) {
-        self.output_buffer.push(line);
+        self.push_lines(plain_lines(vec![line]).into_iter());
     }
 
     /// Add multiple lines to the output buffer
     pub fn add_output_lines(&mut self, lines: Vec<String>) {
         for line in lines {
-            self.output_buffer.push(line);
+            self.add_output(line);
+        }
+    }
+
+    /// Add lines of command output, already parsed for colors
+    pub fn add_styled_lines(&mut self, lines: Vec<StyledLine>) {
+        self.push_lines(lines.into_iter());
+    }
+
+    fn push_lines(&mut self, lines: impl Iterator<Item = StyledLine>) {
+        let before = self.output_buffer.len();
+        self.output_buffer.extend(lines);
+
+        // Keep the view still while scrolled back
+        if self.scroll_offset > 0 {
+            self.scroll_offset += self.output_buffer.len() - before;
+        }
+    }
+
+    /// Show (or stop showing) the screen of a running program
+    pub fn set_live_output(&mut self, view: Option<EmulatorView>) {
+        self.live_output = view;
+    }
+
+    /// Number of output lines, including a running program's screen
+    fn output_len(&self) -> usize {
+        self.output_buffer.len() + self.live_output.as_ref().map_or(0, |live| live.lines.len())
+    }
+
+    fn output_line(&self, index: usize) -> &StyledLine {
+        match index.checked_sub(self.output_buffer.len()) {
+            Some(live_index) => &self.live_output.as_ref().expect("index past the output buffer").lines[live_index],
+            None => &self.output_buffer[index],
         }
     }
 
     /// Clear the output buffer
     pub fn clear_output(&mut self) {
         self.output_buffer.clear();
+        self.last_printed_index = 0;
+        self.scroll_offset = 0;
+        self.search = None;
     }
 
-    /// Render the terminal screen
-    pub fn render(&self, input_buffer: &str, cursor_pos: usize) -> io::Result<()> {
-        let mut stdout = io::stdout();
+    /// Number of output lines one page of scrolling moves
+    pub fn page_size(&self) -> usize {
+        self.visible_lines.max(1)
+    }
+
+    /// Scroll back towards older output
+    pub fn scroll_up(&mut self, lines: usize) {
+        let max_offset = self.output_len().saturating_sub(self.visible_lines);
+        self.scroll_offset = (self.scroll_offset + lines).min(max_offset);
+    }
+
+    /// Scroll forward towards the newest output
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll_offset = self.scroll_offset.saturating_sub(lines);
+    }
+
+    /// Return to the newest output
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll_offset = 0;
+    }
+
+    pub fn is_scrolled(&self) -> bool {
+        self.scroll_offset > 0
+    }
 
-        // Clear screen
-        execute!(stdout, terminal::Clear(ClearType::All))?;
-        execute!(stdout, cursor::MoveTo(0, 0))?;
+    /// Search the output buffer and show the most recent match
+    ///
+    /// The search is case-insensitive unless the query contains uppercase
+    /// letters. Returns the number of matches.
+    pub fn search(&mut self, query: &str) -> usize {
+        let case_sensitive = query.chars().any(char::is_uppercase);
+        let needle = if case_sensitive { query.to_string() } else { query.to_lowercase() };
 
+        let mut matches = Vec::new();
+        for (index, line) in self.output_buffer.iter().map(|l| &l.text).enumerate() {
+            // Lowercasing can change byte lengths; fall back to exact matching then
+            let haystack = if case_sensitive { line.clone() } else { line.to_lowercase() };
+            let haystack = if haystack.len() == line.len() { haystack } else { line.clone() };
+            matches.extend(
+                haystack
+                    .match_indices(&needle)
+                    .map(|(start, m)| (index, start, start + m.len())),
+            );
+        }
+
+        if needle.is_empty() || matches.is_empty() {
+            self.search = None;
+            return 0;
+        }
+
+        let count = matches.len();
+        self.search = Some(OutputSearch {
+            query: query.to_string(),
+            matches,
+            current: count - 1,
+        });
+        self.show_current_match();
+        count
+    }
+
+    /// Move to the next older (or newer) match
+    pub fn next_match(&mut self, older: bool) {
+        if let Some(search) = &mut self.search {
+            let count = search.matches.len();
+            search.current = if older {
+                (search.current + count - 1) % count
+            } else {
+                (search.current + 1) % count
+            };
+        }
+        self.show_current_match();
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    /// Stop highlighting search matches
+    pub fn end_search(&mut self) {
+        self.search = None;
+    }
+
+    /// Scroll so the current match sits in the middle of the output area
+    fn show_current_match(&mut self) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let line = search.matches[search.current].0;
+        let below = self.output_buffer.len() - line - 1;
+        self.scroll_offset = 0;
+        self.scroll_up(below.saturating_sub(self.visible_lines / 2));
+    }
+
+    /// Render the terminal screen
+    ///
+    /// The frame is drawn off-screen and only the cells that changed since
+    /// the previous frame are written to the terminal.
+    pub fn render(&mut self, input: &InputView) -> io::Result<()> {
         // Get terminal size
-        let (_, height) = terminal::size()?;
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let mut screen = Screen::new(width, height);
+
+        // The completion menu sits below the input area and pushes it up
+        let menu_rows = input.menu.map_or(Vec::new(), |m| Self::layout_menu(m, width));
+        let menu_height = menu_rows.len();
 
-        // Calculate how many output lines to show
-        let available_lines = (height as usize).saturating_sub(3);
-        let start_idx = self.output_buffer.len().saturating_sub(available_lines);
+        // Multi-line and wrapped input grows upward, keeping the cursor visible
+        let layout = Self::layout_input(input, width);
+        let max_input_rows = height.saturating_sub(2 + menu_height).max(1);
+        let input_height = layout.rows.len().min(max_input_rows);
+        let first_row = (layout.cursor.0 + 1).saturating_sub(input_height);
+
+        // Fill the output area from the bottom with wrapped rows, clipping
+        // the top line if it doesn't fit completely
+        let available_rows = height.saturating_sub(2 + input_height + menu_height);
+        let end_idx = self.output_len() - self.scroll_offset.min(self.output_len());
+        let mut output_rows: Vec<(usize, Range<usize>)> = Vec::new();
+        let mut start_idx = end_idx;
+        while start_idx > 0 && output_rows.len() < available_rows {
+            start_idx -= 1;
+            let wrapped = wrap_line(&self.output_line(start_idx).text, width);
+            output_rows.extend(wrapped.into_iter().rev().map(|range| (start_idx, range)));
+        }
+        output_rows.truncate(available_rows);
+        output_rows.reverse();
+        self.visible_lines = end_idx - start_idx;
+
+        // A running program's cursor, if its line is on screen
+        let live_cursor = self.live_output.as_ref().and_then(|live| {
+            let index = self.output_buffer.len() + live.cursor.0;
+            let row = output_rows.iter().position(|(i, _)| *i == index)?;
+            Some((row, live.cursor.1))
+        });
 
         // Display output buffer
-        for (i, line) in self.output_buffer.iter().skip(start_idx).enumerate() {
-            queue!(stdout, cursor::MoveTo(0, i as u16))?;
-            queue!(stdout, Print(line))?;
-        }
-
-        // Display separator
-        let separator_line = height.saturating_sub(2);
-        queue!(stdout, cursor::MoveTo(0, separator_line))?;
-        queue!(stdout, SetForegroundColor(Color::DarkGrey))?;
-        queue!(
-            stdout,
-            Print("─".repeat(terminal::size()?.0 as usize))
-        )?;
-        queue!(stdout, ResetColor)?;
-
-        // Display input line
-        let input_line = height.saturating_sub(1);
-        queue!(stdout, cursor::MoveTo(0, input_line))?;
-        queue!(stdout, SetForegroundColor(Color::Green))?;
-        queue!(stdout, Print(PROMPT))?;
-        queue!(stdout, ResetColor)?;
-        queue!(stdout, Print(input_buffer))?;
+        for (row, (index, range)) in output_rows.into_iter().enumerate() {
+            self.draw_output_row(&mut screen, row, index, range);
+        }
+
+        // Display separator, with the scroll position when scrolled back
+        let input_top = height.saturating_sub(input_height + menu_height);
+        let separator_row = input_top.saturating_sub(1);
+        let indicator = self.scroll_indicator();
+        let separator = Style::fg(Color::DarkGrey);
+        if indicator.is_empty() {
+            screen.put_str(separator_row, 0, &"─".repeat(width), separator);
+        } else {
+            let fill = width.saturating_sub(indicator.width() + 2);
+            let col = screen.put_str(separator_row, 0, &"─".repeat(fill), separator);
+            let col = screen.put_str(separator_row, col, &format!(" {}", indicator), Style::fg(Color::Yellow));
+            screen.put_str(separator_row, col, "─", separator);
+        }
+
+        // Display input area
+        for (i, row) in layout.rows.iter().skip(first_row).take(input_height).enumerate() {
+            Self::draw_cells(&mut screen, input_top + i, row);
+        }
+
+        // Display completion menu
+        let menu_top = input_top + input_height;
+        for (row, items) in menu_rows.iter().enumerate() {
+            let mut col = 0;
+            for (text, selected) in items {
+                let style = if *selected {
+                    Style { reverse: true, ..Style::default() }
+                } else {
+                    Style::fg(Color::Cyan)
+                };
+                col = screen.put_str(menu_top + row, col, text, style);
+            }
+        }
 
         // Position cursor
-        let prompt_len = PROMPT.len();
-        queue!(
-            stdout,
-            cursor::MoveTo((prompt_len + cursor_pos) as u16, input_line)
-        )?;
+        let (cursor_row, cursor_col) = layout.cursor;
+        match live_cursor {
+            Some((row, col)) => screen.set_cursor(row, col),
+            None => screen.set_cursor(input_top + cursor_row - first_row, cursor_col),
+        }
 
-        stdout.flush()?;
+        screen.draw(self.previous_frame.as_ref(), &mut io::stdout())?;
+        self.previous_frame = Some(screen);
         Ok(())
     }
 
-    /// Clear the screen completely
-    pub fn clear_screen() -> io::Result<()> {
+    /// Text shown in the separator line while scrolled back or searching
+    fn scroll_indicator(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(search) = &self.search {
+            parts.push(format!(
+                "/{}  {}/{}  n/N older/newer, Esc to close",
+                search.query,
+                search.current + 1,
+                search.matches.len()
+            ));
+        }
+        if self.scroll_offset > 0 {
+            parts.push(format!("↓ {} more lines", self.scroll_offset));
+        }
+        if parts.is_empty() {
+            return String::new();
+        }
+        format!("{} ", parts.join("  ·  "))
+    }
+
+    /// Draw one screen row of an output line, highlighting search matches
+    fn draw_output_row(&self, screen: &mut Screen, row: usize, index: usize, range: Range<usize>) {
+        let line = self.output_line(index);
+        if line.spans.is_empty() && self.search.is_none() {
+            screen.put_str(row, 0, &line.text[range], Style::default());
+            return;
+        }
+
+        let matches: Vec<(usize, usize, bool)> = self.search.as_ref().map_or(Vec::new(), |search| {
+            search
+                .matches
+                .iter()
+                .enumerate()
+                .filter(|(_, (line_idx, start, end))| *line_idx == index && *start < range.end && *end > range.start)
+                .map(|(i, (_, start, end))| (*start, *end, i == search.current))
+                .collect()
+        });
+
+        let mut col = 0;
+        let mut buf = [0; 4];
+        for (pos, c) in line.text[range.clone()].char_indices() {
+            let pos = range.start + pos;
+            let style = match matches.iter().find(|(start, end, _)| *start <= pos && pos < *end) {
+                Some((_, _, true)) => Style { fg: Some(Color::Black), bg: Some(Color::Yellow), ..Style::default() },
+                Some((_, _, false)) => Style { reverse: true, ..Style::default() },
+                None => line.style_at(pos),
+            };
+            col = screen.put_str(row, col, c.encode_utf8(&mut buf), style);
+        }
+    }
+
+    /// Lay out the prompt, buffer and suggestion as rows of styled cells
+    ///
+    /// Each line of the buffer starts a new row after the prompt (or the
+    /// continuation prompt), and rows wrap at the terminal width. Wide
+    /// characters that don't fit at the end of a row move to the next one.
+    fn layout_input(input: &InputView, width: usize) -> InputLayout {
+        let width = width.max(1);
+        let mut rows: Vec<Vec<(char, CellStyle)>> = vec![Vec::new()];
+        let mut cursor = None;
+
+        let push = |rows: &mut Vec<Vec<(char, CellStyle)>>, c: char, style: CellStyle| {
+            if rows.last().is_some_and(|row| row_width(row) + char_width(c) > width) {
+                rows.push(Vec::new());
+            }
+            if let Some(row) = rows.last_mut() {
+                row.push((c, style));
+            }
+        };
+
+        for c in input.prompt.chars() {
+            push(&mut rows, c, CellStyle::Prompt);
+        }
+
+        let mut spans = input.highlights.iter().peekable();
+        for (pos, c) in input.buffer.char_indices() {
+            if pos == input.cursor_pos {
+                cursor = Some(Self::next_cell(&rows, width));
+            }
+            if c == '\n' {
+                rows.push(Vec::new());
+                for p in CONTINUATION_PROMPT.chars() {
+                    push(&mut rows, p, CellStyle::Prompt);
+                }
+                continue;
+            }
+
+            while spans.peek().is_some_and(|span| span.end <= pos) {
+                spans.next();
+            }
+            let style = match spans.peek() {
+                Some(span) if span.start <= pos => CellStyle::Highlight(span.style),
+                _ => CellStyle::Plain,
+            };
+            push(&mut rows, c, style);
+        }
+
+        let cursor = cursor.unwrap_or_else(|| Self::next_cell(&rows, width));
+        if cursor.0 == rows.len() {
+            rows.push(Vec::new());
+        }
+
+        for c in input.suggestion.unwrap_or_default().chars() {
+            if c == '\n' {
+                break;
+            }
+            push(&mut rows, c, CellStyle::Suggestion);
+        }
+
+        InputLayout { rows, cursor }
+    }
+
+    /// Row and column where the next cell will be placed
+    fn next_cell(rows: &[Vec<(char, CellStyle)>], width: usize) -> (usize, usize) {
+        let last = rows.len() - 1;
+        match row_width(&rows[last]) {
+            used if used >= width => (last + 1, 0),
+            used => (last, used),
+        }
+    }
+
+    /// Draw a row of input cells
+    fn draw_cells(screen: &mut Screen, row: usize, cells: &[(char, CellStyle)]) {
+        let mut col = 0;
+        for (c, cell_style) in cells {
+            let style = match cell_style {
+                CellStyle::Plain => Style::default(),
+                CellStyle::Prompt => Style::fg(Color::Green),
+                CellStyle::Suggestion => Style::fg(Color::DarkGrey),
+                CellStyle::Highlight(highlight) => Style {
+                    fg: Some(Self::highlight_color(*highlight)),
+                    underline: *highlight == HighlightStyle::DeniedPath,
+                    ..Style::default()
+                },
+            };
+            let mut buf = [0; 4];
+            col = screen.put_str(row, col, c.encode_utf8(&mut buf), style);
+        }
+    }
+
+    fn highlight_color(style: HighlightStyle) -> Color {
+        match style {
+            HighlightStyle::Builtin => Color::Cyan,
+            HighlightStyle::Command => Color::Blue,
+            HighlightStyle::UnknownCommand => Color::Red,
+            HighlightStyle::Quoted => Color::Yellow,
+            HighlightStyle::Variable => Color::Magenta,
+            HighlightStyle::Operator | HighlightStyle::Redirection => Color::DarkCyan,
+            HighlightStyle::DeniedPath => Color::DarkYellow,
+        }
+    }
+
+    /// Arrange menu candidates in columns, returning padded cells per row
+    fn layout_menu(menu: &CompletionMenu, width: usize) -> Vec<Vec<(String, bool)>> {
+        let column_width = menu
+            .candidates
+            .iter()
+            .map(|c| c.display.width())
+            .max()
+            .unwrap_or(0)
+            + 2;
+        let columns = (width / column_width).max(1);
+        let total_rows = menu.candidates.len().div_ceil(columns);
+
+        let mut rows: Vec<Vec<(String, bool)>> = menu
+            .candidates
+            .chunks(columns)
+            .enumerate()
+            .take(MAX_MENU_ROWS)
+            .map(|(row, chunk)| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .map(|(col, candidate)| {
+                        let index = row * columns + col;
+                        let padding = column_width - candidate.display.width();
+                        let text = format!("{}{}", candidate.display, " ".repeat(padding));
+                        (text, menu.selected == Some(index))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // Replace the last visible row with a count of what didn't fit
+        if total_rows > MAX_MENU_ROWS {
+            let shown = (MAX_MENU_ROWS - 1) * columns;
+            rows[MAX_MENU_ROWS - 1] = vec![(
+                format!("… and {} more", menu.candidates.len() - shown),
+                false,
+            )];
+        }
+
+        rows
+    }
+
+    /// Clear the screen completely, e.g. before an interactive command
+    ///
+    /// The next render repaints everything, since the terminal no longer
+    /// shows the previous frame.
+    pub fn clear_screen(&mut self) -> io::Result<()> {
         execute!(io::stdout(), terminal::Clear(ClearType::All))?;
         execute!(io::stdout(), cursor::MoveTo(0, 0))?;
+        self.previous_frame = None;
         Ok(())
     }
 
     /// Get new output lines that haven't been printed yet (for non-interactive mode)
     pub fn get_new_output(&mut self) -> Vec<String> {
-        let new_lines = self.output_buffer[self.last_printed_index..].to_vec();
+        let new_lines = self.output_buffer[self.last_printed_index..]
+            .iter()
+            .map(|line| line.text.clone())
+            .collect();
         self.last_printed_index = self.output_buffer.len();
         new_lines
     }
 }
+
+/// Display width of a character in terminal columns
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+fn row_width(row: &[(char, CellStyle)]) -> usize {
+    row.iter().map(|(c, _)| char_width(*c)).sum()
+}
+
+/// Split a line into byte ranges that each fit in `width` columns
+fn wrap_line(line: &str, width: usize) -> Vec<Range<usize>> {
+    let width = width.max(1);
+    let mut rows = Vec::new();
+    let mut start = 0;
+    let mut column = 0;
+
+    for (pos, c) in line.char_indices() {
+        let w = char_width(c);
+        if column + w > width && pos > start {
+            rows.push(start..pos);
+            start = pos;
+            column = 0;
+        }
+        column += w;
+    }
+    rows.push(start..line.len());
+    rows
+}
+
+/// Turn lines of builtin output into plain lines, splitting embedded
+/// newlines and expanding tabs
+pub fn plain_lines(lines: Vec<String>) -> Vec<StyledLine> {
+    let mut plain = Vec::with_capacity(lines.len());
+    for line in lines {
+        if line.contains('\n') || line.contains('\t') {
+            plain.extend(line.split('\n').map(|l| StyledLine::plain(expand_tabs(l))));
+        } else {
+            plain.push(StyledLine::plain(line));
+        }
+    }
+    plain
+}
+
+/// Replace tabs with spaces up to the next tab stop
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    let mut column = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = TAB_WIDTH - column % TAB_WIDTH;
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            expanded.push(c);
+            column += char_width(c);
+        }
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renderer_with_lines(count: usize) -> TerminalRenderer {
+        let mut renderer = TerminalRenderer::new("welcome".to_string());
+        renderer.add_output_lines((1..=count).map(|n| format!("line {}", n)).collect());
+        renderer.visible_lines = 10;
+        renderer
+    }
+
+    #[test]
+    fn test_scroll_is_clamped() {
+        let mut renderer = renderer_with_lines(30);
+        renderer.scroll_up(100);
+        assert_eq!(renderer.scroll_offset, 21);
+        renderer.scroll_down(5);
+        assert_eq!(renderer.scroll_offset, 16);
+        // New output doesn't move the view while scrolled back
+        renderer.add_output("new".to_string());
+        assert_eq!(renderer.scroll_offset, 17);
+    }
+
+    #[test]
+    fn test_search_jumps_to_matches() {
+        let mut renderer = renderer_with_lines(30);
+        assert_eq!(renderer.search("LINE 2"), 0);
+        assert_eq!(renderer.search("line 2"), 11);
+        assert_eq!(renderer.search("line 3"), 2);
+        // Starts at the newest match, "line 30"
+        assert_eq!(renderer.scroll_offset, 0);
+        renderer.next_match(true);
+        // "line 3" is near the top, so the view stops at the oldest page
+        assert_eq!(renderer.scroll_offset, 21);
+        assert_eq!(renderer.search("missing"), 0);
+        assert!(!renderer.is_searching());
+    }
+
+    #[test]
+    fn test_wrap_line_by_display_width() {
+        assert_eq!(wrap_line("", 4), vec![0..0]);
+        assert_eq!(wrap_line("abcdefghij", 4), vec![0..4, 4..8, 8..10]);
+        // Each CJK character is two columns wide and never split
+        assert_eq!(wrap_line("日本語", 5), vec![0..6, 6..9]);
+    }
+
+    #[test]
+    fn test_expand_tabs() {
+        assert_eq!(expand_tabs("a\tb"), "a       b");
+        assert_eq!(expand_tabs("abcdefgh\tx"), "abcdefgh        x");
+    }
+
+    #[test]
+    fn test_input_cursor_after_wide_characters() {
+        let view = InputView {
+            prompt: "> ",
+            buffer: "日本",
+            cursor_pos: "日本".len(),
+            highlights: &[],
+            suggestion: None,
+            menu: None,
+        };
+        assert_eq!(TerminalRenderer::layout_input(&view, 80).cursor, (0, 6));
+        // Wrapped onto the next row when the second character doesn't fit
+        assert_eq!(TerminalRenderer::layout_input(&view, 5).cursor, (1, 2));
+    }
+}
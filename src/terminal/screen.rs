@@ -0,0 +1,220 @@
+//! Double-buffered screen model
+//!
+//! The renderer draws each frame into a `Screen` grid of styled cells. The
+//! frame is compared with the previous one and only the cells that changed
+//! are written to the terminal.
+
+use crossterm::{
+    cursor, queue,
+    style::{Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor},
+    terminal::{self, ClearType},
+};
+use std::io::{self, Write};
+use unicode_width::UnicodeWidthChar;
+
+/// Colors and attributes of a cell
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    pub fn fg(color: Color) -> Self {
+        Style { fg: Some(color), ..Style::default() }
+    }
+
+    /// Queue the commands that switch the terminal to this style
+    fn queue(&self, out: &mut impl Write) -> io::Result<()> {
+        queue!(out, SetAttribute(Attribute::Reset), ResetColor)?;
+        if let Some(fg) = self.fg {
+            queue!(out, SetForegroundColor(fg))?;
+        }
+        if let Some(bg) = self.bg {
+            queue!(out, SetBackgroundColor(bg))?;
+        }
+        if self.bold {
+            queue!(out, SetAttribute(Attribute::Bold))?;
+        }
+        if self.dim {
+            queue!(out, SetAttribute(Attribute::Dim))?;
+        }
+        if self.italic {
+            queue!(out, SetAttribute(Attribute::Italic))?;
+        }
+        if self.underline {
+            queue!(out, SetAttribute(Attribute::Underlined))?;
+        }
+        if self.reverse {
+            queue!(out, SetAttribute(Attribute::Reverse))?;
+        }
+        Ok(())
+    }
+}
+
+/// One terminal cell
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    /// The character plus any combining characters; empty for the second
+    /// column of a wide character
+    symbol: String,
+    style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell { symbol: " ".to_string(), style: Style::default() }
+    }
+}
+
+/// A frame: a grid of cells and where the cursor goes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Screen {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    cursor: (usize, usize),
+}
+
+impl Screen {
+    pub fn new(width: usize, height: usize) -> Self {
+        Screen {
+            width,
+            height,
+            cells: vec![Cell::default(); width * height],
+            cursor: (0, 0),
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Write text starting at a row and column, clipped at the right edge
+    ///
+    /// Returns the column after the text.
+    pub fn put_str(&mut self, row: usize, mut col: usize, text: &str, style: Style) -> usize {
+        if row >= self.height {
+            return col;
+        }
+        for c in text.chars() {
+            let w = c.width().unwrap_or(0);
+            if w == 0 {
+                // Combining characters join the previous cell
+                if col > 0 {
+                    self.cells[row * self.width + col - 1].symbol.push(c);
+                }
+                continue;
+            }
+            if col + w > self.width {
+                break;
+            }
+            let index = row * self.width + col;
+            self.cells[index] = Cell { symbol: c.to_string(), style };
+            if w == 2 {
+                self.cells[index + 1] = Cell { symbol: String::new(), style };
+            }
+            col += w;
+        }
+        col
+    }
+
+    pub fn set_cursor(&mut self, row: usize, col: usize) {
+        self.cursor = (row.min(self.height.saturating_sub(1)), col.min(self.width.saturating_sub(1)));
+    }
+
+    /// Draw this frame, writing only the cells that differ from `previous`
+    ///
+    /// Without a previous frame of the same size the whole screen is redrawn.
+    pub fn draw(&self, previous: Option<&Screen>, out: &mut impl Write) -> io::Result<()> {
+        let previous = previous.filter(|p| p.size() == self.size());
+        queue!(out, cursor::Hide)?;
+        if previous.is_none() {
+            queue!(out, SetAttribute(Attribute::Reset), ResetColor, terminal::Clear(ClearType::All))?;
+        }
+
+        let blank = Cell::default();
+        let mut position: Option<(usize, usize)> = None;
+        let mut current_style = Style::default();
+
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let cell = &self.cells[row * self.width + col];
+                let old = previous.map_or(&blank, |p| &p.cells[row * self.width + col]);
+                if cell == old || cell.symbol.is_empty() {
+                    continue;
+                }
+
+                if position != Some((row, col)) {
+                    queue!(out, cursor::MoveTo(col as u16, row as u16))?;
+                }
+                if cell.style != current_style {
+                    cell.style.queue(out)?;
+                    current_style = cell.style;
+                }
+                queue!(out, Print(&cell.symbol))?;
+
+                let w = cell.symbol.chars().next().and_then(|c| c.width()).unwrap_or(1);
+                position = Some((row, col + w));
+            }
+        }
+
+        if current_style != Style::default() {
+            queue!(out, SetAttribute(Attribute::Reset), ResetColor)?;
+        }
+        let (row, col) = self.cursor;
+        queue!(out, cursor::MoveTo(col as u16, row as u16), cursor::Show)?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drawn(screen: &Screen, previous: Option<&Screen>) -> String {
+        let mut out = Vec::new();
+        screen.draw(previous, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_put_str_clips_and_handles_wide_characters() {
+        let mut screen = Screen::new(5, 2);
+        assert_eq!(screen.put_str(0, 0, "abcdefg", Style::default()), 5);
+        // The wide character doesn't fit in the last column
+        assert_eq!(screen.put_str(1, 2, "日本", Style::default()), 4);
+        assert_eq!(screen.cells[5 + 3].symbol, "");
+    }
+
+    #[test]
+    fn test_only_changed_cells_are_drawn() {
+        let mut first = Screen::new(10, 2);
+        first.put_str(0, 0, "hello", Style::default());
+        first.put_str(1, 0, "world", Style::default());
+
+        let mut second = first.clone();
+        second.put_str(1, 0, "W", Style::default());
+
+        let output = drawn(&second, Some(&first));
+        assert!(output.contains('W'));
+        assert!(!output.contains("hello"));
+        assert!(!output.contains("orld"));
+    }
+
+    #[test]
+    fn test_full_redraw_without_previous_frame() {
+        let mut screen = Screen::new(10, 1);
+        screen.put_str(0, 0, "hi", Style::fg(Color::Green));
+        assert!(drawn(&screen, None).contains("hi"));
+
+        // A resized previous frame can't be diffed against
+        let old = Screen::new(8, 1);
+        assert!(drawn(&screen, Some(&old)).contains("hi"));
+    }
+}